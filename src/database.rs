@@ -9,7 +9,15 @@
 use crate::env;
 use crate::schema::{DatabaseConfig, DbDriver, DbIsolation};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default maximum query duration in milliseconds.
+pub const DEFAULT_QUERY_TIMEOUT_MS: u64 = 5000;
+
+/// Default delay between connection attempts in milliseconds.
+pub const DEFAULT_CONNECT_RETRY_DELAY_MS: u64 = 1000;
 
 /// Error type for database operations.
 #[derive(Debug)]
@@ -48,21 +56,69 @@ pub enum Connection {
 /// PostgreSQL connection wrapper.
 pub struct PostgresConnection {
     client: tokio_postgres::Client,
+    /// Maximum time a single query may run.
+    query_timeout: Duration,
     /// Handle to the connection task (kept alive for the connection duration).
     _handle: std::thread::JoinHandle<()>,
+    /// Runtime every query is driven on. Declared last so it is dropped after
+    /// the client.
+    runtime: tokio::runtime::Runtime,
 }
 
 /// SQLite connection wrapper.
 pub struct SqliteConnection {
     conn: rusqlite::Connection,
+    /// Maximum time a single query may run.
+    query_timeout: Duration,
+}
+
+/// Error returned when a query exceeds its timeout.
+fn query_timeout_error(timeout: Duration) -> DbError {
+    DbError {
+        message: format!("query timed out after {}ms", timeout.as_millis()),
+        database: None,
+        masked_url: None,
+    }
+}
+
+/// Rows returned by a query, along with the result column names.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Rows inserted, updated, or deleted by a statement without result columns.
+    pub affected: Option<u64>,
+}
+
+impl QueryResult {
+    /// Format as text: tab-separated columns, newline-separated rows. A statement
+    /// without result columns formats as its affected row count.
+    pub fn to_text(&self) -> String {
+        if self.columns.is_empty()
+            && let Some(affected) = self.affected
+        {
+            return affected.to_string();
+        }
+        self.rows
+            .iter()
+            .map(|row| row.join("\t"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 impl Connection {
     /// Execute a SQL statement and return the result as text.
     ///
     /// For queries that return rows, results are formatted as newline-separated values.
-    /// For statements that don't return rows, returns an empty string.
+    /// For statements without result columns (INSERT, UPDATE, DDL, ...), returns
+    /// the number of affected rows.
     pub fn execute(&mut self, sql: &str) -> Result<String, DbError> {
+        self.execute_with_columns(sql).map(|r| r.to_text())
+    }
+
+    /// Execute a SQL statement and return the rows with their column names.
+    pub fn execute_with_columns(&mut self, sql: &str) -> Result<QueryResult, DbError> {
         match self {
             Connection::Postgres(pg) => pg.execute(sql),
             Connection::Sqlite(sqlite) => sqlite.execute(sql),
@@ -72,27 +128,43 @@ impl Connection {
 
 impl PostgresConnection {
     /// Execute a SQL statement.
-    fn execute(&mut self, sql: &str) -> Result<String, DbError> {
-        use std::fmt::Write;
-
-        // Use a simple runtime for blocking execution
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .map_err(|e| DbError {
-                message: format!("Failed to create runtime: {e}"),
-                database: None,
-                masked_url: None,
-            })?;
-
-        rt.block_on(async {
-            let rows = self.client.query(sql, &[]).await.map_err(|e| DbError {
+    fn execute(&mut self, sql: &str) -> Result<QueryResult, DbError> {
+        let query_timeout = self.query_timeout;
+        self.runtime.block_on(async {
+            let query_failed = |e: tokio_postgres::Error| DbError {
                 message: format!("Query failed: {e}"),
                 database: None,
                 masked_url: None,
-            })?;
+            };
+            let stmt = self.client.prepare(sql).await.map_err(query_failed)?;
+
+            // Statements without result columns report their affected row count
+            if stmt.columns().is_empty() {
+                return match tokio::time::timeout(query_timeout, self.client.execute(&stmt, &[]))
+                    .await
+                {
+                    Ok(affected) => Ok(QueryResult {
+                        affected: Some(affected.map_err(query_failed)?),
+                        ..QueryResult::default()
+                    }),
+                    Err(_) => Err(self.cancel_timed_out(query_timeout).await),
+                };
+            }
 
-            let mut result = String::new();
+            let rows =
+                match tokio::time::timeout(query_timeout, self.client.query(&stmt, &[])).await {
+                    Ok(rows) => rows.map_err(query_failed)?,
+                    Err(_) => return Err(self.cancel_timed_out(query_timeout).await),
+                };
+
+            let mut result = QueryResult {
+                columns: stmt
+                    .columns()
+                    .iter()
+                    .map(|c| c.name().to_string())
+                    .collect(),
+                ..QueryResult::default()
+            };
             for row in rows {
                 let mut row_values = Vec::new();
                 for i in 0..row.len() {
@@ -116,21 +188,51 @@ impl PostgresConnection {
                     };
                     row_values.push(value);
                 }
-                if !result.is_empty() {
-                    result.push('\n');
-                }
-                let _ = write!(result, "{}", row_values.join("\t"));
+                result.rows.push(row_values);
             }
             Ok(result)
         })
     }
+
+    /// Best effort: stop a timed-out query server-side so the connection is
+    /// usable again.
+    async fn cancel_timed_out(&self, query_timeout: Duration) -> DbError {
+        let _ = self
+            .client
+            .cancel_token()
+            .cancel_query(tokio_postgres::NoTls)
+            .await;
+        query_timeout_error(query_timeout)
+    }
 }
 
 impl SqliteConnection {
-    /// Execute a SQL statement.
-    fn execute(&mut self, sql: &str) -> Result<String, DbError> {
-        use std::fmt::Write;
+    /// Execute a SQL statement, interrupting it if it exceeds the query timeout.
+    fn execute(&mut self, sql: &str) -> Result<QueryResult, DbError> {
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let deadline = Instant::now() + self.query_timeout;
+        let flag = Arc::clone(&timed_out);
+        self.conn.progress_handler(
+            1000,
+            Some(move || {
+                let expired = Instant::now() >= deadline;
+                if expired {
+                    flag.store(true, Ordering::Relaxed);
+                }
+                expired
+            }),
+        );
 
+        let result = self.execute_inner(sql);
+        self.conn.progress_handler(0, None::<fn() -> bool>);
+
+        if timed_out.load(Ordering::Relaxed) {
+            return Err(query_timeout_error(self.query_timeout));
+        }
+        result
+    }
+
+    fn execute_inner(&mut self, sql: &str) -> Result<QueryResult, DbError> {
         // Try as a query first (SELECT, etc.)
         let mut stmt = match self.conn.prepare(sql) {
             Ok(s) => s,
@@ -147,14 +249,30 @@ impl SqliteConnection {
         if column_count == 0 {
             // This is likely a non-SELECT statement
             drop(stmt);
+            let total_before = self.conn.total_changes();
             self.conn.execute(sql, []).map_err(|e| DbError {
                 message: format!("Execute failed: {e}"),
                 database: None,
                 masked_url: None,
             })?;
-            return Ok(String::new());
+            // `changes()` keeps the previous count after DDL, so only trust it
+            // when this statement changed something
+            let affected = if self.conn.total_changes() == total_before {
+                0
+            } else {
+                self.conn.changes()
+            };
+            return Ok(QueryResult {
+                affected: Some(affected),
+                ..QueryResult::default()
+            });
         }
 
+        let columns = stmt
+            .column_names()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
         let rows = stmt.query_map([], |row| {
             let mut values = Vec::new();
             for i in 0..column_count {
@@ -177,17 +295,17 @@ impl SqliteConnection {
             masked_url: None,
         })?;
 
-        let mut result = String::new();
+        let mut result = QueryResult {
+            columns,
+            ..QueryResult::default()
+        };
         for row in rows {
             let values = row.map_err(|e| DbError {
                 message: format!("Failed to read row: {e}"),
                 database: None,
                 masked_url: None,
             })?;
-            if !result.is_empty() {
-                result.push('\n');
-            }
-            let _ = write!(result, "{}", values.join("\t"));
+            result.rows.push(values);
         }
         Ok(result)
     }
@@ -225,6 +343,96 @@ pub fn interpolate_env(s: &str) -> Result<String, DbError> {
     })
 }
 
+/// Split a SQL script into individual statements on `;`.
+///
+/// Semicolons inside string literals, quoted identifiers, Postgres dollar-quoted
+/// bodies, and `--` or `/* */` comments don't end a statement, nor do those in
+/// the `BEGIN ... END` body of a `CREATE` statement such as a trigger.
+/// Statements holding nothing but whitespace and comments are dropped.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let bytes = sql.as_bytes();
+    let find = |from: usize, pattern: &[u8]| {
+        bytes[from.min(bytes.len())..]
+            .windows(pattern.len())
+            .position(|w| w == pattern)
+            .map(|pos| from + pos)
+    };
+
+    let mut statements = Vec::new();
+    let mut start = 0;
+    // Whether the current statement has anything besides whitespace and comments
+    let mut has_code = false;
+    // Whether it starts with CREATE, so BEGIN ... END may enclose semicolons
+    let mut creates = false;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes[i..].starts_with(b"--") => {
+                i = find(i, b"\n").unwrap_or(bytes.len());
+            }
+            b'/' if bytes[i..].starts_with(b"/*") => {
+                i = find(i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+            }
+            // A doubled quote inside a literal closes and reopens it, which
+            // splits the same way as treating it as an escape
+            quote @ (b'\'' | b'"' | b'`') => {
+                has_code = true;
+                i = find(i + 1, &[quote]).map_or(bytes.len(), |end| end + 1);
+            }
+            b'$' => {
+                has_code = true;
+                let tag_len = bytes[i + 1..]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'));
+                match tag_len {
+                    Some(len) if bytes[i + 1 + len] == b'$' && !bytes[i + 1].is_ascii_digit() => {
+                        let tag = &bytes[i..i + len + 2];
+                        i = find(i + tag.len(), tag).map_or(bytes.len(), |end| end + tag.len());
+                    }
+                    _ => i += 1,
+                }
+            }
+            b';' if depth == 0 => {
+                if has_code {
+                    statements.push(sql[start..i].trim().to_string());
+                }
+                i += 1;
+                start = i;
+                has_code = false;
+                creates = false;
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let len = bytes[i..]
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                    .unwrap_or(bytes.len() - i);
+                let word = &sql[i..i + len];
+                if !has_code {
+                    creates = word.eq_ignore_ascii_case("CREATE");
+                }
+                if creates {
+                    if word.eq_ignore_ascii_case("BEGIN") || word.eq_ignore_ascii_case("CASE") {
+                        depth += 1;
+                    } else if word.eq_ignore_ascii_case("END") {
+                        depth = depth.saturating_sub(1);
+                    }
+                }
+                has_code = true;
+                i += len;
+            }
+            b => {
+                has_code |= !b.is_ascii_whitespace();
+                i += 1;
+            }
+        }
+    }
+    if has_code {
+        statements.push(sql[start..].trim().to_string());
+    }
+    statements
+}
+
 /// Connect to a database using the provided configuration.
 fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
     // Interpolate environment variables in URL
@@ -233,7 +441,28 @@ fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
         e
     })?;
 
-    let masked = mask_password(&url);
+    let retry_delay = Duration::from_millis(
+        config
+            .connect_retry_delay_ms
+            .unwrap_or(DEFAULT_CONNECT_RETRY_DELAY_MS),
+    );
+    let mut attempts_left = config.connect_retries;
+    loop {
+        match open(config, &url, name) {
+            Err(_) if attempts_left > 0 => {
+                attempts_left -= 1;
+                std::thread::sleep(retry_delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Make one attempt at opening a connection to an already-interpolated URL.
+fn open(config: &DatabaseConfig, url: &str, name: &str) -> Result<Connection, DbError> {
+    let masked = mask_password(url);
+    let query_timeout =
+        Duration::from_millis(config.query_timeout_ms.unwrap_or(DEFAULT_QUERY_TIMEOUT_MS));
 
     match config.driver {
         DbDriver::Postgres => {
@@ -247,7 +476,7 @@ fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
                 })?;
 
             let (client, connection) = rt
-                .block_on(tokio_postgres::connect(&url, tokio_postgres::NoTls))
+                .block_on(tokio_postgres::connect(url, tokio_postgres::NoTls))
                 .map_err(|e| DbError {
                     message: format!("Connection failed: {e}"),
                     database: Some(name.to_string()),
@@ -269,7 +498,9 @@ fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
 
             Ok(Connection::Postgres(PostgresConnection {
                 client,
+                query_timeout,
                 _handle: handle,
+                runtime: rt,
             }))
         }
         DbDriver::Sqlite => {
@@ -281,7 +512,7 @@ fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
             } else if let Some(path) = url.strip_prefix("sqlite://") {
                 path.to_string()
             } else {
-                url.clone()
+                url.to_string()
             };
 
             let conn = if path == ":memory:" {
@@ -295,11 +526,17 @@ fn connect(config: &DatabaseConfig, name: &str) -> Result<Connection, DbError> {
                 masked_url: Some(masked),
             })?;
 
-            Ok(Connection::Sqlite(SqliteConnection { conn }))
+            Ok(Connection::Sqlite(SqliteConnection {
+                conn,
+                query_timeout,
+            }))
         }
     }
 }
 
+/// Name of the snapshot taken after file setup for isolated databases.
+const ISOLATION_SNAPSHOT: &str = "__isolation__";
+
 /// A database snapshot stored in memory.
 ///
 /// Currently only SQLite snapshots are supported.
@@ -362,6 +599,12 @@ impl ConnectionManager {
 
     /// Execute a SQL statement on the named database.
     pub fn execute(&self, database: &str, sql: &str) -> Result<String, DbError> {
+        self.execute_with_columns(database, sql)
+            .map(|r| r.to_text())
+    }
+
+    /// Execute a SQL statement on the named database, keeping the result column names.
+    pub fn execute_with_columns(&self, database: &str, sql: &str) -> Result<QueryResult, DbError> {
         let mut connections = self.get(database)?;
         let conn = connections.get_mut(database).ok_or_else(|| DbError {
             message: format!("Connection for '{database}' not found after creation"),
@@ -369,12 +612,43 @@ impl ConnectionManager {
             masked_url: None,
         })?;
 
-        conn.execute(sql).map_err(|mut e| {
+        conn.execute_with_columns(sql).map_err(|mut e| {
             e.database = Some(database.to_string());
             e
         })
     }
 
+    /// Execute each statement of a SQL script on the named database.
+    ///
+    /// The script is split with [`split_statements`] and statements run in order,
+    /// stopping at the first error.
+    pub fn execute_script(&self, database: &str, sql: &str) -> Result<(), DbError> {
+        for statement in split_statements(sql) {
+            self.execute(database, &statement)?;
+        }
+        Ok(())
+    }
+
+    /// Whether this manager connects to exactly these databases.
+    pub fn has_configs(&self, configs: &HashMap<String, DatabaseConfig>) -> bool {
+        &self.configs == configs
+    }
+
+    /// Drop every snapshot, so a manager shared across spec files starts each
+    /// file without the previous file's snapshots.
+    pub fn clear_snapshots(&self) {
+        if let Ok(mut snapshots) = self.snapshots.lock() {
+            snapshots.clear();
+        }
+    }
+
+    /// Get the names of all configured databases, sorted for deterministic order.
+    pub fn database_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.configs.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
     /// Check if any databases are configured.
     pub fn has_databases(&self) -> bool {
         !self.configs.is_empty()
@@ -390,68 +664,79 @@ impl ConnectionManager {
         self.configs.get(name).map(|c| c.isolation)
     }
 
-    /// Get all database names that have per-file isolation enabled.
+    /// The configurations of the databases this manager connects to.
+    pub fn configs(&self) -> &HashMap<String, DatabaseConfig> {
+        &self.configs
+    }
+
+    /// Get all database names that have per-file or per-test isolation enabled.
     pub fn get_isolated_databases(&self) -> Vec<String> {
         self.configs
             .iter()
-            .filter(|(_, config)| config.isolation == DbIsolation::PerFile)
+            .filter(|(_, config)| config.isolation != DbIsolation::None)
             .map(|(name, _)| name.clone())
             .collect()
     }
 
+    /// Whether any database uses per-test isolation (which forces tests serial).
+    pub fn has_per_test_isolation(&self) -> bool {
+        self.configs
+            .values()
+            .any(|config| config.isolation == DbIsolation::PerTest)
+    }
+
     /// Initialize isolation for a database.
     ///
-    /// For SQLite with `per_file` isolation: Creates a fresh in-memory database.
-    /// For PostgreSQL with `per_file` isolation: Creates an automatic snapshot
+    /// For SQLite with `per_file` or `per_test` isolation: Creates a fresh in-memory database.
+    /// For PostgreSQL with `per_file` or `per_test` isolation: Creates an automatic snapshot
     /// named `__isolation__` after file-level setup completes.
     ///
-    /// This should be called after file-level setup for databases with `per_file` isolation.
+    /// This should be called after file-level setup for databases with isolation enabled.
     pub fn init_isolation(&self, database: &str) -> Result<(), DbError> {
-        let config = self.configs.get(database).ok_or_else(|| DbError {
-            message: format!("Database '{database}' is not configured"),
-            database: Some(database.to_string()),
-            masked_url: None,
-        })?;
-
-        if config.isolation != DbIsolation::PerFile {
+        let Some(config) = self.isolated_config(database)? else {
             return Ok(());
-        }
+        };
 
         match config.driver {
             DbDriver::Sqlite => {
                 // For SQLite, we create a snapshot after setup completes
                 // This will be restored before each test
-                self.create_snapshot(database, "__isolation__")
+                self.create_snapshot(database, ISOLATION_SNAPSHOT)
             }
             DbDriver::Postgres => {
                 // For PostgreSQL, we also create a snapshot after setup
                 // Note: This requires the connection to already exist (from setup)
                 // If no setup was run, we try to connect and create a snapshot
                 drop(self.get(database)?);
-                self.create_snapshot(database, "__isolation__")
+                self.create_snapshot(database, ISOLATION_SNAPSHOT)
             }
         }
     }
 
     /// Reset a database to its isolated state.
     ///
-    /// For databases with `per_file` isolation, restores from the `__isolation__`
-    /// snapshot created after file-level setup.
+    /// For databases with `per_file` or `per_test` isolation, restores from the
+    /// `__isolation__` snapshot created after file-level setup.
     ///
-    /// This should be called before each test for databases with `per_file` isolation.
+    /// This should be called before each test for databases with isolation enabled.
     pub fn reset_isolation(&self, database: &str) -> Result<(), DbError> {
+        if self.isolated_config(database)?.is_none() {
+            return Ok(());
+        }
+
+        // Restore from the isolation snapshot
+        self.restore_snapshot(database, ISOLATION_SNAPSHOT)
+    }
+
+    /// The config of a database with `per_file` or `per_test` isolation, or
+    /// `None` if the database is not isolated.
+    fn isolated_config(&self, database: &str) -> Result<Option<&DatabaseConfig>, DbError> {
         let config = self.configs.get(database).ok_or_else(|| DbError {
             message: format!("Database '{database}' is not configured"),
             database: Some(database.to_string()),
             masked_url: None,
         })?;
-
-        if config.isolation != DbIsolation::PerFile {
-            return Ok(());
-        }
-
-        // Restore from the isolation snapshot
-        self.restore_snapshot(database, "__isolation__")
+        Ok((config.isolation != DbIsolation::None).then_some(config))
     }
 
     /// Create a snapshot of the database state.
@@ -676,12 +961,53 @@ mod tests {
         assert!(result.unwrap_err().message.contains("Unclosed"));
     }
 
+    #[test]
+    fn test_split_statements() {
+        let script = "CREATE TABLE t (x INTEGER);\nINSERT INTO t VALUES (1);\n\n;  ";
+        assert_eq!(
+            split_statements(script),
+            vec!["CREATE TABLE t (x INTEGER)", "INSERT INTO t VALUES (1)"]
+        );
+        assert!(split_statements("").is_empty());
+    }
+
+    #[test]
+    fn test_split_statements_ignores_quoted_and_commented_semicolons() {
+        let script = "INSERT INTO t VALUES ('a;b', 'it''s;');\n\
+                      -- a comment; not a statement\n\
+                      /* block; comment */ SELECT \"odd;name\" FROM t;\n\
+                      SELECT $body$ x; y $body$, $1;\n\
+                      -- trailing comment";
+        assert_eq!(
+            split_statements(script),
+            vec![
+                "INSERT INTO t VALUES ('a;b', 'it''s;')",
+                "-- a comment; not a statement\n/* block; comment */ SELECT \"odd;name\" FROM t",
+                "SELECT $body$ x; y $body$, $1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_statements_keeps_trigger_bodies_whole() {
+        let trigger = "CREATE TRIGGER log_insert AFTER INSERT ON t\n\
+                       BEGIN\n\
+                       INSERT INTO log VALUES (CASE WHEN new.x > 0 THEN 'pos' ELSE 'neg' END);\n\
+                       UPDATE t SET x = x;\n\
+                       END";
+        let script = format!("BEGIN;\n{trigger};\nCOMMIT;");
+        assert_eq!(split_statements(&script), vec!["BEGIN", trigger, "COMMIT"]);
+    }
+
     #[test]
     fn test_sqlite_memory() {
         let config = DatabaseConfig {
             driver: DbDriver::Sqlite,
             url: "sqlite::memory:".to_string(),
             isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
         };
 
         let mut conn = connect(&config, "test").unwrap();
@@ -706,6 +1032,208 @@ mod tests {
         assert_eq!(count, "2");
     }
 
+    #[test]
+    fn test_sqlite_execute_with_columns() {
+        let config = DatabaseConfig {
+            driver: DbDriver::Sqlite,
+            url: "sqlite::memory:".to_string(),
+            isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        let result = conn
+            .execute_with_columns("SELECT 1 AS id, 'alice' AS name")
+            .unwrap();
+        assert_eq!(result.columns, vec!["id", "name"]);
+        assert_eq!(result.rows, vec![vec!["1", "alice"]]);
+
+        let result = conn.execute_with_columns("CREATE TABLE t (x)").unwrap();
+        assert_eq!(
+            result,
+            QueryResult {
+                affected: Some(0),
+                ..QueryResult::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_sqlite_dml_returns_affected_rows() {
+        let config = DatabaseConfig {
+            driver: DbDriver::Sqlite,
+            url: "sqlite::memory:".to_string(),
+            isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        conn.execute("CREATE TABLE t (id INTEGER, done INTEGER)")
+            .unwrap();
+        assert_eq!(
+            conn.execute("INSERT INTO t VALUES (1, 0), (2, 0), (3, 0), (4, 1)")
+                .unwrap(),
+            "4"
+        );
+        assert_eq!(
+            conn.execute("UPDATE t SET done = 1 WHERE done = 0")
+                .unwrap(),
+            "3"
+        );
+        // DDL after DML reports no changes rather than the previous count
+        assert_eq!(conn.execute("CREATE TABLE u (x)").unwrap(), "0");
+        // Queries are unaffected, even when they return no rows
+        assert_eq!(conn.execute("SELECT id FROM t WHERE id > 9").unwrap(), "");
+    }
+
+    #[test]
+    fn test_sqlite_query_timeout() {
+        let config = DatabaseConfig {
+            driver: DbDriver::Sqlite,
+            url: "sqlite::memory:".to_string(),
+            isolation: DbIsolation::None,
+            query_timeout_ms: Some(100),
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        let start = Instant::now();
+        let err = conn
+            .execute(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) \
+                 SELECT COUNT(*) FROM n",
+            )
+            .unwrap_err();
+        assert_eq!(err.message, "query timed out after 100ms");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // The connection remains usable after a timeout
+        assert_eq!(conn.execute("SELECT 1").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_postgres_query_timeout() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            return;
+        };
+        let config = DatabaseConfig {
+            driver: DbDriver::Postgres,
+            url,
+            isolation: DbIsolation::None,
+            query_timeout_ms: Some(200),
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        let err = conn.execute("SELECT pg_sleep(5)").unwrap_err();
+        assert_eq!(err.message, "query timed out after 200ms");
+    }
+
+    #[test]
+    fn test_connect_retries_until_database_is_available() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent = dir.path().join("not-yet");
+        let config = DatabaseConfig {
+            driver: DbDriver::Sqlite,
+            url: parent.join("test.db").display().to_string(),
+            isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 0,
+            connect_retry_delay_ms: Some(20),
+        };
+        assert!(connect(&config, "test").is_err());
+
+        let creator = {
+            let parent = parent.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                std::fs::create_dir(parent).unwrap();
+            })
+        };
+        let config = DatabaseConfig {
+            connect_retries: 100,
+            ..config
+        };
+        let mut conn = connect(&config, "test").unwrap();
+        creator.join().unwrap();
+        assert_eq!(conn.execute("SELECT 1").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_postgres_connect_retries_until_port_opens() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            return;
+        };
+        // Forward a port that only starts listening after a delay to the real server
+        let (scheme, rest) = url.split_once("://").unwrap();
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (userinfo, target) = match authority.rsplit_once('@') {
+            Some((userinfo, target)) => (format!("{userinfo}@"), target.to_string()),
+            None => (String::new(), authority.to_string()),
+        };
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap();
+            for client in listener.incoming().flatten() {
+                let server = std::net::TcpStream::connect(&target).unwrap();
+                for (mut from, mut to) in [
+                    (client.try_clone().unwrap(), server.try_clone().unwrap()),
+                    (server, client),
+                ] {
+                    std::thread::spawn(move || std::io::copy(&mut from, &mut to));
+                }
+            }
+        });
+        let config = DatabaseConfig {
+            driver: DbDriver::Postgres,
+            url: format!("{scheme}://{userinfo}127.0.0.1:{port}/{path}"),
+            isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 20,
+            connect_retry_delay_ms: Some(100),
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        assert_eq!(conn.execute("SELECT 1").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_postgres_many_queries_share_runtime() {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            return;
+        };
+        let config = DatabaseConfig {
+            driver: DbDriver::Postgres,
+            url,
+            isolation: DbIsolation::None,
+            query_timeout_ms: None,
+            connect_retries: 0,
+            connect_retry_delay_ms: None,
+        };
+
+        let mut conn = connect(&config, "test").unwrap();
+        let start = Instant::now();
+        for i in 0..500 {
+            assert_eq!(conn.execute(&format!("SELECT {i}")).unwrap(), i.to_string());
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "500 queries took {:?}",
+            start.elapsed()
+        );
+    }
+
     #[test]
     fn test_connection_manager_sqlite() {
         let mut configs = HashMap::new();
@@ -715,6 +1243,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -747,6 +1278,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -806,6 +1340,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -827,6 +1364,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -884,6 +1424,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::PerFile,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -957,6 +1500,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::PerFile,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
         configs.insert(
@@ -965,6 +1511,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
         configs.insert(
@@ -973,6 +1522,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::PerFile,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -981,6 +1533,41 @@ mod tests {
         isolated.sort();
 
         assert_eq!(isolated, vec!["isolated1", "isolated2"]);
+        assert!(!manager.has_per_test_isolation());
+    }
+
+    #[test]
+    fn test_per_test_isolation_resets() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "default".to_string(),
+            DatabaseConfig {
+                driver: DbDriver::Sqlite,
+                url: "sqlite::memory:".to_string(),
+                isolation: DbIsolation::PerTest,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
+            },
+        );
+
+        let manager = ConnectionManager::new(configs);
+        assert!(manager.has_per_test_isolation());
+        assert_eq!(manager.get_isolated_databases(), vec!["default"]);
+
+        manager
+            .execute("default", "CREATE TABLE test (x INTEGER)")
+            .unwrap();
+        manager.init_isolation("default").unwrap();
+        manager
+            .execute("default", "INSERT INTO test VALUES (1)")
+            .unwrap();
+        manager.reset_isolation("default").unwrap();
+
+        let count = manager
+            .execute("default", "SELECT COUNT(*) FROM test")
+            .unwrap();
+        assert_eq!(count, "0");
     }
 
     #[test]
@@ -992,6 +1579,9 @@ mod tests {
                 driver: DbDriver::Sqlite,
                 url: "sqlite::memory:".to_string(),
                 isolation: DbIsolation::None,
+                query_timeout_ms: None,
+                connect_retries: 0,
+                connect_retry_delay_ms: None,
             },
         );
 
@@ -1,6 +1,7 @@
 //! Environment variable interpolation utilities.
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 /// Interpolate environment variables in a string.
 ///
@@ -9,7 +10,8 @@ use std::collections::HashMap;
 /// # Examples
 ///
 /// ```
-/// std::env::set_var("MY_VAR", "hello");
+/// // SAFETY: the example is single-threaded
+/// unsafe { std::env::set_var("MY_VAR", "hello") };
 /// assert_eq!(bintest::env::interpolate_env("${MY_VAR}").unwrap(), "hello");
 /// assert_eq!(bintest::env::interpolate_env("prefix_${MY_VAR}_suffix").unwrap(), "prefix_hello_suffix");
 /// ```
@@ -22,6 +24,28 @@ pub fn interpolate_env(s: &str) -> Result<String, String> {
 /// First checks the provided map, then falls back to system environment variables.
 /// Supports `${VAR}` syntax. Returns an error message if a referenced variable is not set.
 pub fn interpolate_env_with(s: &str, env: &HashMap<String, String>) -> Result<String, String> {
+    interpolate(s, env, false)
+}
+
+/// Interpolate environment variables in a command argument.
+///
+/// Only `${NAME}` references to a plain identifier set in `env` are replaced; there
+/// is no fallback to the system environment. Anything else, such as the shell
+/// syntax `${VAR:-default}` or a shell-local `${f}` in a `sh -c` script, is kept
+/// verbatim for the shell to expand.
+pub fn interpolate_arg(s: &str, env: &HashMap<String, String>) -> Result<String, String> {
+    interpolate(s, env, true)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn interpolate(s: &str, env: &HashMap<String, String>, known_only: bool) -> Result<String, String> {
     let mut result = String::with_capacity(s.len());
     let mut chars = s.chars().peekable();
 
@@ -38,6 +62,12 @@ pub fn interpolate_env_with(s: &str, env: &HashMap<String, String>) -> Result<St
                     }
                 }
             }
+            if known_only && !(is_identifier(&var_name) && env.contains_key(&var_name)) {
+                result.push_str("${");
+                result.push_str(&var_name);
+                result.push('}');
+                continue;
+            }
             // First check provided env map, then system env
             let value = env
                 .get(&var_name)
@@ -53,6 +83,93 @@ pub fn interpolate_env_with(s: &str, env: &HashMap<String, String>) -> Result<St
     Ok(result)
 }
 
+/// Replace `${vars.NAME}` references in a string with values from `vars`.
+///
+/// A name missing from `vars` falls back to the system environment variable `NAME`.
+/// Other `${...}` references are left untouched, so shell syntax like `${HOME:-x}`
+/// in command arguments passes through.
+pub fn interpolate_vars(s: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    const PREFIX: &str = "${vars.";
+
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(PREFIX) {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + PREFIX.len()..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unclosed variable reference: {}", &rest[start..]))?;
+        let name = &after[..end];
+        let value = vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .ok_or_else(|| format!("Variable 'vars.{name}' is not defined"))?;
+        result.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Expand `${VAR}` references and a leading `~` (the home directory) in a path.
+///
+/// Paths that aren't valid UTF-8 are returned unchanged.
+pub fn expand_path(path: &Path) -> Result<PathBuf, String> {
+    let Some(s) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    let s = interpolate_env(s)?;
+    let home = || std::env::var("HOME").map_err(|_| "HOME is not set".to_string());
+    Ok(match s.strip_prefix('~') {
+        Some("") => PathBuf::from(home()?),
+        Some(rest) if rest.starts_with('/') => PathBuf::from(home()? + rest),
+        _ => PathBuf::from(s),
+    })
+}
+
+/// Parse a dotenv file into a map of variables.
+///
+/// Each non-blank line that isn't a `#` comment must be `KEY=VALUE`, optionally
+/// prefixed with `export `. Values wrapped in matching single or double quotes
+/// have the quotes removed; no interpolation or escape processing is done.
+pub fn parse_env_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_env(&contents)
+}
+
+/// Parse dotenv-formatted contents. See [`parse_env_file`].
+fn parse_env(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut vars = HashMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=VALUE", index + 1))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("line {}: missing variable name", index + 1));
+        }
+
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|&(open, close)| value.strip_prefix(open).and_then(|v| v.strip_suffix(close)))
+            .unwrap_or(value);
+
+        vars.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(vars)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,4 +202,98 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Unclosed"));
     }
+
+    #[test]
+    fn test_interpolate_arg() {
+        let env = HashMap::from([("NAME".to_string(), "world".to_string())]);
+        assert_eq!(
+            interpolate_arg("--name=${NAME}", &env).unwrap(),
+            "--name=world"
+        );
+        assert_eq!(
+            interpolate_arg("echo ${HOME:-none} ${PATH-x} ${NAME}", &env).unwrap(),
+            "echo ${HOME:-none} ${PATH-x} world"
+        );
+        assert_eq!(
+            interpolate_arg("${BINTEST_UNSET_ARG_12345}", &env).unwrap(),
+            "${BINTEST_UNSET_ARG_12345}"
+        );
+        assert!(interpolate_arg("${UNCLOSED", &env).is_err());
+    }
+
+    #[test]
+    fn test_interpolate_vars() {
+        let vars = HashMap::from([("port".to_string(), "8080".to_string())]);
+        assert_eq!(
+            interpolate_vars("--port=${vars.port}", &vars).unwrap(),
+            "--port=8080"
+        );
+        assert_eq!(
+            interpolate_vars("echo ${HOME:-none} ${vars.port}", &vars).unwrap(),
+            "echo ${HOME:-none} 8080"
+        );
+        let err = interpolate_vars("${vars.missing_12345}", &vars).unwrap_err();
+        assert!(err.contains("vars.missing_12345"), "{err}");
+        assert!(interpolate_vars("${vars.port", &vars).is_err());
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(
+            expand_path(Path::new("${HOME}/sandbox")).unwrap(),
+            PathBuf::from(format!("{home}/sandbox"))
+        );
+        assert_eq!(
+            expand_path(Path::new("~/sandbox")).unwrap(),
+            PathBuf::from(format!("{home}/sandbox"))
+        );
+        assert_eq!(expand_path(Path::new("~")).unwrap(), PathBuf::from(&home));
+        assert_eq!(
+            expand_path(Path::new("a/~b")).unwrap(),
+            PathBuf::from("a/~b")
+        );
+        assert!(expand_path(Path::new("${BINTEST_UNSET_SANDBOX_VAR}/x")).is_err());
+    }
+
+    #[test]
+    fn test_parse_env() {
+        let vars = parse_env(
+            "# database settings\n\
+             DATABASE_URL=postgres://localhost/test\n\
+             \n\
+             export API_KEY=secret\n\
+             GREETING=\"hello world\"\n\
+             SINGLE='single quoted'\n\
+             EMPTY=\n\
+             WITH_EQUALS=a=b\n",
+        )
+        .unwrap();
+
+        assert_eq!(vars["DATABASE_URL"], "postgres://localhost/test");
+        assert_eq!(vars["API_KEY"], "secret");
+        assert_eq!(vars["GREETING"], "hello world");
+        assert_eq!(vars["SINGLE"], "single quoted");
+        assert_eq!(vars["EMPTY"], "");
+        assert_eq!(vars["WITH_EQUALS"], "a=b");
+        assert_eq!(vars.len(), 6);
+    }
+
+    #[test]
+    fn test_parse_env_invalid_line() {
+        let result = parse_env("VALID=1\nnot a variable\n");
+        assert!(result.unwrap_err().contains("line 2"));
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(&path, "export FOO='bar'\n").unwrap();
+
+        let vars = parse_env_file(&path).unwrap();
+        assert_eq!(vars["FOO"], "bar");
+
+        assert!(parse_env_file(&dir.path().join("missing.env")).is_err());
+    }
 }
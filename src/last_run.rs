@@ -0,0 +1,74 @@
+//! The failures of the previous run, cached for `--only-failures`.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where each run records its failures, relative to the working directory.
+pub const LAST_RUN_PATH: &str = ".bintest/last-run.json";
+
+/// The tests that failed in the most recent run.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LastRun {
+    pub failed: Vec<FailedTest>,
+}
+
+/// One failing test. Names in angle brackets (`<load>`, `<setup>`, ...) mark a
+/// failure of the file as a whole.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailedTest {
+    /// Absolute path of the spec file.
+    pub file: PathBuf,
+    pub test: String,
+}
+
+impl LastRun {
+    /// Record a failing test of the spec at `file`.
+    pub fn add(&mut self, file: &Path, test: &str) {
+        self.failed.push(FailedTest {
+            file: std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf()),
+            test: test.to_string(),
+        });
+    }
+
+    /// Load the cache written by the previous run.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    }
+
+    /// Write the cache as pretty-printed JSON, creating its directory.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n").map_err(|e| e.to_string())
+    }
+
+    /// What to re-run of the spec at `file`, or `None` if nothing in it failed.
+    pub fn rerun_for(&self, file: &Path) -> Option<Rerun> {
+        let file = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+        let names: Vec<String> = self
+            .failed
+            .iter()
+            .filter(|f| f.file == file)
+            .map(|f| f.test.clone())
+            .collect();
+        if names.is_empty() {
+            None
+        } else if names.iter().any(|n| n.starts_with('<')) {
+            Some(Rerun::File)
+        } else {
+            Some(Rerun::Tests(names))
+        }
+    }
+}
+
+/// The part of a spec file that failed last time.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rerun {
+    /// The file as a whole failed (e.g. to load or set up): run all its tests.
+    File,
+    /// Only these tests failed.
+    Tests(Vec<String>),
+}
@@ -0,0 +1,292 @@
+//! A declarative integration test runner for executables.
+//!
+//! The `bintest` binary is a thin CLI over this library. To drive bintest from
+//! your own harness, call [`run_path`] on a spec file or directory, build a run
+//! from [`Suite`], [`load_specs`] and [`run_specs`] as the CLI does, or load and
+//! run specs one at a time with [`loader::load_spec`] and [`runner::run_spec`].
+//!
+//! ```
+//! let dir = tempfile::tempdir().unwrap();
+//! std::fs::write(
+//!     dir.path().join("echo.yaml"),
+//!     r#"
+//! version: 1
+//! tests:
+//!   - name: greets
+//!     run: { cmd: echo, args: ["hello"] }
+//!     expect: { stdout: "hello\n" }
+//! "#,
+//! )
+//! .unwrap();
+//!
+//! let report = bintest::run_path(dir.path(), &bintest::RunOptions::default()).unwrap();
+//! assert!(report.passed());
+//! let names: Vec<_> = report.tests().map(|t| t.name.as_str()).collect();
+//! assert_eq!(names, ["greets"]);
+//! ```
+
+pub mod database;
+pub mod env;
+pub mod loader;
+pub mod record;
+pub mod runner;
+pub mod schema;
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// Options for [`run_path`], mirroring the flags of `bintest run`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Which tests run (`--filter`, `--tag`, `--exclude-tag`). `only` is set
+    /// automatically when any loaded test has `only: true`.
+    pub filter: runner::TestFilter,
+    /// Directory for test sandboxes, overriding the suite config (`--sandbox-dir`).
+    pub sandbox_dir: Option<schema::SandboxDir>,
+    /// Most spec files to run at once (`--max-parallel-files`). Unbounded when unset.
+    pub max_parallel_files: Option<usize>,
+    /// SQL to seed every configured database with before file setup (`--seed-db`).
+    pub seed_sql: Option<String>,
+}
+
+/// The outcome of [`run_path`]: one entry per spec file, in discovery order.
+#[derive(Debug)]
+pub struct RunReport {
+    /// The result of each spec file, in discovery order.
+    pub files: Vec<FileReport>,
+    /// The error from suite teardown, if it failed.
+    pub suite_teardown_error: Option<String>,
+}
+
+/// The outcome of one spec file.
+#[derive(Debug)]
+pub struct FileReport {
+    /// Path of the spec file.
+    pub path: PathBuf,
+    /// The file's test results, or the error that kept it from loading.
+    pub result: Result<runner::SpecResult, loader::LoadError>,
+}
+
+impl RunReport {
+    /// Whether every file loaded, every test passed (skipped tests count as
+    /// passed), and suite teardown succeeded.
+    pub fn passed(&self) -> bool {
+        self.suite_teardown_error.is_none()
+            && self.files.iter().all(|file| {
+                file.result
+                    .as_ref()
+                    .is_ok_and(|spec| spec.tests.iter().all(|t| t.passed))
+            })
+    }
+
+    /// The results of every test in every file that loaded.
+    pub fn tests(&self) -> impl Iterator<Item = &runner::TestResult> {
+        self.files
+            .iter()
+            .filter_map(|file| file.result.as_ref().ok())
+            .flat_map(|spec| &spec.tests)
+    }
+}
+
+/// A spec file path and the result of loading it.
+pub type LoadedSpec = (PathBuf, Result<schema::TestSpec, loader::LoadError>);
+
+/// The directory whose `bintest.yaml` applies to a spec file or directory.
+pub fn test_root(path: &Path) -> &Path {
+    if path.is_file() {
+        path.parent().unwrap_or(path)
+    } else {
+        path
+    }
+}
+
+/// Load each spec file, keeping load errors alongside the path.
+pub fn load_specs(paths: &[PathBuf]) -> Vec<LoadedSpec> {
+    paths
+        .iter()
+        .map(|p| (p.clone(), loader::load_spec(p)))
+        .collect()
+}
+
+/// Whether any loaded test is marked `only: true`, which focuses every file
+/// in the run on those tests (see [`runner::TestFilter::only`]).
+pub fn has_only_tests(specs: &[LoadedSpec]) -> bool {
+    specs
+        .iter()
+        .filter_map(|(_, spec)| spec.as_ref().ok())
+        .any(|spec| spec.tests.iter().any(|t| t.only))
+}
+
+/// The suite config of a run, with the sandbox shared by suite setup and
+/// teardown.
+pub struct Suite {
+    config: Option<schema::SuiteConfig>,
+    context: Option<runner::SuiteContext>,
+}
+
+impl Suite {
+    /// Create the suite sandbox when `config` has setup or teardown steps,
+    /// exposing its path to every test as `BINTEST_SUITE_DIR`.
+    pub fn new(mut config: Option<schema::SuiteConfig>) -> Result<Self, String> {
+        let context = match config {
+            Some(ref mut config) if !config.setup.is_empty() || !config.teardown.is_empty() => {
+                let suite = runner::SuiteContext::new(config)?;
+                config.env.insert(
+                    runner::SUITE_DIR_ENV.to_string(),
+                    suite.dir().display().to_string(),
+                );
+                Some(suite)
+            }
+            _ => None,
+        };
+        Ok(Self { config, context })
+    }
+
+    /// The suite config, if any.
+    pub fn config(&self) -> Option<&schema::SuiteConfig> {
+        self.config.as_ref()
+    }
+
+    /// Run suite setup, unless only teardown runs (`--teardown-only`).
+    pub fn setup(&self) -> Result<(), String> {
+        match (&self.config, &self.context) {
+            (Some(config), Some(suite))
+                if config.fixture_mode != schema::FixtureMode::TeardownOnly =>
+            {
+                runner::run_suite_setup(config, suite)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run suite teardown, unless only setup runs (`--setup-only`).
+    pub fn teardown(&self) -> Result<(), String> {
+        match (&self.config, &self.context) {
+            (Some(config), Some(suite))
+                if config.fixture_mode != schema::FixtureMode::SetupOnly =>
+            {
+                runner::run_suite_teardown(config, suite)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Run loaded spec files the way `bintest run` does.
+///
+/// Files run in parallel, at most `max_parallel_files` at once, or one after
+/// another when the suite is `serial` or uses `no_capture`. Serial files share
+/// suite database connections when the suite sets `reuse_connections`.
+/// `filter_for` picks each file's test filter, and `on_file` is called from
+/// the worker thread as each file finishes. Reports keep the order of `specs`.
+pub fn run_specs(
+    specs: Vec<LoadedSpec>,
+    suite_config: Option<&schema::SuiteConfig>,
+    max_parallel_files: Option<usize>,
+    filter_for: impl Fn(&Path) -> runner::TestFilter + Sync,
+    on_file: impl Fn(&FileReport) + Sync,
+) -> Vec<FileReport> {
+    let max_files = if suite_config.is_some_and(|c| c.serial || c.no_capture) {
+        1
+    } else {
+        max_parallel_files.unwrap_or(usize::MAX)
+    };
+    let workers = max_files.min(specs.len());
+    // With serial files, suite databases may keep one connection for the whole run
+    let shared_db = suite_config
+        .filter(|c| max_files == 1 && c.reuse_connections && !c.databases.is_empty())
+        .map(|c| database::ConnectionManager::new(c.databases.clone()));
+
+    // Each worker pulls the next file from a shared queue; results are put
+    // back in the original order
+    let queue = Mutex::new(specs.into_iter().enumerate());
+    let mut reports: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let next = queue.lock().expect("queue poisoned").next();
+                        let Some((index, (path, spec))) = next else {
+                            break;
+                        };
+                        let filter = filter_for(&path);
+                        let report = FileReport {
+                            result: spec.map(|spec| {
+                                runner::run_spec_filtered(
+                                    &spec,
+                                    suite_config,
+                                    &filter,
+                                    shared_db.as_ref(),
+                                )
+                            }),
+                            path,
+                        };
+                        on_file(&report);
+                        done.push((index, report));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("Spec thread panicked"))
+            .collect()
+    });
+    if let Some(db) = &shared_db {
+        db.close_all();
+    }
+    reports.sort_by_key(|(index, _)| *index);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Run the spec file or directory at `path`, like `bintest run`.
+///
+/// The suite config (`bintest.yaml`) next to the file, or in the directory, is
+/// applied, including suite setup and teardown. An error is returned only if
+/// the run could not start or its suite setup failed; failing tests, specs
+/// that fail to load, and a failed suite teardown are reported in the
+/// [`RunReport`].
+pub fn run_path(path: impl AsRef<Path>, options: &RunOptions) -> Result<RunReport, String> {
+    let path = path.as_ref();
+    let spec_paths = loader::find_specs(path).map_err(|e| format!("Error finding specs: {e}"))?;
+    if spec_paths.is_empty() {
+        return Err(format!("No spec files found at: {}", path.display()));
+    }
+
+    let mut suite_config = loader::load_suite_config(test_root(path))
+        .map_err(|e| format!("Error loading suite config: {e}"))?;
+    if let Some(dir) = &options.sandbox_dir {
+        suite_config
+            .get_or_insert_with(Default::default)
+            .sandbox_dir = Some(dir.clone());
+    }
+    if let Some(sql) = &options.seed_sql {
+        suite_config.get_or_insert_with(Default::default).seed_sql = Some(sql.clone());
+    }
+
+    let suite = Suite::new(suite_config)?;
+    suite
+        .setup()
+        .map_err(|e| format!("Suite setup failed: {e}"))?;
+
+    let specs = load_specs(&spec_paths);
+    let filter = runner::TestFilter {
+        only: has_only_tests(&specs),
+        ..options.filter.clone()
+    };
+    let files = run_specs(
+        specs,
+        suite.config(),
+        options.max_parallel_files,
+        |_| filter.clone(),
+        |_| {},
+    );
+
+    Ok(RunReport {
+        files,
+        suite_teardown_error: suite.teardown().err(),
+    })
+}
@@ -3,7 +3,8 @@
 //! Loads and parses test specification files from disk.
 
 use crate::env;
-use crate::schema::{SuiteConfig, TestSpec};
+use crate::schema::{SqlExpect, SuiteConfig, TestSpec};
+use std::collections::HashMap;
 use std::path::Path;
 
 /// Error type for spec loading operations.
@@ -15,6 +16,8 @@ pub enum LoadError {
     Yaml(serde_yaml::Error),
     /// Failed to parse TOML.
     Toml(toml::de::Error),
+    /// Failed to parse JSON.
+    Json(serde_json::Error),
     /// Unsupported file extension.
     UnsupportedFormat(String),
     /// Spec validation failed.
@@ -27,10 +30,11 @@ impl std::fmt::Display for LoadError {
             LoadError::Io(e) => write!(f, "failed to read file: {e}"),
             LoadError::Yaml(e) => write!(f, "invalid YAML: {e}"),
             LoadError::Toml(e) => write!(f, "invalid TOML: {e}"),
+            LoadError::Json(e) => write!(f, "invalid JSON: {e}"),
             LoadError::UnsupportedFormat(ext) => {
                 write!(
                     f,
-                    "unsupported file format: {ext} (expected .yaml, .yml, or .toml)"
+                    "unsupported file format: {ext} (expected .yaml, .yml, .toml, or .json)"
                 )
             }
             LoadError::Validation(msg) => write!(f, "validation error: {msg}"),
@@ -51,48 +55,105 @@ pub fn load_spec(path: &Path) -> Result<TestSpec, LoadError> {
     let mut spec: TestSpec = match ext {
         "yaml" | "yml" => serde_yaml::from_str(&contents).map_err(LoadError::Yaml)?,
         "toml" => toml::from_str(&contents).map_err(LoadError::Toml)?,
+        "json" => serde_json::from_str(&contents).map_err(LoadError::Json)?,
         other => return Err(LoadError::UnsupportedFormat(other.to_string())),
     };
 
     validate_spec(&spec)?;
+    spec.source_path = Some(path.to_path_buf());
 
     // Resolve binary path relative to spec file location
     if let Some(binary) = &spec.binary {
         spec.resolved_binary = Some(resolve_binary_path(binary, path)?);
     }
 
+    // Load sandbox env_file relative to spec file location
+    if let Some(env_file) = &spec.sandbox.env_file {
+        merge_env_file(&mut spec.sandbox.env, env_file, path)?;
+    }
+
     Ok(spec)
 }
 
+/// Merge variables from a dotenv file into an env map.
+///
+/// The file is resolved relative to the config file's directory. Variables
+/// already present in `env` take precedence over those from the file.
+fn merge_env_file(
+    env: &mut HashMap<String, String>,
+    env_file: &Path,
+    config_path: &Path,
+) -> Result<(), LoadError> {
+    let config_dir = config_path.parent().unwrap_or(Path::new("."));
+    let vars = env::parse_env_file(&config_dir.join(env_file))
+        .map_err(|e| LoadError::Validation(format!("env_file '{}': {e}", env_file.display())))?;
+    for (key, value) in vars {
+        env.entry(key).or_insert(value);
+    }
+    Ok(())
+}
+
 /// Resolve a binary path relative to a config file location.
 ///
 /// If the binary path contains `${VAR}` references, they are interpolated first.
 /// The resulting path is resolved relative to the config file's directory,
-/// then canonicalized to an absolute path.
+/// then canonicalized to an absolute path and checked to be an executable file.
 fn resolve_binary_path(binary: &str, config_path: &Path) -> Result<std::path::PathBuf, LoadError> {
     // Interpolate environment variables in the binary path
     let interpolated = env::interpolate_env(binary).map_err(LoadError::Validation)?;
 
     let binary_path = std::path::Path::new(&interpolated);
 
-    // If already absolute, just canonicalize
-    if binary_path.is_absolute() {
-        return binary_path
-            .canonicalize()
-            .map_err(|e| LoadError::Validation(format!("binary '{}': {}", binary, e)));
+    // If already absolute, just canonicalize; otherwise resolve relative to
+    // the config file's parent directory
+    let resolved = if binary_path.is_absolute() {
+        binary_path.to_path_buf()
+    } else {
+        let config_dir = config_path.parent().unwrap_or(Path::new("."));
+        config_dir.join(binary_path)
+    };
+
+    let resolved = resolved
+        .canonicalize()
+        .map_err(|e| LoadError::Validation(format!("binary '{}': {}", binary, e)))?;
+    check_executable(&resolved)
+        .map_err(|e| LoadError::Validation(format!("binary '{}': {}", binary, e)))?;
+    Ok(resolved)
+}
+
+/// Check that a path is a file that can be executed, so a misconfigured
+/// binary fails at load time instead of at the first spawn.
+fn check_executable(path: &Path) -> Result<(), String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
     }
 
-    // Resolve relative to config file's parent directory
-    let config_dir = config_path.parent().unwrap_or(Path::new("."));
-    let resolved = config_dir.join(binary_path);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!(
+                "{} is not executable (no execute permission)",
+                path.display()
+            ));
+        }
+    }
 
-    resolved
-        .canonicalize()
-        .map_err(|e| LoadError::Validation(format!("binary '{}': {}", binary, e)))
+    Ok(())
 }
 
 /// Validate a test spec for semantic correctness.
 fn validate_spec(spec: &TestSpec) -> Result<(), LoadError> {
+    let duplicates = duplicate_names(spec.tests.iter().map(|t| t.name.as_str()));
+    if !duplicates.is_empty() {
+        let messages: Vec<_> = duplicates
+            .iter()
+            .map(|(name, count)| format!("test '{name}' is defined {count} times"))
+            .collect();
+        return Err(LoadError::Validation(messages.join("; ")));
+    }
+
     for test in &spec.tests {
         if test.steps.is_empty() {
             return Err(LoadError::Validation(format!(
@@ -100,6 +161,48 @@ fn validate_spec(spec: &TestSpec) -> Result<(), LoadError> {
                 test.name
             )));
         }
+        if let Some(name) = test
+            .use_fixtures
+            .iter()
+            .find(|name| !spec.fixtures.contains_key(*name))
+        {
+            return Err(LoadError::Validation(format!(
+                "test '{}': unknown fixture '{name}'",
+                test.name
+            )));
+        }
+        // An empty dimension would expand to no tests at all
+        if let Some(key) = test
+            .matrix
+            .iter()
+            .flatten()
+            .filter(|(_, values)| values.is_empty())
+            .map(|(key, _)| key)
+            .min()
+        {
+            return Err(LoadError::Validation(format!(
+                "test '{}': matrix dimension '{key}' has no values",
+                test.name
+            )));
+        }
+        if test.deterministic && test.steps.len() > 1 {
+            return Err(LoadError::Validation(format!(
+                "test '{}': deterministic requires a single-step test",
+                test.name
+            )));
+        }
+        let duplicates = duplicate_names(test.steps.iter().map(|s| s.name.as_str()));
+        if !duplicates.is_empty() {
+            let messages: Vec<_> = duplicates
+                .iter()
+                .map(|(name, count)| format!("step '{name}' is defined {count} times"))
+                .collect();
+            return Err(LoadError::Validation(format!(
+                "test '{}': {}",
+                test.name,
+                messages.join("; ")
+            )));
+        }
         for (i, step) in test.steps.iter().enumerate() {
             if step.name.is_empty() {
                 return Err(LoadError::Validation(format!(
@@ -107,11 +210,84 @@ fn validate_spec(spec: &TestSpec) -> Result<(), LoadError> {
                     test.name, i
                 )));
             }
+            if step.run.stdin.is_some() && step.run.stdin_file.is_some() {
+                return Err(LoadError::Validation(format!(
+                    "test '{}' step '{}': stdin and stdin_file are mutually exclusive",
+                    test.name, step.name
+                )));
+            }
+            for (index, sql) in step.expect.sql.iter().enumerate() {
+                validate_sql_expect(sql).map_err(|e| {
+                    LoadError::Validation(format!(
+                        "test '{}' step '{}' sql[{index}]: {e}",
+                        test.name, step.name
+                    ))
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that a SQL assertion sets at most one assertion type, that
+/// result checks are only used together with `query`, and that `row_count`
+/// names exactly one of `table` or `query`.
+fn validate_sql_expect(sql: &SqlExpect) -> Result<(), String> {
+    let kinds: Vec<&str> = [
+        ("query", sql.query.is_some()),
+        ("table_exists", sql.table_exists.is_some()),
+        ("table_not_exists", sql.table_not_exists.is_some()),
+        ("row_count", sql.row_count.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect();
+    if kinds.len() > 1 {
+        return Err(format!("{} are mutually exclusive", kinds.join(", ")));
+    }
+
+    if let Some(row_count) = &sql.row_count {
+        match (&row_count.table, &row_count.query) {
+            (Some(_), Some(_)) => {
+                return Err("row_count: table, query are mutually exclusive".to_string());
+            }
+            (None, None) => return Err("row_count requires table or query".to_string()),
+            _ => {}
         }
     }
+
+    if sql.query.is_none() {
+        let result_checks: Vec<&str> = [
+            ("returns", sql.returns.is_some()),
+            ("returns_empty", sql.returns_empty.is_some()),
+            ("returns_null", sql.returns_null.is_some()),
+            ("returns_one_row", sql.returns_one_row.is_some()),
+            ("returns_map", sql.returns_map.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect();
+        if !result_checks.is_empty() {
+            return Err(format!("{} requires query", result_checks.join(", ")));
+        }
+    }
+
     Ok(())
 }
 
+/// Names that occur more than once, with their counts, in order of first appearance.
+fn duplicate_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for name in names {
+        match counts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    counts.retain(|(_, count)| *count > 1);
+    counts
+}
+
 /// Load suite configuration from a directory.
 ///
 /// Looks for `bintest.yaml` in the given directory.
@@ -131,6 +307,11 @@ pub fn load_suite_config(dir: &Path) -> Result<Option<SuiteConfig>, LoadError> {
         config.resolved_binary = Some(resolve_binary_path(binary, &config_path)?);
     }
 
+    // Load env_file relative to config file location
+    if let Some(env_file) = &config.env_file {
+        merge_env_file(&mut config.env, env_file, &config_path)?;
+    }
+
     Ok(Some(config))
 }
 
@@ -155,9 +336,13 @@ fn collect_specs_recursive(
         let path = entry.path();
 
         if path.is_dir() {
+            // Hidden directories such as .bintest/ hold caches and sandboxes, not specs
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
             collect_specs_recursive(&path, specs)?;
         } else if let Some(ext) = path.extension().and_then(|e| e.to_str())
-            && (ext == "yaml" || ext == "yml" || ext == "toml")
+            && (ext == "yaml" || ext == "yml" || ext == "toml" || ext == "json")
         {
             // Skip suite config file
             if path.file_name().is_some_and(|f| f == SUITE_CONFIG_FILENAME) {
@@ -209,6 +394,57 @@ tests:
         assert!(matches!(result, Err(LoadError::Yaml(_))));
     }
 
+    #[test]
+    fn misspelled_expect_is_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("typo.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: test1
+    run:
+      cmd: echo
+    expct:
+      exit: 0
+"#,
+        )
+        .unwrap();
+
+        let err = load_spec(&path).unwrap_err();
+        assert!(matches!(err, LoadError::Yaml(_)));
+        assert!(err.to_string().contains("unknown field `expct`"), "{err}");
+    }
+
+    #[test]
+    fn misspelled_nested_field_is_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("typo.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: test1
+    steps:
+      - name: first
+        run:
+          cmd: echo
+        expect:
+          exit_code: 0
+"#,
+        )
+        .unwrap();
+
+        let err = load_spec(&path).unwrap_err();
+        assert!(matches!(err, LoadError::Yaml(_)));
+        assert!(
+            err.to_string().contains("unknown field `exit_code`"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn unsupported_format() {
         let dir = tempdir().unwrap();
@@ -256,16 +492,274 @@ exit = 0
         assert!(matches!(result, Err(LoadError::Toml(_))));
     }
 
+    #[test]
+    fn load_valid_json_spec() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.json");
+        std::fs::write(
+            &path,
+            r#"{
+  "version": 1,
+  "tests": [
+    {
+      "name": "test1",
+      "run": { "cmd": "echo" },
+      "expect": { "exit": 0 }
+    }
+  ]
+}"#,
+        )
+        .unwrap();
+
+        let spec = load_spec(&path).unwrap();
+        assert_eq!(spec.version, 1);
+        assert_eq!(spec.tests.len(), 1);
+        assert_eq!(spec.tests[0].name, "test1");
+    }
+
+    #[test]
+    fn load_invalid_json() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("bad.json");
+        std::fs::write(&path, r#"{"version": 1, "tests": ["#).unwrap();
+
+        let result = load_spec(&path);
+        assert!(matches!(result, Err(LoadError::Json(_))));
+    }
+
+    #[test]
+    fn reject_stdin_and_stdin_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: both_stdin
+    run:
+      cmd: cat
+      stdin: "inline"
+      stdin_file: input.bin
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.contains("mutually exclusive"))
+        );
+    }
+
+    #[test]
+    fn reject_deterministic_multi_step() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: two_steps
+    deterministic: true
+    steps:
+      - name: first
+        run: { cmd: "true" }
+      - name: second
+        run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.contains("single-step"))
+        );
+    }
+
+    #[test]
+    fn reject_duplicate_test_names() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: init
+    run: { cmd: "true" }
+  - name: build
+    run: { cmd: "true" }
+  - name: init
+    run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg == "test 'init' is defined 2 times"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn reject_unknown_fixture() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+fixtures:
+  config:
+    - write_file: { path: app.toml, contents: "" }
+tests:
+  - name: uses_missing
+    use_fixtures: [config, seed_db]
+    run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg == "test 'uses_missing': unknown fixture 'seed_db'"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn reject_empty_matrix_dimension() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: per_os
+    matrix:
+      os: []
+      arch: [x86_64]
+    run: { cmd: echo, args: ["${matrix.os}"] }
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg == "test 'per_os': matrix dimension 'os' has no values"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn reject_duplicate_step_names() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+tests:
+  - name: workflow
+    steps:
+      - name: build
+        run: { cmd: "true" }
+      - name: build
+        run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg == "test 'workflow': step 'build' is defined 2 times"),
+            "{result:?}"
+        );
+    }
+
+    fn load_sql_expect(sql: &str) -> Result<TestSpec, LoadError> {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+version: 1
+tests:
+  - name: check_db
+    run: {{ cmd: "true" }}
+    expect:
+      sql:
+        - {sql}
+"#
+            ),
+        )
+        .unwrap();
+        load_spec(&path)
+    }
+
+    #[test]
+    fn accept_single_sql_assertion() {
+        assert!(load_sql_expect(r#"{ query: "SELECT 1", returns: "1" }"#).is_ok());
+        assert!(load_sql_expect("{ table_exists: users }").is_ok());
+        assert!(load_sql_expect("{ row_count: { table: users, equals: 2 } }").is_ok());
+        assert!(
+            load_sql_expect(r#"{ row_count: { query: "SELECT * FROM users", equals: 2 } }"#)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn reject_conflicting_sql_assertions() {
+        let result = load_sql_expect(r#"{ table_exists: users, query: "SELECT 1" }"#);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg))
+                if msg == "test 'check_db' step 'run' sql[0]: query, table_exists are mutually exclusive"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn reject_row_count_without_exactly_one_target() {
+        let result =
+            load_sql_expect(r#"{ row_count: { table: users, query: "SELECT 1", equals: 1 } }"#);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg))
+                if msg == "test 'check_db' step 'run' sql[0]: row_count: table, query are mutually exclusive"),
+            "{result:?}"
+        );
+
+        let result = load_sql_expect("{ row_count: { equals: 1 } }");
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg))
+                if msg == "test 'check_db' step 'run' sql[0]: row_count requires table or query"),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn reject_sql_returns_without_query() {
+        let result = load_sql_expect(r#"{ table_exists: users, returns: "1" }"#);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.ends_with("returns requires query")),
+            "{result:?}"
+        );
+    }
+
     #[test]
     fn find_specs_in_directory() {
         let dir = tempdir().unwrap();
         std::fs::write(dir.path().join("a.yaml"), "").unwrap();
         std::fs::write(dir.path().join("b.yml"), "").unwrap();
         std::fs::write(dir.path().join("c.toml"), "").unwrap();
-        std::fs::write(dir.path().join("d.txt"), "").unwrap();
+        std::fs::write(dir.path().join("d.json"), "").unwrap();
+        std::fs::write(dir.path().join("e.txt"), "").unwrap();
 
         let specs = find_specs(dir.path()).unwrap();
-        assert_eq!(specs.len(), 3);
+        assert_eq!(specs.len(), 4);
     }
 
     #[test]
@@ -279,6 +773,17 @@ exit = 0
         assert!(specs[0].file_name().unwrap() != "bintest.yaml");
     }
 
+    #[test]
+    fn find_specs_skips_hidden_directories() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "").unwrap();
+        std::fs::create_dir_all(dir.path().join(".bintest")).unwrap();
+        std::fs::write(dir.path().join(".bintest/last-run.json"), "{}").unwrap();
+
+        let specs = find_specs(dir.path()).unwrap();
+        assert_eq!(specs, [dir.path().join("a.yaml")]);
+    }
+
     #[test]
     fn load_suite_config_not_found() {
         let dir = tempdir().unwrap();
@@ -306,6 +811,71 @@ env:
         assert_eq!(config.env.get("MY_VAR"), Some(&"my_value".to_string()));
     }
 
+    #[test]
+    fn load_suite_config_env_file() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(".env"), "FROM_FILE=file\nOVERRIDDEN=file\n").unwrap();
+        std::fs::write(
+            dir.path().join("bintest.yaml"),
+            r#"
+version: 1
+env_file: .env
+env:
+  OVERRIDDEN: config
+"#,
+        )
+        .unwrap();
+
+        let config = load_suite_config(dir.path()).unwrap().unwrap();
+        assert_eq!(config.env["FROM_FILE"], "file");
+        assert_eq!(config.env["OVERRIDDEN"], "config");
+    }
+
+    #[test]
+    fn load_spec_sandbox_env_file() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("fixtures")).unwrap();
+        std::fs::write(dir.path().join("fixtures/test.env"), "SECRET=hunter2\n").unwrap();
+        let path = dir.path().join("spec.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+sandbox:
+  env_file: fixtures/test.env
+tests:
+  - name: t
+    run:
+      cmd: "true"
+"#,
+        )
+        .unwrap();
+
+        let spec = load_spec(&path).unwrap();
+        assert_eq!(spec.sandbox.env["SECRET"], "hunter2");
+    }
+
+    #[test]
+    fn load_spec_missing_env_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spec.yaml");
+        std::fs::write(
+            &path,
+            r#"
+version: 1
+sandbox:
+  env_file: missing.env
+tests: []
+"#,
+        )
+        .unwrap();
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.contains("missing.env"))
+        );
+    }
+
     #[test]
     fn load_suite_config_invalid() {
         let dir = tempdir().unwrap();
@@ -336,7 +906,6 @@ sandbox_dir: local
     #[test]
     fn load_suite_config_with_sandbox_dir_path() {
         use crate::schema::SandboxDir;
-        use std::path::PathBuf;
 
         let dir = tempdir().unwrap();
         std::fs::write(
@@ -351,7 +920,7 @@ sandbox_dir: /tmp/custom-dir
         let config = load_suite_config(dir.path()).unwrap().unwrap();
         assert!(matches!(
             config.sandbox_dir,
-            Some(SandboxDir::Path(p)) if p == PathBuf::from("/tmp/custom-dir")
+            Some(SandboxDir::Path(p)) if p == std::path::Path::new("/tmp/custom-dir")
         ));
     }
 }
@@ -390,4 +959,57 @@ tests:
         );
         println!("resolved_binary: {:?}", spec.resolved_binary);
     }
+
+    fn spec_with_binary(dir: &Path, binary: &str) -> std::path::PathBuf {
+        let path = dir.join("test.yaml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+version: 1
+binary: {binary}
+tests:
+  - name: test1
+    run:
+      cmd: "${{BINARY}}"
+"#
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_binary_must_be_a_file() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("build")).unwrap();
+        let path = spec_with_binary(dir.path(), "build");
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.contains("is not a file")),
+            "{result:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_binary_must_be_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let binary = dir.path().join("app");
+        std::fs::write(&binary, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let path = spec_with_binary(dir.path(), "app");
+
+        let result = load_spec(&path);
+        assert!(
+            matches!(result, Err(LoadError::Validation(ref msg)) if msg.contains("not executable")),
+            "{result:?}"
+        );
+
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(load_spec(&path).is_ok());
+    }
 }
@@ -1,14 +1,15 @@
-mod database;
-mod env;
-mod loader;
-mod runner;
-mod schema;
+mod color;
+mod last_run;
 
+use bintest::{env, loader, record, runner, schema};
 use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write as _;
 use std::fs;
-use std::path::PathBuf;
-use std::thread;
+use std::io::{IsTerminal, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 #[derive(Clone, Copy, Default, ValueEnum)]
@@ -20,6 +21,10 @@ enum OutputFormat {
     Json,
     /// JUnit XML output for CI systems
     Junit,
+    /// One JSON object per test, written as each spec file finishes, then a
+    /// summary object
+    #[value(alias = "jsonl")]
+    JsonLines,
 }
 
 #[derive(Parser)]
@@ -39,7 +44,8 @@ BINARY UNDER TEST:
           cmd: \"${BINARY}\"   # automatically set from binary field
           args: [\"--help\"]
 
-  The binary path is resolved relative to the spec file and validated at load time.
+  The binary path is resolved relative to the spec file and checked at load time
+  to exist and be executable.
   File-level 'binary' overrides suite-level 'binary' from bintest.yaml.
 ")]
 struct Cli {
@@ -47,7 +53,9 @@ struct Cli {
     command: Command,
 }
 
+// Parsed once per process, so the size difference between variants is acceptable.
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     /// Execute test specs
     Run {
@@ -56,9 +64,29 @@ enum Command {
         /// Output format
         #[arg(short, long, default_value = "human")]
         output: OutputFormat,
+        /// Color the human output: auto (when stdout is a terminal and NO_COLOR
+        /// is not set), always, or never.
+        #[arg(long, value_name = "WHEN", default_value = "auto")]
+        color: color::ColorChoice,
+        /// JSON output schema version: 1 (tests only), 2 (adds per-step
+        /// results), 3 (adds test ids), or 4 (adds exit codes and signals).
+        /// Defaults to the latest.
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = JSON_SCHEMA_VERSION,
+            value_parser = clap::value_parser!(u8).range(1..=JSON_SCHEMA_VERSION as i64)
+        )]
+        json_version: u8,
         /// Filter tests by name pattern (substring match)
         #[arg(short, long)]
         filter: Option<String>,
+        /// Run only tests with this tag (repeatable; a test needs any one of them)
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Skip tests with this tag (repeatable)
+        #[arg(long = "exclude-tag", value_name = "TAG")]
+        exclude_tags: Vec<String>,
         /// Show verbose output (command details, full diffs)
         #[arg(short, long)]
         verbose: bool,
@@ -66,6 +94,102 @@ enum Command {
         /// Use "local" for .bintest/<timestamp>/, or specify a path.
         #[arg(long)]
         sandbox_dir: Option<String>,
+        /// Keep the temporary sandbox of any file with a failing test and print
+        /// its path, even if the file or suite sets `preserve: never`.
+        #[arg(long)]
+        keep_on_failure: bool,
+        /// Write the formatted results to a file instead of stdout.
+        /// A short summary is still printed to the terminal.
+        #[arg(long)]
+        output_file: Option<PathBuf>,
+        /// Also write the failing tests (file, name, failures, failed_step) as a
+        /// JSON array to this file, whatever the --output format.
+        #[arg(long, value_name = "FILE")]
+        failures_json: Option<PathBuf>,
+        /// SQL file run against every configured database before each file's setup.
+        /// It runs once per spec file, so a database shared by several files is
+        /// seeded once for each of them. Statements are split on ';'.
+        #[arg(long)]
+        seed_db: Option<PathBuf>,
+        /// Run only suite/file/test setup steps (no commands or assertions).
+        /// Combine with --sandbox-dir to inspect the materialized fixtures.
+        #[arg(long, conflicts_with = "teardown_only")]
+        setup_only: bool,
+        /// Run only suite/file/test teardown steps (no commands or assertions).
+        #[arg(long)]
+        teardown_only: bool,
+        /// Print the execution plan (parallel/serial scheduling, timeouts,
+        /// databases) without running anything.
+        #[arg(long)]
+        plan: bool,
+        /// Run built-in smoke tests (`--help` and `--version` exit 0) against the
+        /// configured binary instead of spec files. The binary comes from
+        /// bintest.yaml, or from the spec file given as PATH.
+        #[arg(long, conflicts_with = "plan")]
+        smoke: bool,
+        /// Report which host environment variables leak into each spec's test
+        /// commands (not set by the spec but visible to them), without running tests.
+        #[arg(long, conflicts_with_all = ["plan", "smoke"])]
+        check_isolation: bool,
+        /// Print each spec's effective configuration (suite and file settings
+        /// merged, and every test's resolved timeout) as JSON, without running tests.
+        #[arg(long, conflicts_with_all = ["plan", "smoke", "check_isolation"])]
+        explain: bool,
+        /// Directory where each run's JSON results are saved as a timestamped file.
+        #[arg(long)]
+        results_dir: Option<PathBuf>,
+        /// Number of most recent runs to keep in --results-dir (default: all).
+        #[arg(
+            long,
+            requires = "results_dir",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        keep_runs: Option<usize>,
+        /// Shell command run (via `sh -c`) after the run when any test failed.
+        /// Receives BINTEST_FAILED_COUNT, BINTEST_PASSED_COUNT, and
+        /// BINTEST_SKIPPED_COUNT in its environment.
+        #[arg(long, value_name = "CMD")]
+        on_failure_command: Option<String>,
+        /// Record every step's command and output to a JSON file, for
+        /// bootstrapping golden tests with --replay.
+        #[arg(long, value_name = "FILE")]
+        record: Option<PathBuf>,
+        /// Replace each step's exit/stdout/stderr expectations with the outputs
+        /// recorded by --record, asserting the commands still behave the same.
+        #[arg(long, value_name = "FILE")]
+        replay: Option<PathBuf>,
+        /// Start each file's parallel tests in a random order. Only the spawn order
+        /// changes: the tests still run concurrently, and serial tests keep their
+        /// declared order. The seed is printed so the order can be reproduced.
+        #[arg(long)]
+        shuffle: bool,
+        /// Seed for --shuffle, to reproduce a previous order (implies --shuffle).
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Connect test commands to the terminal (stdin, stdout, stderr) instead of
+        /// capturing their output, for debugging hung tests. Runs every file and
+        /// test serially; stdout/stderr assertions are skipped.
+        #[arg(long, conflicts_with_all = ["plan", "check_isolation", "max_parallel_files"])]
+        no_capture: bool,
+        /// After the summary of a human run, list the N slowest tests.
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        slowest: Option<usize>,
+        /// Re-run only the tests that failed in the previous run (recorded in
+        /// .bintest/last-run.json). Combines with --filter and --tag.
+        #[arg(long, conflicts_with_all = ["plan", "smoke", "check_isolation"])]
+        only_failures: bool,
+        /// Maximum number of spec files run at the same time (default: all).
+        /// Tests within a file are scheduled independently of this limit.
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        max_parallel_files: Option<usize>,
     },
     /// Validate test specs without running them
     Validate {
@@ -80,6 +204,15 @@ enum Command {
     },
     /// Output the spec schema (for AI consumers)
     Schema,
+    /// Delete persisted sandboxes (`.bintest/`, or the suite `sandbox_dir`)
+    Clean {
+        /// Test root whose bintest.yaml names the sandbox directory
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Only remove sandboxes older than this age, e.g. 30m, 12h, 7d.
+        #[arg(long, value_name = "DURATION", value_parser = parse_age)]
+        older_than: Option<Duration>,
+    },
 }
 
 fn main() {
@@ -89,21 +222,55 @@ fn main() {
         Command::Run {
             path,
             output,
+            color,
+            json_version,
             filter,
+            tags,
+            exclude_tags,
             verbose,
             sandbox_dir,
+            keep_on_failure,
+            output_file,
+            failures_json,
+            seed_db,
+            setup_only,
+            teardown_only,
+            plan,
+            smoke,
+            check_isolation,
+            explain,
+            results_dir,
+            keep_runs,
+            on_failure_command,
+            max_parallel_files,
+            slowest,
+            only_failures,
+            no_capture,
+            record,
+            replay,
+            shuffle,
+            seed,
         } => {
             // Show filter info in verbose mode
             if verbose && let Some(ref f) = filter {
                 eprintln!("Filtering tests by: {f:?}");
             }
+            if verbose && !tags.is_empty() {
+                eprintln!("Including tags: {tags:?}");
+            }
+            if verbose && !exclude_tags.is_empty() {
+                eprintln!("Excluding tags: {exclude_tags:?}");
+            }
+            let mut test_filter = runner::TestFilter {
+                name: filter,
+                tags,
+                exclude_tags,
+                names: None,
+                only: false,
+            };
 
             // Determine the test root directory for suite config
-            let test_root = if path.is_file() {
-                path.parent().unwrap_or(&path)
-            } else {
-                &path
-            };
+            let test_root = bintest::test_root(&path);
 
             // Load suite config if present
             let mut suite_config = match loader::load_suite_config(test_root) {
@@ -126,101 +293,287 @@ fn main() {
                 }
             }
 
-            let spec_paths = match loader::find_specs(&path) {
-                Ok(s) => s,
+            // CLI keep_on_failure overrides the suite and file preserve policies
+            if keep_on_failure {
+                suite_config
+                    .get_or_insert_with(Default::default)
+                    .keep_on_failure = true;
+            }
+
+            // CLI shuffle randomizes parallel test order with a reproducible seed
+            if shuffle || seed.is_some() {
+                let seed = seed.unwrap_or_else(|| {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_nanos() as u64)
+                        .unwrap_or_default()
+                });
+                eprintln!("Shuffling tests with seed {seed} (reproduce with --seed {seed})");
+                suite_config
+                    .get_or_insert_with(Default::default)
+                    .shuffle_seed = Some(seed);
+            }
+
+            // CLI no_capture streams command output to the terminal
+            if no_capture {
+                eprintln!("--no-capture: running serially; stdout/stderr assertions are skipped");
+                suite_config.get_or_insert_with(Default::default).no_capture = true;
+            }
+
+            // CLI record captures step outputs into the results
+            if record.is_some() {
+                suite_config.get_or_insert_with(Default::default).record = true;
+            }
+
+            // CLI replay turns recorded outputs into expectations
+            let replay = replay.map(|replay_path| match record::Recording::load(&replay_path) {
+                Ok(recording) => recording,
                 Err(e) => {
-                    eprintln!("Error finding specs: {e}");
+                    eprintln!("Error loading recording {}: {e}", replay_path.display());
                     std::process::exit(1);
                 }
+            });
+
+            // CLI seed_db is applied to every database of every file
+            if let Some(ref seed_path) = seed_db {
+                let seed_sql = match fs::read_to_string(seed_path) {
+                    Ok(sql) => sql,
+                    Err(e) => {
+                        eprintln!("Error reading seed file {}: {e}", seed_path.display());
+                        std::process::exit(1);
+                    }
+                };
+                suite_config.get_or_insert_with(Default::default).seed_sql = Some(seed_sql);
+            }
+
+            // CLI fixture flags restrict which phases run
+            let fixture_mode = if setup_only {
+                schema::FixtureMode::SetupOnly
+            } else if teardown_only {
+                schema::FixtureMode::TeardownOnly
+            } else {
+                schema::FixtureMode::Full
+            };
+            if fixture_mode != schema::FixtureMode::Full {
+                suite_config
+                    .get_or_insert_with(Default::default)
+                    .fixture_mode = fixture_mode;
+            }
+
+            // --smoke replaces spec discovery with generated tests for the binary
+            let smoke_spec = if smoke {
+                match smoke_binary(&path, suite_config.as_ref()) {
+                    Ok(binary) => Some(build_smoke_spec(binary)),
+                    Err(e) => {
+                        eprintln!("Error: {e}");
+                        std::process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+
+            let mut spec_paths = if smoke_spec.is_some() {
+                vec![PathBuf::from(SMOKE_SPEC_NAME)]
+            } else {
+                match loader::find_specs(&path) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("Error finding specs: {e}");
+                        std::process::exit(1);
+                    }
+                }
             };
+            // Files a previous run wrote under the test root are not specs
+            let outputs: Vec<PathBuf> = [&output_file, &failures_json, &record, &results_dir]
+                .into_iter()
+                .flatten()
+                .filter_map(|p| fs::canonicalize(p).ok())
+                .collect();
+            spec_paths.retain(|p| {
+                fs::canonicalize(p).map_or(true, |p| !outputs.iter().any(|out| p.starts_with(out)))
+            });
 
             if spec_paths.is_empty() {
                 eprintln!("No spec files found at: {}", path.display());
                 std::process::exit(1);
             }
 
-            // Run suite-level setup if configured
-            if let Some(ref config) = suite_config
-                && let Err(e) = runner::run_suite_setup(config)
-            {
-                eprintln!("Suite setup failed: {e}");
-                std::process::exit(1);
-            }
-
-            // Determine if we should run files serially
-            let run_serial = suite_config.as_ref().is_some_and(|c| c.serial);
+            // --only-failures narrows the run to what failed last time
+            let reruns = if only_failures {
+                let cache = match last_run::LastRun::load(Path::new(last_run::LAST_RUN_PATH)) {
+                    Ok(cache) => cache,
+                    Err(e) => {
+                        eprintln!(
+                            "Error reading {}: {e}\nRun `bintest run` once without --only-failures first.",
+                            last_run::LAST_RUN_PATH
+                        );
+                        std::process::exit(1);
+                    }
+                };
+                let reruns: HashMap<PathBuf, last_run::Rerun> = spec_paths
+                    .iter()
+                    .filter_map(|p| cache.rerun_for(p).map(|rerun| (p.clone(), rerun)))
+                    .collect();
+                if reruns.is_empty() {
+                    println!("No failures from the previous run to re-run");
+                    return;
+                }
+                spec_paths.retain(|p| reruns.contains_key(p));
+                reruns
+            } else {
+                HashMap::new()
+            };
 
             // Load all specs first, tracking any load failures
-            let specs_with_paths: Vec<_> = spec_paths
+            let mut specs_with_paths = match smoke_spec {
+                Some(spec) => vec![(spec_paths[0].clone(), Ok(spec))],
+                None => bintest::load_specs(&spec_paths),
+            };
+            if let Some(ref recording) = replay {
+                for (path, spec) in &mut specs_with_paths {
+                    if let (Ok(spec), Some(runs)) = (spec, recording.runs_for(path)) {
+                        spec.replay = runs.to_vec();
+                    }
+                }
+            }
+            let spec_versions: HashMap<PathBuf, u32> = specs_with_paths
                 .iter()
-                .map(|p| (p.clone(), loader::load_spec(p)))
+                .filter_map(|(path, spec)| Some((path.clone(), spec.as_ref().ok()?.version)))
                 .collect();
 
-            // Track total execution time
-            let run_start = std::time::Instant::now();
+            // A test marked `only` anywhere in the run focuses every file on those tests
+            test_filter.only = bintest::has_only_tests(&specs_with_paths);
+            if verbose && test_filter.only {
+                eprintln!("Running only tests marked `only: true`");
+            }
 
-            // Run specs (parallel by default, serial if configured)
-            let filter_ref = filter.as_deref();
-            let file_results: Vec<(PathBuf, Result<runner::SpecResult, String>)> = if run_serial {
-                // Serial execution
-                specs_with_paths
-                    .into_iter()
-                    .map(|(path, spec_result)| {
-                        let result = match spec_result {
-                            Ok(spec) => Ok(runner::run_spec_filtered(
-                                &spec,
-                                suite_config.as_ref(),
-                                filter_ref,
-                            )),
-                            Err(e) => Err(e.to_string()),
-                        };
-                        (path, result)
-                    })
-                    .collect()
-            } else {
-                // Parallel execution (default)
-                thread::scope(|s| {
-                    let handles: Vec<_> = specs_with_paths
-                        .into_iter()
-                        .map(|(path, spec_result)| {
-                            let suite_config_ref = suite_config.as_ref();
-                            s.spawn(move || {
-                                let result = match spec_result {
-                                    Ok(spec) => Ok(runner::run_spec_filtered(
-                                        &spec,
-                                        suite_config_ref,
-                                        filter_ref,
-                                    )),
-                                    Err(e) => Err(e.to_string()),
-                                };
-                                (path, result)
-                            })
-                        })
-                        .collect();
-
-                    handles
-                        .into_iter()
-                        .map(|h| h.join().expect("Spec thread panicked"))
-                        .collect()
-                })
+            // Print the execution plan instead of running
+            if plan {
+                let (plan_text, load_errors) =
+                    format_plan(&specs_with_paths, suite_config.as_ref(), &test_filter);
+                print!("{plan_text}");
+                if load_errors > 0 {
+                    std::process::exit(1);
+                }
+                return;
+            }
+
+            // Report host env leakage instead of running
+            if check_isolation {
+                if !check_env_isolation(&spec_paths, suite_config.as_ref()) {
+                    std::process::exit(1);
+                }
+                return;
+            }
+
+            // Print the merged configuration instead of running
+            if explain {
+                let (explained, load_errors) = explain_specs(&spec_paths, suite_config.as_ref());
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&explained).expect("JSON serialization")
+                );
+                if load_errors > 0 {
+                    std::process::exit(1);
+                }
+                return;
+            }
+
+            // Suite setup and teardown share one sandbox, exposed to every test
+            let suite = match bintest::Suite::new(suite_config) {
+                Ok(suite) => suite,
+                Err(e) => {
+                    eprintln!("Error: {e}");
+                    std::process::exit(1);
+                }
             };
+            if let Err(e) = suite.setup() {
+                eprintln!("Suite setup failed: {e}");
+                drop(suite);
+                std::process::exit(1);
+            }
 
-            // Sort results by original path order for deterministic output
-            let mut sorted_results: Vec<_> = file_results;
-            sorted_results.sort_by(|a, b| {
-                spec_paths
-                    .iter()
-                    .position(|p| p == &a.0)
-                    .cmp(&spec_paths.iter().position(|p| p == &b.0))
-            });
+            // Track total execution time
+            let run_start = std::time::Instant::now();
+
+            // Live "X/Y specs complete" line, replaced by the report once done
+            let total_specs = specs_with_paths.len();
+            let completed = AtomicUsize::new(0);
+            let show_progress = matches!(output, OutputFormat::Human)
+                && !verbose
+                && !no_capture
+                && std::io::stdout().is_terminal();
+            if show_progress {
+                print_progress(0, total_specs);
+            }
+            // JSON Lines results are written as each file finishes, not in the report
+            let json_lines: Option<Mutex<Box<dyn std::io::Write + Send>>> =
+                match (&output, &output_file) {
+                    (OutputFormat::JsonLines, Some(file)) => match fs::File::create(file) {
+                        Ok(file) => Some(Mutex::new(Box::new(file))),
+                        Err(e) => {
+                            eprintln!("Error writing output file {}: {e}", file.display());
+                            std::process::exit(1);
+                        }
+                    },
+                    (OutputFormat::JsonLines, None) => {
+                        Some(Mutex::new(Box::new(std::io::stdout())))
+                    }
+                    _ => None,
+                };
+            // Run specs (parallel by default, serial if configured); results
+            // come back in discovery order
+            let file_results = bintest::run_specs(
+                specs_with_paths,
+                suite.config(),
+                max_parallel_files,
+                |path| match reruns.get(path) {
+                    Some(last_run::Rerun::Tests(names)) => runner::TestFilter {
+                        names: Some(names.clone()),
+                        ..test_filter.clone()
+                    },
+                    _ => test_filter.clone(),
+                },
+                |file| {
+                    if let Some(sink) = &json_lines {
+                        write_json_lines(sink, file);
+                    }
+                    let finished = completed.fetch_add(1, Ordering::Relaxed) + 1;
+                    if show_progress {
+                        print_progress(finished, total_specs);
+                    }
+                },
+            );
+            if show_progress {
+                // Erase the progress line
+                print!("\r\x1b[2K");
+            }
 
             let mut json_results = Vec::new();
             let mut junit_results = Vec::new();
+            let mut recorded_files = Vec::new();
             let mut total_passed = 0;
             let mut total_failed = 0;
             let mut total_skipped = 0;
+            // Failing tests for --failures-json
+            let mut failed_tests = Vec::new();
+            // Test durations for --slowest: (duration, file, test name)
+            let mut timings: Vec<(Duration, String, String)> = Vec::new();
+            // Failing tests for the next --only-failures
+            let mut last_run = last_run::LastRun::default();
+            // Formatted output for the chosen format (printed or written to --output-file)
+            let mut report = String::new();
+            let palette = color::Palette::new(color.enabled(
+                output_file.is_none() && std::io::stdout().is_terminal(),
+                std::env::var("NO_COLOR").ok().as_deref(),
+            ));
 
-            for (spec_path, result) in sorted_results {
+            for bintest::FileReport {
+                path: spec_path,
+                result,
+            } in file_results
+            {
                 match result {
                     Err(e) => {
                         if matches!(output, OutputFormat::Human) {
@@ -232,71 +585,154 @@ fn main() {
                                 file: spec_path.display().to_string(),
                                 tests: vec![runner::TestResult {
                                     name: "<load>".to_string(),
+                                    description: None,
                                     passed: false,
                                     skipped: false,
                                     skip_reason: None,
                                     duration: Duration::ZERO,
                                     failures: vec![format!("Failed to load spec: {e}")],
                                     failed_step: None,
+                                    exit_code: None,
+                                    signal: None,
                                     fs_diff: None,
+                                    steps: vec![],
                                 }],
                                 total_time: Duration::ZERO,
                             });
                         }
+                        failed_tests.push(serde_json::json!({
+                            "file": spec_path.display().to_string(),
+                            "name": "<load>",
+                            "failures": [format!("Failed to load spec: {e}")],
+                            "failed_step": null,
+                        }));
+                        last_run.add(&spec_path, "<load>");
                         total_failed += 1;
                     }
-                    Ok(spec_result) => {
+                    Ok(mut spec_result) => {
+                        let spec_version = spec_versions[&spec_path];
+                        if record.is_some() {
+                            recorded_files.push(record::RecordedFile {
+                                path: spec_path.clone(),
+                                runs: std::mem::take(&mut spec_result.recordings),
+                            });
+                        }
                         let file_time: Duration =
                             spec_result.tests.iter().map(|t| t.duration).sum();
 
                         for test in &spec_result.tests {
+                            if !test.skipped {
+                                timings.push((
+                                    test.duration,
+                                    spec_path.display().to_string(),
+                                    test.name.clone(),
+                                ));
+                            }
                             if test.skipped {
                                 total_skipped += 1;
                             } else if test.passed {
                                 total_passed += 1;
                             } else {
                                 total_failed += 1;
+                                failed_tests.push(serde_json::json!({
+                                    "file": spec_path.display().to_string(),
+                                    "name": test.name,
+                                    "failures": test.failures,
+                                    "failed_step": test.failed_step,
+                                }));
+                                last_run.add(&spec_path, &test.name);
                             }
                         }
 
+                        // JSON results feed both --output json and --results-dir
+                        if matches!(output, OutputFormat::Json) || results_dir.is_some() {
+                            let file = spec_path.display().to_string();
+                            let mut file_json = serde_json::json!({
+                                "file": file,
+                                "tests": tests_json(
+                                    &spec_result.tests,
+                                    &file,
+                                    &spec_id(&spec_path, test_root),
+                                    json_version,
+                                ),
+                            });
+                            if json_version >= 3 {
+                                file_json["version"] = spec_version.into();
+                            }
+                            json_results.push(file_json);
+                        }
+
                         match output {
                             OutputFormat::Human => {
-                                println!("\n{}", spec_path.display());
+                                let _ = writeln!(report, "\n{}", spec_path.display());
                                 for test in &spec_result.tests {
                                     if test.skipped {
-                                        print!("  ⊘ {} (skipped)", test.name);
+                                        let _ = write!(
+                                            report,
+                                            "  {} {} {}",
+                                            palette.yellow("⊘"),
+                                            test.name,
+                                            palette.dim("(skipped)")
+                                        );
                                         if let Some(ref reason) = test.skip_reason {
-                                            print!(" - {reason}");
+                                            let _ = write!(report, " - {reason}");
                                         }
-                                        println!();
+                                        report.push('\n');
                                     } else if test.passed {
-                                        println!("  ✓ {} ({:.2?})", test.name, test.duration);
+                                        let _ = writeln!(
+                                            report,
+                                            "  {} {} {}",
+                                            palette.green("✓"),
+                                            test.name,
+                                            palette.dim(&format!("({:.2?})", test.duration))
+                                        );
                                     } else {
-                                        println!("  ✗ {} ({:.2?})", test.name, test.duration);
-                                        for failure in &test.failures {
-                                            println!("    {failure}");
-                                        }
+                                        let _ = writeln!(
+                                            report,
+                                            "  {} {} {}",
+                                            palette.red("✗"),
+                                            test.name,
+                                            palette.dim(&format!("({:.2?})", test.duration))
+                                        );
+                                    }
+                                    if verbose {
+                                        write_descriptions(&mut report, test, palette);
+                                    }
+                                    for failure in &test.failures {
+                                        let _ = writeln!(report, "    {failure}");
                                     }
                                     // Show filesystem diff if captured
                                     if let Some(ref diff) = test.fs_diff {
                                         if verbose {
                                             // Verbose mode: show full file paths
                                             if !diff.added.is_empty() {
-                                                println!("    fs added:");
+                                                let _ = writeln!(report, "    fs added:");
                                                 for path in &diff.added {
-                                                    println!("      + {}", path.display());
+                                                    let _ = writeln!(
+                                                        report,
+                                                        "      + {}",
+                                                        path.display()
+                                                    );
                                                 }
                                             }
                                             if !diff.removed.is_empty() {
-                                                println!("    fs removed:");
+                                                let _ = writeln!(report, "    fs removed:");
                                                 for path in &diff.removed {
-                                                    println!("      - {}", path.display());
+                                                    let _ = writeln!(
+                                                        report,
+                                                        "      - {}",
+                                                        path.display()
+                                                    );
                                                 }
                                             }
                                             if !diff.modified.is_empty() {
-                                                println!("    fs modified:");
+                                                let _ = writeln!(report, "    fs modified:");
                                                 for path in &diff.modified {
-                                                    println!("      ~ {}", path.display());
+                                                    let _ = writeln!(
+                                                        report,
+                                                        "      ~ {}",
+                                                        path.display()
+                                                    );
                                                 }
                                             }
                                         } else {
@@ -319,18 +755,18 @@ fn main() {
                                                 ));
                                             }
                                             if !diff_parts.is_empty() {
-                                                println!("    fs: {}", diff_parts.join(", "));
+                                                let _ = writeln!(
+                                                    report,
+                                                    "    fs: {}",
+                                                    diff_parts.join(", ")
+                                                );
                                             }
                                         }
                                     }
                                 }
                             }
-                            OutputFormat::Json => {
-                                json_results.push(serde_json::json!({
-                                    "file": spec_path.display().to_string(),
-                                    "tests": spec_result.tests,
-                                }));
-                            }
+                            // Collected above, or already written
+                            OutputFormat::Json | OutputFormat::JsonLines => {}
                             OutputFormat::Junit => {
                                 junit_results.push(JunitFileResult {
                                     file: spec_path.display().to_string(),
@@ -343,47 +779,154 @@ fn main() {
                 }
             }
 
-            // Run suite-level teardown if configured (always runs)
-            if let Some(ref config) = suite_config
-                && let Err(e) = runner::run_suite_teardown(config)
-            {
+            // Run suite-level teardown if configured (always runs, unless only running setup)
+            if let Err(e) = suite.teardown() {
                 if matches!(output, OutputFormat::Human) {
                     eprintln!("Suite teardown failed: {e}");
                 }
+                if let Some(sink) = &json_lines {
+                    write_json_line(
+                        sink,
+                        serde_json::json!({
+                            "type": "test",
+                            "file": test_root.join(loader::SUITE_CONFIG_FILENAME).display().to_string(),
+                            "name": "<suite teardown>",
+                            "passed": false,
+                            "skipped": false,
+                            "duration": 0.0,
+                            "failures": [format!("Suite teardown failed: {e}")],
+                            "exit_code": null,
+                            "signal": null,
+                        }),
+                    );
+                }
+                failed_tests.push(serde_json::json!({
+                    "file": test_root.join(loader::SUITE_CONFIG_FILENAME).display().to_string(),
+                    "name": "<suite teardown>",
+                    "failures": [format!("Suite teardown failed: {e}")],
+                    "failed_step": null,
+                }));
                 total_failed += 1;
             }
+            // Remove the suite sandbox now; the process may exit without unwinding
+            drop(suite);
 
             let total_time = run_start.elapsed();
 
+            let mut summary_parts = vec![
+                format!("{total_passed} passed"),
+                format!("{total_failed} failed"),
+            ];
+            if total_skipped > 0 {
+                summary_parts.push(format!("{total_skipped} skipped"));
+            }
+            let summary = summary_parts.join(", ");
+
+            let json_report = serde_json::json!({
+                "schema_version": json_version,
+                "passed": total_passed,
+                "failed": total_failed,
+                "skipped": total_skipped,
+                "results": json_results,
+            });
+            let json_text =
+                serde_json::to_string_pretty(&json_report).expect("Failed to serialize");
+
+            if let Some(ref dir) = results_dir {
+                match save_run_results(dir, &json_text, keep_runs) {
+                    Ok(saved) => {
+                        if verbose {
+                            eprintln!("Run results saved to: {}", saved.display());
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error saving results to {}: {e}", dir.display());
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            if !smoke && let Err(e) = last_run.save(Path::new(last_run::LAST_RUN_PATH)) {
+                eprintln!("Warning: could not save {}: {e}", last_run::LAST_RUN_PATH);
+            }
+
+            if let Some(ref path) = failures_json {
+                let text =
+                    serde_json::to_string_pretty(&failed_tests).expect("Failed to serialize");
+                if let Err(e) = fs::write(path, text) {
+                    eprintln!("Error writing failures file {}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            }
+
+            if let Some(ref record_path) = record {
+                match record::Recording::new(recorded_files).save(record_path) {
+                    Ok(()) => {
+                        if verbose {
+                            eprintln!("Recording saved to: {}", record_path.display());
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error saving recording to {}: {e}", record_path.display());
+                        std::process::exit(1);
+                    }
+                }
+            }
+
             match output {
                 OutputFormat::Human => {
-                    let mut summary_parts = vec![
-                        format!("{total_passed} passed"),
-                        format!("{total_failed} failed"),
-                    ];
-                    if total_skipped > 0 {
-                        summary_parts.push(format!("{total_skipped} skipped"));
+                    let _ = writeln!(report, "\n{summary}");
+                    if let Some(n) = slowest
+                        && !timings.is_empty()
+                    {
+                        timings.sort_by_key(|t| std::cmp::Reverse(t.0));
+                        let _ = writeln!(report, "\nSlowest tests:");
+                        for (duration, file, name) in timings.iter().take(n) {
+                            let _ = writeln!(report, "  {duration:.2?} {file}::{name}");
+                        }
                     }
-                    println!("\n{}", summary_parts.join(", "));
                 }
                 OutputFormat::Json => {
-                    let output = serde_json::json!({
-                        "passed": total_passed,
-                        "failed": total_failed,
-                        "skipped": total_skipped,
-                        "results": json_results,
-                    });
-                    println!(
-                        "{}",
-                        serde_json::to_string_pretty(&output).expect("Failed to serialize")
-                    );
+                    let _ = writeln!(report, "{json_text}");
                 }
                 OutputFormat::Junit => {
-                    print!("{}", format_junit_xml(&junit_results, total_time));
+                    report.push_str(&format_junit_xml(&junit_results, total_time));
+                }
+                OutputFormat::JsonLines => {
+                    if let Some(sink) = &json_lines {
+                        write_json_line(
+                            sink,
+                            serde_json::json!({
+                                "type": "summary",
+                                "passed": total_passed,
+                                "failed": total_failed,
+                                "skipped": total_skipped,
+                                "duration": total_time.as_secs_f64(),
+                            }),
+                        );
+                    }
+                }
+            }
+
+            match output_file {
+                Some(ref file) => {
+                    // JSON Lines output was written to the file as it streamed
+                    if !matches!(output, OutputFormat::JsonLines)
+                        && let Err(e) = fs::write(file, &report)
+                    {
+                        eprintln!("Error writing output file {}: {e}", file.display());
+                        std::process::exit(1);
+                    }
+                    println!("{summary}");
+                    println!("Results written to: {}", file.display());
                 }
+                None => print!("{report}"),
             }
 
             if total_failed > 0 {
+                if let Some(ref hook) = on_failure_command {
+                    run_failure_hook(hook, total_passed, total_failed, total_skipped);
+                }
                 std::process::exit(1);
             }
         }
@@ -513,7 +1056,559 @@ tests:
             let json = serde_json::to_string_pretty(&schema).expect("Failed to serialize schema");
             println!("{json}");
         }
+        Command::Clean { path, older_than } => {
+            let suite_config = match loader::load_suite_config(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error loading suite config: {e}");
+                    std::process::exit(1);
+                }
+            };
+            // A configured directory may hold unrelated files; only the default
+            // .bintest belongs to bintest entirely
+            let (dir, owned) = match suite_config.and_then(|c| c.sandbox_dir) {
+                Some(schema::SandboxDir::Path(p)) => match env::expand_path(&p) {
+                    Ok(dir) => (dir, false),
+                    Err(e) => {
+                        eprintln!("Error: sandbox path {}: {e}", p.display());
+                        std::process::exit(1);
+                    }
+                },
+                _ => (PathBuf::from(".bintest"), true),
+            };
+            if !dir.exists() {
+                println!("Nothing to clean: {} does not exist", dir.display());
+                return;
+            }
+            let spec_names: HashSet<String> = loader::find_specs(&path)
+                .unwrap_or_default()
+                .iter()
+                .map(|p| runner::spec_sandbox_name(Some(p)))
+                .collect();
+            match clean_sandboxes(&dir, owned, &spec_names, older_than) {
+                Ok(removed) => println!(
+                    "Removed {removed} sandbox director{} from {}",
+                    if removed == 1 { "y" } else { "ies" },
+                    dir.display()
+                ),
+                Err(e) => {
+                    eprintln!("Error cleaning {}: {e}", dir.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Parse an age such as `90s`, `30m`, `12h`, or `7d`.
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a number followed by s, m, h, or d: {s:?}"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {unit:?} (expected s, m, h, or d)")),
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age is too large: {s:?}"))
+}
+
+/// Delete the sandbox directories in `dir`, returning how many were removed.
+///
+/// Sandboxes are the subdirectories bintest names: timestamps, and under
+/// `sandbox_name: spec` the names in `spec_names` or the suite sandbox, with an
+/// optional `-N` suffix. Other entries are left alone. Without `older_than`,
+/// every sandbox is removed, or, when `dir` is `owned` by bintest (the default
+/// `.bintest`), `dir` itself. Otherwise only sandboxes older than that are
+/// removed, aged by their timestamp name or else their modification time.
+fn clean_sandboxes(
+    dir: &Path,
+    owned: bool,
+    spec_names: &HashSet<String>,
+    older_than: Option<Duration>,
+) -> std::io::Result<usize> {
+    let now = chrono::Local::now().naive_local();
+    let mut sandboxes = Vec::new();
+    let mut subdirs = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+        subdirs += 1;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let created = chrono::NaiveDateTime::parse_from_str(&name, "%Y%m%d_%H%M%S_%3f").ok();
+        if created.is_none() && !is_spec_sandbox_name(&name, spec_names) {
+            continue;
+        }
+        let age = match created {
+            Some(created) => (now - created).to_std().ok(),
+            None => entry.metadata()?.modified()?.elapsed().ok(),
+        };
+        sandboxes.push((entry.path(), age));
+    }
+
+    let Some(older_than) = older_than else {
+        if owned {
+            fs::remove_dir_all(dir)?;
+            return Ok(subdirs);
+        }
+        for (path, _) in &sandboxes {
+            fs::remove_dir_all(path)?;
+        }
+        return Ok(sandboxes.len());
+    };
+
+    let mut removed = 0;
+    for (path, age) in sandboxes {
+        if age.is_some_and(|age| age > older_than) {
+            fs::remove_dir_all(path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether `name` is a `sandbox_name: spec` sandbox: a spec name or the suite
+/// sandbox, optionally followed by the `-N` suffix added when names collide.
+fn is_spec_sandbox_name(name: &str, spec_names: &HashSet<String>) -> bool {
+    let base = match name.rsplit_once('-') {
+        Some((base, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => name,
+    };
+    [name, base]
+        .iter()
+        .any(|n| *n == runner::SUITE_SANDBOX_NAME || spec_names.contains(*n))
+}
+
+/// Overwrite the terminal's current line with the number of finished spec files.
+fn print_progress(done: usize, total: usize) {
+    print!("\r{done}/{total} specs complete");
+    let _ = std::io::stdout().flush();
+}
+
+/// Save a run's JSON results as `run-<timestamp>.json` in `dir`, then delete
+/// the oldest saved runs so that at most `keep` remain.
+fn save_run_results(
+    dir: &std::path::Path,
+    json: &str,
+    keep: Option<usize>,
+) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
+    // Runs saved within the same millisecond get a `-2`, `-3`, ... suffix
+    let mut attempt = 1;
+    let (path, mut file) = loop {
+        let path = if attempt == 1 {
+            dir.join(format!("run-{timestamp}.json"))
+        } else {
+            dir.join(format!("run-{timestamp}-{attempt}.json"))
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+            result => break (path, result?),
+        }
+    };
+    file.write_all(json.as_bytes())?;
+
+    if let Some(keep) = keep {
+        let mut runs: Vec<(String, u32, PathBuf)> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|p| {
+                let (stamp, attempt) = run_order(p.file_name()?.to_str()?)?;
+                Some((stamp.to_string(), attempt, p))
+            })
+            .collect();
+        // Timestamps sort chronologically, then collision suffixes in order
+        runs.sort();
+        let excess = runs.len().saturating_sub(keep);
+        for (_, _, old) in &runs[..excess] {
+            fs::remove_file(old)?;
+        }
+    }
+
+    Ok(path)
+}
+
+/// The timestamp and collision attempt of a saved `run-<timestamp>[-<n>].json`
+/// name, or `None` for other files.
+fn run_order(name: &str) -> Option<(&str, u32)> {
+    let stem = name.strip_prefix("run-")?.strip_suffix(".json")?;
+    match stem.rsplit_once('-') {
+        Some((stamp, attempt)) => Some((stamp, attempt.parse().ok()?)),
+        None => Some((stem, 1)),
+    }
+}
+
+/// Latest JSON output schema version, selected with `--json-version`.
+/// Version 1 reports tests only; version 2 adds each test's `steps`; version 3
+/// adds each file's spec `version` and each test's `file` and stable `id`;
+/// version 4 adds each test's observed `exit_code` and `signal`.
+const JSON_SCHEMA_VERSION: u8 = 4;
+
+/// Serialize test results in the shape of the given JSON schema version.
+fn tests_json(
+    tests: &[runner::TestResult],
+    file: &str,
+    file_id: &str,
+    version: u8,
+) -> serde_json::Value {
+    let mut value = serde_json::to_value(tests).expect("Failed to serialize");
+    let Some(tests) = value.as_array_mut() else {
+        return value;
+    };
+    for test in tests.iter_mut().filter_map(|t| t.as_object_mut()) {
+        if version < 2 {
+            test.remove("steps");
+        }
+        if version < 4 {
+            test.remove("exit_code");
+            test.remove("signal");
+        } else {
+            test.entry("exit_code").or_insert(serde_json::Value::Null);
+            test.entry("signal").or_insert(serde_json::Value::Null);
+        }
+        if version >= 3 {
+            let name = test["name"].as_str().unwrap_or_default();
+            let id = format!("{file_id}::{name}");
+            test.insert("id".to_string(), id.into());
+            test.insert("file".to_string(), file.into());
+            // Always present, so consumers needn't special-case missing keys
+            test.entry("skipped").or_insert(false.into());
+            test.entry("skip_reason").or_insert(serde_json::Value::Null);
+            test.entry("failed_step").or_insert(serde_json::Value::Null);
+        }
+    }
+    value
+}
+
+/// Write a `--output json-lines` line for each test of a finished spec file.
+fn write_json_lines(sink: &Mutex<Box<dyn std::io::Write + Send>>, report: &bintest::FileReport) {
+    let file = report.path.display().to_string();
+    let lines: Vec<_> = match &report.result {
+        Ok(spec_result) => spec_result
+            .tests
+            .iter()
+            .map(|test| {
+                serde_json::json!({
+                    "type": "test",
+                    "file": file,
+                    "name": test.name,
+                    "passed": test.passed,
+                    "skipped": test.skipped,
+                    "duration": test.duration.as_secs_f64(),
+                    "failures": test.failures,
+                    "exit_code": test.exit_code,
+                    "signal": test.signal,
+                })
+            })
+            .collect(),
+        Err(e) => vec![serde_json::json!({
+            "type": "test",
+            "file": file,
+            "name": "<load>",
+            "passed": false,
+            "skipped": false,
+            "duration": 0.0,
+            "failures": [format!("Failed to load spec: {e}")],
+            "exit_code": null,
+            "signal": null,
+        })],
+    };
+    // Hold the lock for the whole file so its lines stay together
+    let mut sink = sink.lock().expect("output poisoned");
+    for line in lines {
+        let _ = writeln!(sink, "{line}");
+    }
+    let _ = sink.flush();
+}
+
+/// Write one compact JSON object and a newline, flushed so readers see it at once.
+fn write_json_line(sink: &Mutex<Box<dyn std::io::Write + Send>>, value: serde_json::Value) {
+    let mut sink = sink.lock().expect("output poisoned");
+    let _ = writeln!(sink, "{value}");
+    let _ = sink.flush();
+}
+
+/// Write a test's description, and that of its failing step, under the test's
+/// line of the human report.
+fn write_descriptions(report: &mut String, test: &runner::TestResult, palette: color::Palette) {
+    if let Some(description) = &test.description {
+        let _ = writeln!(report, "    {}", palette.dim(description));
+    }
+    if let Some(step) = &test.failed_step
+        && let Some(description) = &step.description
+    {
+        let _ = writeln!(
+            report,
+            "    {}",
+            palette.dim(&format!("step '{}': {description}", step.name))
+        );
+    }
+}
+
+/// The file part of a test's JSON `id`: the spec path relative to the test
+/// root, with `/` separators, so it doesn't depend on where bintest runs from.
+fn spec_id(spec_path: &Path, test_root: &Path) -> String {
+    let relative = spec_path.strip_prefix(test_root).unwrap_or(spec_path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Display name for the generated smoke test spec.
+const SMOKE_SPEC_NAME: &str = "<smoke>";
+
+/// Built-in smoke tests for `--smoke`; `${BINARY}` is the binary under test.
+const SMOKE_SPEC: &str = r#"version: 1
+tests:
+  - name: help_exits_zero
+    run:
+      cmd: "${BINARY}"
+      args: ["--help"]
+    expect:
+      exit: 0
+  - name: version_prints_version
+    run:
+      cmd: "${BINARY}"
+      args: ["--version"]
+    expect:
+      exit: 0
+      stdout:
+        regex: "\\d+\\.\\d+"
+"#;
+
+/// Find the binary for `--smoke`: the suite config's binary, or the binary
+/// declared in the spec file given as the run path.
+fn smoke_binary(
+    path: &std::path::Path,
+    suite_config: Option<&schema::SuiteConfig>,
+) -> Result<PathBuf, String> {
+    if let Some(binary) = suite_config.and_then(|c| c.resolved_binary.clone()) {
+        return Ok(binary);
+    }
+    if path.is_file() {
+        let spec = loader::load_spec(path).map_err(|e| e.to_string())?;
+        if let Some(binary) = spec.resolved_binary {
+            return Ok(binary);
+        }
+    }
+    Err(
+        "--smoke requires a binary: set 'binary' in bintest.yaml or in the given spec file"
+            .to_string(),
+    )
+}
+
+/// Build the smoke test spec for a binary.
+fn build_smoke_spec(binary: PathBuf) -> schema::TestSpec {
+    let mut spec: schema::TestSpec =
+        serde_yaml::from_str(SMOKE_SPEC).expect("built-in smoke spec is valid");
+    spec.resolved_binary = Some(binary);
+    spec
+}
+
+/// Run the `--on-failure-command` hook, passing the run's counts in its
+/// environment. A failing hook is reported but doesn't change the exit code.
+fn run_failure_hook(hook: &str, passed: usize, failed: usize, skipped: usize) {
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("BINTEST_PASSED_COUNT", passed.to_string())
+        .env("BINTEST_FAILED_COUNT", failed.to_string())
+        .env("BINTEST_SKIPPED_COUNT", skipped.to_string())
+        .status();
+    match status {
+        Ok(status) if !status.success() => {
+            eprintln!("On-failure command exited with {status}");
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to run on-failure command: {e}"),
+    }
+}
+
+/// Print, for `--check-isolation`, the host environment variables visible to
+/// each spec's test commands. Returns false if any spec failed to load or probe.
+fn check_env_isolation(spec_paths: &[PathBuf], suite_config: Option<&schema::SuiteConfig>) -> bool {
+    let mut ok = true;
+    for path in spec_paths {
+        let leaked = loader::load_spec(path)
+            .map_err(|e| e.to_string())
+            .and_then(|spec| runner::probe_env_leaks(&spec, suite_config));
+        match leaked {
+            Ok(names) if names.is_empty() => {
+                println!("{}: no host environment variables leak", path.display());
+            }
+            Ok(names) => {
+                println!(
+                    "{}: {} host environment variable(s) leak: {}",
+                    path.display(),
+                    names.len(),
+                    names.join(", ")
+                );
+            }
+            Err(e) => {
+                println!("{}: error: {e}", path.display());
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// The effective configuration of each spec for `--explain`, as a JSON array
+/// of `{"file", "config"}` (or `{"file", "error"}` for specs that fail to
+/// load). Returns the array and the number of load errors.
+fn explain_specs(
+    spec_paths: &[PathBuf],
+    suite_config: Option<&schema::SuiteConfig>,
+) -> (serde_json::Value, usize) {
+    let mut load_errors = 0;
+    let files = spec_paths
+        .iter()
+        .map(|path| {
+            let file = path.display().to_string();
+            match loader::load_spec(path) {
+                Ok(spec) => serde_json::json!({
+                    "file": file,
+                    "config": runner::explain_spec(&spec, suite_config),
+                }),
+                Err(e) => {
+                    load_errors += 1;
+                    serde_json::json!({ "file": file, "error": e.to_string() })
+                }
+            }
+        })
+        .collect();
+    (serde_json::Value::Array(files), load_errors)
+}
+
+/// Format the execution plan for `--plan`: how files and tests are scheduled,
+/// their timeouts, and the databases they use. Returns the text and the number
+/// of specs that failed to load.
+fn format_plan(
+    specs: &[bintest::LoadedSpec],
+    suite_config: Option<&schema::SuiteConfig>,
+    filter: &runner::TestFilter,
+) -> (String, usize) {
+    let mut out = String::new();
+    let mut load_errors = 0;
+
+    let _ = writeln!(
+        out,
+        "{} file(s), run {}",
+        specs.len(),
+        if suite_config.is_some_and(|c| c.serial) {
+            "serially (suite serial: true)"
+        } else {
+            "in parallel"
+        }
+    );
+    if let Some(config) = suite_config
+        && (!config.setup.is_empty() || !config.teardown.is_empty())
+    {
+        let _ = writeln!(
+            out,
+            "suite: {} setup step(s), {} teardown step(s)",
+            config.setup.len(),
+            config.teardown.len()
+        );
+    }
+
+    for (spec_path, spec) in specs {
+        let _ = writeln!(out, "\n{}", spec_path.display());
+        let spec = match spec {
+            Ok(spec) => spec,
+            Err(e) => {
+                let _ = writeln!(out, "  ✗ failed to load: {e}");
+                load_errors += 1;
+                continue;
+            }
+        };
+
+        // Default timeout: file overrides suite overrides built-in default
+        let (timeout, source) = match (spec.timeout, suite_config.and_then(|c| c.timeout)) {
+            (Some(t), _) => (t, "file"),
+            (None, Some(t)) => (t, "suite"),
+            (None, None) => (runner::DEFAULT_TIMEOUT_SECS, "default"),
+        };
+        let _ = writeln!(out, "  timeout: {timeout}s ({source})");
+
+        // Databases: file-level overrides suite-level
+        let mut databases = suite_config
+            .map(|c| c.databases.clone())
+            .unwrap_or_default();
+        databases.extend(spec.databases.clone());
+        let mut db_names: Vec<_> = databases.keys().collect();
+        db_names.sort();
+        for name in &db_names {
+            let db = &databases[*name];
+            let _ = writeln!(
+                out,
+                "  database: {name} ({}, isolation: {})",
+                serde_name(&db.driver),
+                serde_name(&db.isolation)
+            );
+        }
+        // --plan can't be combined with --no-capture
+        let force_serial = runner::forces_serial_tests(&databases, false);
+        if force_serial {
+            let _ = writeln!(out, "  all tests serial (per_test database isolation)");
+        }
+
+        let tests: Vec<_> = spec
+            .tests
+            .iter()
+            .flat_map(|t| runner::expand_matrix(t).unwrap_or_else(|_| vec![t.clone()]))
+            .filter(|t| filter.matches(t))
+            .collect();
+        let (serial, parallel) =
+            runner::partition_tests(tests.iter().map(|t| ((), t)).collect(), force_serial);
+        for (label, group) in [("serial", &serial), ("parallel", &parallel)] {
+            if group.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "  {label} ({}):", group.len());
+            for (_, test) in group {
+                let _ = write!(out, "    {}", test.name);
+                if test.steps.len() > 1 {
+                    let _ = write!(out, " [{} steps]", test.steps.len());
+                }
+                if let Some(t) = test.timeout {
+                    let _ = write!(out, " [timeout {t}s]");
+                }
+                if let Some(resource) = &test.resource
+                    && !test.serial
+                    && !force_serial
+                {
+                    let _ = write!(out, " [resource {resource}]");
+                }
+                out.push('\n');
+            }
+        }
     }
+
+    (out, load_errors)
+}
+
+/// The YAML spelling of a unit enum variant (e.g. `per_file`).
+fn serde_name<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
 }
 
 /// A file result for JUnit output.
@@ -560,6 +1655,19 @@ fn format_junit_xml(results: &[JunitFileResult], total_time: Duration) -> String
                 test.duration.as_secs_f64()
             );
 
+            // Observed exit status, as testcase properties
+            let properties: Vec<_> = [("exit_code", test.exit_code), ("signal", test.signal)]
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|v| (name, v)))
+                .collect();
+            if !properties.is_empty() {
+                xml.push_str("      <properties>\n");
+                for (name, value) in properties {
+                    let _ = writeln!(xml, "        <property name=\"{name}\" value=\"{value}\"/>");
+                }
+                xml.push_str("      </properties>\n");
+            }
+
             if !test.passed {
                 let message = test
                     .failures
@@ -621,3 +1729,28 @@ fn escape_xml(s: &str) -> String {
         .replace('"', "&quot;")
         .replace('\'', "&apos;")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_run_results_never_overwrites_and_keeps_newest() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        // Back-to-back saves usually land in the same millisecond
+        let paths: Vec<PathBuf> = (1..=4)
+            .map(|i| save_run_results(dir, &i.to_string(), None).unwrap())
+            .collect();
+        let unique: std::collections::HashSet<_> = paths.iter().collect();
+        assert_eq!(unique.len(), 4, "paths: {paths:?}");
+
+        save_run_results(dir, "5", Some(3)).unwrap();
+        let mut kept: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        kept.sort();
+        assert_eq!(kept, ["3", "4", "5"]);
+    }
+}
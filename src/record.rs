@@ -0,0 +1,150 @@
+//! Recording command runs (`--record`) and replaying them as expectations (`--replay`).
+
+use crate::schema::{ExitMatch, Expect, OutputMatch, Signal};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Current recording file format version.
+const RECORDING_VERSION: u32 = 1;
+
+/// A recording file: the captured runs of every spec file in a run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub files: Vec<RecordedFile>,
+}
+
+/// The captured runs of one spec file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedFile {
+    /// Spec file path, as given on the command line or discovered in a directory.
+    pub path: PathBuf,
+    pub runs: Vec<RecordedRun>,
+}
+
+/// One step's command and what it produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRun {
+    pub test: String,
+    pub step: String,
+    pub cmd: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Recording {
+    pub fn new(files: Vec<RecordedFile>) -> Self {
+        Self {
+            version: RECORDING_VERSION,
+            files,
+        }
+    }
+
+    /// Load a recording written by `--record`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let recording: Self = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        if recording.version != RECORDING_VERSION {
+            return Err(format!(
+                "unsupported recording version {} (expected {RECORDING_VERSION})",
+                recording.version
+            ));
+        }
+        Ok(recording)
+    }
+
+    /// Write the recording as pretty-printed JSON.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n").map_err(|e| e.to_string())
+    }
+
+    /// The recorded runs for a spec file, if it was part of the recording.
+    pub fn runs_for(&self, path: &Path) -> Option<&[RecordedRun]> {
+        self.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.runs.as_slice())
+    }
+}
+
+impl RecordedRun {
+    /// Replace the exit status and output expectations with the recorded ones.
+    /// Other assertions (files, tree, sql) are kept.
+    pub fn apply_to(&self, expect: &mut Expect) {
+        expect.exit = self.exit_code.map(ExitMatch::Code);
+        expect.signal = self.signal.map(Signal);
+        expect.stdout = Some(OutputMatch::Exact(self.stdout.clone()));
+        expect.stderr = Some(OutputMatch::Exact(self.stderr.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded_run() -> RecordedRun {
+        RecordedRun {
+            test: "greet".to_string(),
+            step: "run".to_string(),
+            cmd: "echo".to_string(),
+            args: vec!["hi".to_string()],
+            stdin: None,
+            exit_code: Some(0),
+            signal: None,
+            stdout: "hi\n".to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.json");
+        Recording::new(vec![RecordedFile {
+            path: PathBuf::from("tests/greet.yaml"),
+            runs: vec![recorded_run()],
+        }])
+        .save(&path)
+        .unwrap();
+
+        let loaded = Recording::load(&path).unwrap();
+        let runs = loaded.runs_for(Path::new("tests/greet.yaml")).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].stdout, "hi\n");
+        assert!(loaded.runs_for(Path::new("tests/other.yaml")).is_none());
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.json");
+        std::fs::write(&path, r#"{"version": 99, "files": []}"#).unwrap();
+
+        assert!(Recording::load(&path).unwrap_err().contains("version 99"));
+    }
+
+    #[test]
+    fn test_apply_to_keeps_other_assertions() {
+        let mut expect = Expect {
+            exit: Some(ExitMatch::Code(3)),
+            stdout: Some(OutputMatch::Exact("old".to_string())),
+            max_duration_ms: Some(100),
+            ..Default::default()
+        };
+        recorded_run().apply_to(&mut expect);
+
+        assert_eq!(expect.exit, Some(ExitMatch::Code(0)));
+        assert!(matches!(expect.stdout, Some(OutputMatch::Exact(ref s)) if s == "hi\n"));
+        assert!(matches!(expect.stderr, Some(OutputMatch::Exact(ref s)) if s.is_empty()));
+        assert_eq!(expect.max_duration_ms, Some(100));
+    }
+}
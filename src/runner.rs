@@ -2,36 +2,51 @@
 //!
 //! Runs test specs in isolated sandboxes and captures results.
 
-use crate::database::ConnectionManager;
+use crate::database::{ConnectionManager, QueryResult, mask_password};
 use crate::env;
+use crate::record::RecordedRun;
 use crate::schema::{
-    Condition, DatabaseConfig, DbDriver, Expect, FileExpect, OutputMatch, OutputMatchStructured,
-    RowCountExpect, Run, RunStep, Sandbox, SandboxDir, SetupStep, SqlExpect, SqlOnError,
-    SqlReturns, SqlReturnsStructured, SuiteConfig, TeardownStep, Test, TestSpec, TreeExpect,
-    WorkDir,
+    AllowedLine, CaptureSource, CaptureStream, Condition, DatabaseConfig, DbDriver, DbIsolation,
+    ExitMatch, Expect, FileExpect, FixtureMode, NumericMatch, OutputMatch, OutputMatchStructured,
+    PreservePolicy, RowCountExpect, Run, RunStep, Sandbox, SandboxDir, SandboxName, SetupStep,
+    Signal, SqlExpect, SqlOnError, SqlReturns, SqlReturnsStructured, Step, SuiteConfig,
+    TeardownStep, Test, TestSpec, TreeExpect, WorkDir,
 };
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 
 /// Default timeout per test in seconds.
-const DEFAULT_TIMEOUT_SECS: u64 = 3;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 3;
+
+/// Default grace period between SIGTERM and SIGKILL on timeout, in milliseconds.
+const DEFAULT_KILL_GRACE_MS: u64 = 2000;
+
+/// Default cap on captured stdout and stderr per command, in bytes.
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 10 * 1024 * 1024;
 
 /// Result of running a test spec file.
 #[derive(Debug, serde::Serialize)]
 pub struct SpecResult {
     pub tests: Vec<TestResult>,
+    /// Each step's command and output, when recording (`--record`).
+    #[serde(skip)]
+    pub recordings: Vec<RecordedRun>,
 }
 
 /// Result of running a single test.
 #[derive(Debug, serde::Serialize)]
 pub struct TestResult {
     pub name: String,
+    /// The test's `description`, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     pub passed: bool,
-    /// Whether the test was skipped due to skip_if or require conditions.
+    /// Whether the test was skipped by `skip`, skip_if or require conditions.
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub skipped: bool,
     /// Reason for skipping the test (if skipped).
@@ -43,9 +58,37 @@ pub struct TestResult {
     /// Which step failed (None if test-level setup/teardown failed, or for single-step tests).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub failed_step: Option<StepFailure>,
+    /// Observed exit code of the failing step's command, or of the last step
+    /// that ran if none failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal that terminated that command (Unix only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
     /// Filesystem changes during test execution (if capture_fs_diff enabled).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fs_diff: Option<FilesystemDiff>,
+    /// Outcome of each step that ran, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepResult>,
+}
+
+/// Outcome of one step of a test.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StepResult {
+    pub name: String,
+    pub passed: bool,
+    /// Whether the step was skipped because it doesn't run on this platform.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    /// Observed exit code (None if the command didn't run or was killed by a signal).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the command (Unix only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    #[serde(serialize_with = "serialize_duration")]
+    pub duration: Duration,
 }
 
 /// Information about which step failed in a multi-step test.
@@ -53,8 +96,27 @@ pub struct TestResult {
 pub struct StepFailure {
     /// Step name.
     pub name: String,
+    /// The step's `description`, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     /// Step index (0-based).
     pub index: usize,
+    /// Phase of the step that failed.
+    pub phase: StepPhase,
+}
+
+/// Phase of a step in which a failure occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepPhase {
+    /// Step-level setup failed.
+    Setup,
+    /// The command could not be run (spawn error, timeout).
+    Command,
+    /// The command ran but its assertions failed.
+    Assertion,
+    /// Step-level teardown failed.
+    Teardown,
 }
 
 /// Filesystem changes captured during test execution.
@@ -94,9 +156,12 @@ enum ConditionResult {
 /// For `cmd` conditions: checks if the command exits with code 0.
 fn check_condition(condition: &Condition) -> bool {
     if let Some(env_var) = &condition.env {
-        // Check if environment variable is set and non-empty
+        // Check if environment variable equals the value, or is set and non-empty
         return std::env::var(env_var)
-            .map(|v| !v.is_empty())
+            .map(|v| match &condition.equals {
+                Some(expected) => v == *expected,
+                None => !v.is_empty(),
+            })
             .unwrap_or(false);
     }
 
@@ -123,22 +188,44 @@ fn check_condition(condition: &Condition) -> bool {
         return result.is_ok_and(|status| status.success());
     }
 
+    if let Some(path) = &condition.file {
+        // Treat interpolation failure as condition not met
+        return env::expand_path(path).is_ok_and(|path| path.exists());
+    }
+
     // If no condition type is specified, treat as satisfied
     true
 }
 
-/// Evaluate skip_if and require conditions for a test.
+/// Evaluate `skip`, step platforms, skip_if and require conditions for a test.
 ///
 /// Returns `ConditionResult::Run` if the test should run, or
 /// `ConditionResult::Skip(reason)` if it should be skipped.
 fn evaluate_conditions(test: &Test) -> ConditionResult {
+    if test.skip {
+        return ConditionResult::Skip("explicitly skipped".to_string());
+    }
+    if !test.steps.is_empty() && !test.steps.iter().any(Step::runs_on_current_platform) {
+        return ConditionResult::Skip(format!(
+            "platform: no step runs on {}",
+            std::env::consts::OS
+        ));
+    }
+
     // Check skip_if conditions - skip if ANY condition is true
     for condition in &test.skip_if {
         if check_condition(condition) {
             let reason = if let Some(env_var) = &condition.env {
-                format!("skip_if: environment variable '{}' is set", env_var)
+                match &condition.equals {
+                    Some(value) => {
+                        format!("skip_if: environment variable '{env_var}' equals '{value}'")
+                    }
+                    None => format!("skip_if: environment variable '{}' is set", env_var),
+                }
             } else if let Some(cmd) = &condition.cmd {
                 format!("skip_if: command '{}' succeeded", cmd)
+            } else if let Some(path) = &condition.file {
+                format!("skip_if: file '{}' exists", path.display())
             } else {
                 "skip_if: condition met".to_string()
             };
@@ -150,9 +237,16 @@ fn evaluate_conditions(test: &Test) -> ConditionResult {
     for condition in &test.require {
         if !check_condition(condition) {
             let reason = if let Some(env_var) = &condition.env {
-                format!("require: environment variable '{}' is not set", env_var)
+                match &condition.equals {
+                    Some(value) => format!(
+                        "require: environment variable '{env_var}' does not equal '{value}'"
+                    ),
+                    None => format!("require: environment variable '{}' is not set", env_var),
+                }
             } else if let Some(cmd) = &condition.cmd {
                 format!("require: command '{}' failed or not found", cmd)
+            } else if let Some(path) = &condition.file {
+                format!("require: file '{}' does not exist", path.display())
             } else {
                 "require: condition not met".to_string()
             };
@@ -163,34 +257,60 @@ fn evaluate_conditions(test: &Test) -> ConditionResult {
     ConditionResult::Run
 }
 
-/// Run suite-level setup steps.
+/// Environment variable holding the suite sandbox directory.
+pub const SUITE_DIR_ENV: &str = "BINTEST_SUITE_DIR";
+
+/// Name of the suite sandbox under `sandbox_name: spec`.
+pub const SUITE_SANDBOX_NAME: &str = "suite";
+
+/// The sandbox shared by suite-level setup and teardown.
 ///
-/// Creates a temporary context for suite setup (uses temp directory).
-pub fn run_suite_setup(config: &SuiteConfig) -> Result<(), String> {
+/// It lives for the whole run, so suite setup can prepare files that spec
+/// tests read through `BINTEST_SUITE_DIR`, and teardown sees what they left.
+pub struct SuiteContext {
+    ctx: ExecutionContext,
+}
+
+impl SuiteContext {
+    pub fn new(config: &SuiteConfig) -> Result<Self, String> {
+        let name = (config.sandbox_name == SandboxName::Spec).then_some(SUITE_SANDBOX_NAME);
+        let ctx = ExecutionContext::new(&Sandbox::default(), config.sandbox_dir.as_ref(), name)
+            .map_err(|e| format!("Failed to create suite context: {e}"))?;
+        Ok(Self { ctx })
+    }
+
+    /// Absolute path of the suite sandbox directory.
+    pub fn dir(&self) -> PathBuf {
+        std::fs::canonicalize(&self.ctx.sandbox_dir)
+            .unwrap_or_else(|_| self.ctx.sandbox_dir.clone())
+    }
+}
+
+/// Run suite-level setup steps in the suite sandbox.
+pub fn run_suite_setup(config: &SuiteConfig, suite: &SuiteContext) -> Result<(), String> {
     if config.setup.is_empty() {
         return Ok(());
     }
 
-    let ctx = ExecutionContext::new(&Sandbox::default(), config.sandbox_dir.as_ref())
-        .map_err(|e| format!("Failed to create suite context: {e}"))?;
+    let setup = substitute_vars(&config.setup, &defer_captured(&config.vars, &config.setup))?;
     let db_manager = ConnectionManager::new(config.databases.clone());
-    let result = run_setup_steps(&config.setup, &ctx, &db_manager);
+    let result = run_setup_steps(&setup, &suite.ctx, &db_manager, &mut HashMap::new());
     db_manager.close_all();
     result
 }
 
-/// Run suite-level teardown steps.
-///
-/// Creates a temporary context for suite teardown (uses temp directory).
-pub fn run_suite_teardown(config: &SuiteConfig) -> Result<(), String> {
+/// Run suite-level teardown steps in the suite sandbox.
+pub fn run_suite_teardown(config: &SuiteConfig, suite: &SuiteContext) -> Result<(), String> {
     if config.teardown.is_empty() {
         return Ok(());
     }
 
-    let ctx = ExecutionContext::new(&Sandbox::default(), config.sandbox_dir.as_ref())
-        .map_err(|e| format!("Failed to create suite context: {e}"))?;
+    let teardown = substitute_vars(
+        &config.teardown,
+        &defer_captured(&config.vars, &config.teardown),
+    )?;
     let db_manager = ConnectionManager::new(config.databases.clone());
-    let result = run_teardown_steps(&config.teardown, &ctx, &db_manager);
+    let result = run_teardown_steps(&teardown, &suite.ctx, &db_manager, &HashMap::new());
     db_manager.close_all();
     result
 }
@@ -200,24 +320,47 @@ struct ExecutionContext {
     sandbox_dir: PathBuf,
     env: HashMap<String, String>,
     inherit_env: bool,
-    _temp_dir: Option<tempfile::TempDir>,
+    inherit_env_except: Vec<String>,
+    normalize_locale: Option<String>,
+    /// Time to wait after SIGTERM before SIGKILL when a command times out.
+    kill_grace: Duration,
+    /// Bytes of stdout and of stderr kept per command.
+    max_output_bytes: u64,
+    /// Default for matchers that don't set `strip_ansi`.
+    strip_ansi: bool,
+    /// Default for matchers that don't set `normalize_newlines`.
+    normalize_newlines: bool,
+    /// Fail steps with stderr output but no `expect.stderr`.
+    strict_stderr: bool,
+    /// Shell for `shell: true` commands without their own `shell_program`.
+    shell_program: Option<String>,
+    /// Directory around the sandbox watched for writes escaping it.
+    outside_dir: Option<PathBuf>,
+    /// Connect commands to the terminal instead of capturing their output.
+    no_capture: bool,
+    /// Variables captured by the file's setup commands (`capture_as`).
+    vars: HashMap<String, String>,
+    /// Temporary sandbox, deleted on drop unless preserved.
+    temp_dir: Option<tempfile::TempDir>,
+    /// Sink for step commands and outputs when recording.
+    recorder: Option<Mutex<Vec<RecordedRun>>>,
 }
 
 impl ExecutionContext {
-    fn new(sandbox: &Sandbox, suite_sandbox_dir: Option<&SandboxDir>) -> std::io::Result<Self> {
+    /// `name` names the directory created under a suite `sandbox_dir`; without
+    /// it the directory is named by timestamp.
+    fn new(
+        sandbox: &Sandbox,
+        suite_sandbox_dir: Option<&SandboxDir>,
+        name: Option<&str>,
+    ) -> std::io::Result<Self> {
         let (sandbox_dir, temp_dir) = match (&sandbox.workdir, suite_sandbox_dir) {
             // If suite specifies a sandbox_dir and workdir is temp, use the suite's dir
             (WorkDir::Temp, Some(SandboxDir::Local)) => {
-                let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
-                let dir = PathBuf::from(".bintest").join(timestamp.to_string());
-                std::fs::create_dir_all(&dir)?;
-                (dir, None)
+                (create_sandbox_subdir(Path::new(".bintest"), name)?, None)
             }
             (WorkDir::Temp, Some(SandboxDir::Path(p))) => {
-                let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
-                let dir = p.join(timestamp.to_string());
-                std::fs::create_dir_all(&dir)?;
-                (dir, None)
+                (create_sandbox_subdir(&expand_sandbox_path(p)?, name)?, None)
             }
             // Default temp behavior
             (WorkDir::Temp, None) => {
@@ -227,8 +370,9 @@ impl ExecutionContext {
             }
             // Explicit path always wins
             (WorkDir::Path(p), _) => {
-                std::fs::create_dir_all(p)?;
-                (p.clone(), None)
+                let dir = expand_sandbox_path(p)?;
+                std::fs::create_dir_all(&dir)?;
+                (dir, None)
             }
         };
 
@@ -236,10 +380,118 @@ impl ExecutionContext {
             sandbox_dir,
             env: sandbox.env.clone(),
             inherit_env: sandbox.inherit_env,
-            _temp_dir: temp_dir,
+            inherit_env_except: sandbox.inherit_env_except.clone(),
+            normalize_locale: sandbox.normalize_locale.clone(),
+            kill_grace: Duration::from_millis(DEFAULT_KILL_GRACE_MS),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            outside_dir: None,
+            no_capture: false,
+            vars: HashMap::new(),
+            temp_dir,
+            recorder: None,
         })
     }
 
+    /// A context identical to this one except that commands run in `dir`.
+    fn with_sandbox_dir(&self, dir: &Path) -> Self {
+        Self {
+            sandbox_dir: dir.to_path_buf(),
+            env: self.env.clone(),
+            inherit_env: self.inherit_env,
+            inherit_env_except: self.inherit_env_except.clone(),
+            normalize_locale: self.normalize_locale.clone(),
+            kill_grace: self.kill_grace,
+            max_output_bytes: self.max_output_bytes,
+            strip_ansi: self.strip_ansi,
+            normalize_newlines: self.normalize_newlines,
+            strict_stderr: self.strict_stderr,
+            shell_program: self.shell_program.clone(),
+            outside_dir: None,
+            no_capture: self.no_capture,
+            vars: self.vars.clone(),
+            temp_dir: None,
+            recorder: None,
+        }
+    }
+
+    /// Watch the directory containing the sandbox for writes that escape it.
+    ///
+    /// With `private_parent`, the sandbox first moves one level down into a
+    /// `sandbox/` directory, so the watched parent isn't shared with the
+    /// sandboxes of other spec files.
+    fn watch_outside_writes(&mut self, private_parent: bool) -> std::io::Result<()> {
+        if private_parent {
+            self.sandbox_dir = self.sandbox_dir.join("sandbox");
+            std::fs::create_dir_all(&self.sandbox_dir)?;
+        }
+        let sandbox_dir = self.sandbox_dir.canonicalize()?;
+        self.outside_dir = sandbox_dir.parent().map(Path::to_path_buf);
+        Ok(())
+    }
+
+    /// Add a step's command and output to the recorder, if recording.
+    fn record(&self, test: &Test, step: &Step, output: &CommandOutput) {
+        if let Some(recorder) = &self.recorder {
+            recorder
+                .lock()
+                .expect("recorder poisoned")
+                .push(RecordedRun {
+                    test: test.name.clone(),
+                    step: step.name.clone(),
+                    cmd: step.run.cmd.clone(),
+                    args: step.run.args.clone(),
+                    stdin: step.run.stdin.clone(),
+                    exit_code: output.exit_code,
+                    signal: output.signal,
+                    stdout: output.stdout.clone(),
+                    stderr: output.stderr.clone(),
+                });
+        }
+    }
+
+    /// Set `LANG` and `LC_ALL` to the normalized locale unless already configured.
+    fn apply_locale(&self, env: &mut HashMap<String, String>) {
+        if let Some(locale) = &self.normalize_locale {
+            for var in ["LANG", "LC_ALL"] {
+                env.entry(var.to_string()).or_insert_with(|| locale.clone());
+            }
+        }
+    }
+
+    /// The environment a command sees: the given variables on top of the
+    /// inherited host environment (minus blocked names) when `inherit_env` is set.
+    /// Used to expand `${NAME}` in arguments without leaking host variables into
+    /// an isolated sandbox.
+    fn visible_env(&self, env: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut visible = HashMap::new();
+        if self.inherit_env {
+            visible.extend(
+                std::env::vars().filter(|(name, _)| !self.inherit_env_except.contains(name)),
+            );
+        }
+        visible.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        visible
+    }
+
+    /// Set a command's environment: the given variables on top of either a
+    /// cleared environment or the inherited host environment minus blocked names.
+    fn apply_env(&self, cmd: &mut Command, env: &HashMap<String, String>) {
+        if !self.inherit_env {
+            cmd.env_clear();
+        } else {
+            for name in &self.inherit_env_except {
+                cmd.env_remove(name);
+            }
+        }
+        for (k, v) in env {
+            cmd.env(k, v);
+        }
+    }
+
     fn resolve_path(&self, path: &Path) -> PathBuf {
         if path.is_absolute() {
             path.to_path_buf()
@@ -249,6 +501,56 @@ impl ExecutionContext {
     }
 }
 
+/// Create a sandbox directory under `parent`, named `name` or by timestamp.
+///
+/// A name that is taken gets `-2`, `-3`, ... appended; each candidate is
+/// created atomically, so concurrent spec files never share a directory.
+fn create_sandbox_subdir(parent: &Path, name: Option<&str>) -> std::io::Result<PathBuf> {
+    let Some(name) = name else {
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f");
+        let dir = parent.join(timestamp.to_string());
+        std::fs::create_dir_all(&dir)?;
+        return Ok(dir);
+    };
+    std::fs::create_dir_all(parent)?;
+    let mut attempt = 1;
+    loop {
+        let dir = if attempt == 1 {
+            parent.join(name)
+        } else {
+            parent.join(format!("{name}-{attempt}"))
+        };
+        match std::fs::create_dir(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+            result => return result.map(|()| dir),
+        }
+    }
+}
+
+/// The sandbox name for a spec file under `sandbox_name: spec`: its file stem,
+/// with characters other than ASCII alphanumerics, `-`, `_` and `.` replaced by `_`.
+pub fn spec_sandbox_name(spec_path: Option<&Path>) -> String {
+    let stem = spec_path
+        .and_then(Path::file_stem)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "spec".to_string());
+    stem.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Expand `${VAR}` and `~` in a sandbox path, as an IO error on failure.
+fn expand_sandbox_path(path: &Path) -> std::io::Result<PathBuf> {
+    env::expand_path(path)
+        .map_err(|e| std::io::Error::other(format!("sandbox path {}: {e}", path.display())))
+}
+
 /// Effective configuration for running a spec, combining suite and file settings.
 #[derive(Debug, Clone, Default)]
 pub struct EffectiveConfig {
@@ -260,12 +562,46 @@ pub struct EffectiveConfig {
     pub inherit_env: Option<bool>,
     /// Whether to capture filesystem diffs (suite-level default).
     pub capture_fs_diff: bool,
+    /// Whether to strip ANSI escape codes before matching output (suite-level default).
+    pub strip_ansi: bool,
+    /// Whether to normalize line endings before matching output (suite-level default).
+    pub normalize_newlines: bool,
+    /// Whether unexpected stderr output fails a step (suite-level).
+    pub strict_stderr: bool,
+    /// Shell for `shell: true` commands (suite-level).
+    pub shell_program: Option<String>,
+    /// Whether to fail tests that write next to the sandbox.
+    pub forbid_outside_writes: bool,
     /// Directory for test sandboxes (from suite config or CLI).
     pub sandbox_dir: Option<SandboxDir>,
+    /// How sandboxes under `sandbox_dir` are named.
+    pub sandbox_name: SandboxName,
+    /// Suite-level `${vars.NAME}` variables.
+    pub vars: HashMap<String, String>,
     /// Suite-level database configurations.
     pub databases: HashMap<String, DatabaseConfig>,
     /// Resolved binary path from suite config (file-level can override).
     pub resolved_binary: Option<PathBuf>,
+    /// SQL to seed every configured database with before file setup.
+    pub seed_sql: Option<String>,
+    /// Grace period between SIGTERM and SIGKILL on timeout (milliseconds).
+    pub kill_grace_ms: Option<u64>,
+    /// Cap on captured stdout and stderr per command (bytes).
+    pub max_output_bytes: Option<u64>,
+    /// Which fixture phases to run (from CLI).
+    pub fixture_mode: FixtureMode,
+    /// Default policy for keeping temporary sandboxes.
+    pub preserve: Option<PreservePolicy>,
+    /// Capture each step's command and output into the results (from --record).
+    pub record: bool,
+    /// Seed for shuffling the spawn order of each file's parallel tests (from
+    /// --shuffle/--seed).
+    pub shuffle_seed: Option<u64>,
+    /// Whether commands use the terminal instead of captured pipes.
+    pub no_capture: bool,
+    /// Keep a failing file's sandbox even if its preserve policy says not to
+    /// (from --keep-on-failure).
+    pub keep_on_failure: bool,
 }
 
 impl EffectiveConfig {
@@ -277,37 +613,178 @@ impl EffectiveConfig {
                 suite_env: cfg.env.clone(),
                 inherit_env: cfg.inherit_env,
                 capture_fs_diff: cfg.capture_fs_diff,
+                strip_ansi: cfg.strip_ansi,
+                normalize_newlines: cfg.normalize_newlines,
+                strict_stderr: cfg.strict_stderr,
+                shell_program: cfg.shell_program.clone(),
+                forbid_outside_writes: cfg.forbid_outside_writes,
                 sandbox_dir: cfg.sandbox_dir.clone(),
+                sandbox_name: cfg.sandbox_name,
+                vars: cfg.vars.clone(),
                 databases: cfg.databases.clone(),
                 resolved_binary: cfg.resolved_binary.clone(),
+                seed_sql: cfg.seed_sql.clone(),
+                kill_grace_ms: cfg.kill_grace_ms,
+                max_output_bytes: cfg.max_output_bytes,
+                fixture_mode: cfg.fixture_mode,
+                preserve: cfg.preserve,
+                record: cfg.record,
+                shuffle_seed: cfg.shuffle_seed,
+                no_capture: cfg.no_capture,
+                keep_on_failure: cfg.keep_on_failure,
             },
             None => Self::default(),
         }
     }
 }
 
+/// Selects which tests run, by name substring and tags.
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter {
+    /// Substring the test name must contain.
+    pub name: Option<String>,
+    /// If non-empty, the test must have at least one of these tags.
+    pub tags: Vec<String>,
+    /// The test must have none of these tags.
+    pub exclude_tags: Vec<String>,
+    /// If set, the exact (matrix-expanded) names of the tests to run.
+    pub names: Option<Vec<String>>,
+    /// Only run tests marked `only: true` (set when any test in the run has it).
+    pub only: bool,
+}
+
+impl TestFilter {
+    /// Whether a test is selected by this filter.
+    pub fn matches(&self, test: &Test) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|f| test.name.contains(f.as_str()))
+            && self.names.as_ref().is_none_or(|n| n.contains(&test.name))
+            && (self.tags.is_empty() || self.tags.iter().any(|t| test.tags.contains(t)))
+            && !self.exclude_tags.iter().any(|t| test.tags.contains(t))
+            && (!self.only || test.only)
+    }
+}
+
 /// Run a test specification file with optional suite configuration.
-#[cfg_attr(not(test), allow(dead_code))]
 pub fn run_spec(spec: &TestSpec, suite_config: Option<&SuiteConfig>) -> SpecResult {
-    run_spec_filtered(spec, suite_config, None)
+    run_spec_filtered(spec, suite_config, &TestFilter::default(), None)
 }
 
 /// Run a test specification file with optional suite configuration and filter.
+///
+/// `shared_db` is a connection manager kept open across spec files (suite
+/// `reuse_connections`). It is used when the file's merged databases match its
+/// own; otherwise the file opens its own connections. Only safe when spec files
+/// run serially.
 pub fn run_spec_filtered(
     spec: &TestSpec,
     suite_config: Option<&SuiteConfig>,
-    filter: Option<&str>,
+    filter: &TestFilter,
+    shared_db: Option<&ConnectionManager>,
 ) -> SpecResult {
     let effective = EffectiveConfig::from_suite(suite_config);
-    run_spec_with_config(spec, &effective, filter)
+    run_spec_with_config(spec, &effective, filter, shared_db)
 }
 
-/// Run a test specification file with effective configuration.
-fn run_spec_with_config(
+/// Report which host environment variables a test command in this spec would
+/// see without the suite, file, or sandbox setting them. Runs `env` as a probe
+/// in a temporary sandbox; returns the leaked names, sorted.
+pub fn probe_env_leaks(
     spec: &TestSpec,
-    effective: &EffectiveConfig,
-    filter: Option<&str>,
-) -> SpecResult {
+    suite_config: Option<&SuiteConfig>,
+) -> Result<Vec<String>, String> {
+    let effective = EffectiveConfig::from_suite(suite_config);
+    let ctx = ExecutionContext::new(&merged_sandbox(spec, &effective), None, None)
+        .map_err(|e| format!("Failed to create sandbox: {e}"))?;
+    let probe = Run {
+        cmd: "env".to_string(),
+        args: vec![],
+        stdin: None,
+        stdin_file: None,
+        stdout_file: None,
+        stderr_file: None,
+        env: HashMap::new(),
+        cwd: None,
+        shell: false,
+        shell_program: None,
+    };
+    let output = run_command(
+        &probe,
+        &ctx,
+        &HashMap::new(),
+        Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+    )?;
+    if output.exit_code != Some(0) {
+        return Err(format!("env probe failed: {}", output.stderr.trim()));
+    }
+
+    let mut set_by_spec = ctx.env.clone();
+    ctx.apply_locale(&mut set_by_spec);
+    let visible: Vec<&str> = output
+        .stdout
+        .lines()
+        .filter_map(|line| line.split_once('=').map(|(name, _)| name))
+        .collect();
+    let mut leaked: Vec<String> = std::env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| visible.contains(&name.as_str()) && !set_by_spec.contains_key(name))
+        .collect();
+    leaked.sort();
+    Ok(leaked)
+}
+
+/// The settings a spec runs with after merging suite and file config, and the
+/// timeout each (matrix-expanded) test resolves to, as JSON. Runs nothing.
+pub fn explain_spec(spec: &TestSpec, suite_config: Option<&SuiteConfig>) -> serde_json::Value {
+    let effective = EffectiveConfig::from_suite(suite_config);
+    let sandbox = merged_sandbox(spec, &effective);
+    let file_timeout = spec.timeout.or(effective.default_timeout);
+
+    let mut vars = effective.vars.clone();
+    vars.extend(spec.vars.clone());
+    let mut databases = effective.databases.clone();
+    databases.extend(spec.databases.clone());
+    // Connection URLs may carry credentials
+    for config in databases.values_mut() {
+        config.url = mask_password(&config.url);
+    }
+
+    let tests: Vec<_> = spec
+        .tests
+        .iter()
+        .flat_map(|t| expand_matrix(t).unwrap_or_else(|_| vec![t.clone()]))
+        .map(|t| {
+            serde_json::json!({
+                "name": t.name,
+                "timeout": t.timeout.or(file_timeout).unwrap_or(DEFAULT_TIMEOUT_SECS),
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "timeout": file_timeout.unwrap_or(DEFAULT_TIMEOUT_SECS),
+        "env": sandbox.env,
+        "inherit_env": sandbox.inherit_env,
+        "vars": vars,
+        "databases": databases,
+        "capture_fs_diff": spec.capture_fs_diff.unwrap_or(effective.capture_fs_diff),
+        "strip_ansi": effective.strip_ansi,
+        "normalize_newlines": effective.normalize_newlines,
+        "strict_stderr": effective.strict_stderr,
+        "shell_program": effective.shell_program.as_deref().unwrap_or(DEFAULT_SHELL),
+        "forbid_outside_writes": effective.forbid_outside_writes,
+        "sandbox_dir": effective.sandbox_dir,
+        "sandbox_name": effective.sandbox_name,
+        "kill_grace_ms": effective.kill_grace_ms,
+        "max_output_bytes": effective.max_output_bytes,
+        "tests": tests,
+    })
+}
+
+/// The spec's sandbox with env merged suite < file < sandbox, `BINARY` set, and
+/// the suite's `inherit_env` default applied.
+fn merged_sandbox(spec: &TestSpec, effective: &EffectiveConfig) -> Sandbox {
     // Build merged environment: suite < file < sandbox
     // Start with suite env
     let mut file_env = effective.suite_env.clone();
@@ -340,6 +817,61 @@ fn run_spec_with_config(
             merged_sandbox.inherit_env = inherit;
         }
     }
+    merged_sandbox
+}
+
+/// Run a test specification file with effective configuration.
+fn run_spec_with_config(
+    spec: &TestSpec,
+    effective: &EffectiveConfig,
+    filter: &TestFilter,
+    shared_db: Option<&ConnectionManager>,
+) -> SpecResult {
+    // Variables: file-level overrides suite-level
+    let mut vars = effective.vars.clone();
+    vars.extend(spec.vars.clone());
+    let vars = defer_captured(
+        &vars,
+        &(
+            &spec.setup,
+            &spec.fixtures,
+            &spec.teardown,
+            &spec.default_expect,
+            &spec.tests,
+        ),
+    );
+
+    let substituted = (|| {
+        Ok::<_, String>((
+            substitute_vars(&merged_sandbox(spec, effective), &vars)?,
+            substitute_vars(&spec.setup, &vars)?,
+            substitute_vars(&spec.fixtures, &vars)?,
+            substitute_vars(&spec.teardown, &vars)?,
+            substitute_vars(&spec.default_expect, &vars)?,
+        ))
+    })();
+    let (merged_sandbox, setup, fixtures, teardown, default_expect) = match substituted {
+        Ok(substituted) => substituted,
+        Err(e) => {
+            return SpecResult {
+                tests: vec![TestResult {
+                    name: "<setup>".to_string(),
+                    description: None,
+                    passed: false,
+                    skipped: false,
+                    skip_reason: None,
+                    duration: Duration::ZERO,
+                    failures: vec![format!("Variable expansion failed: {e}")],
+                    failed_step: None,
+                    exit_code: None,
+                    signal: None,
+                    fs_diff: None,
+                    steps: vec![],
+                }],
+                recordings: vec![],
+            };
+        }
+    };
 
     // Determine file-level default timeout
     let file_timeout = spec.timeout.or(effective.default_timeout);
@@ -347,23 +879,69 @@ fn run_spec_with_config(
     // Determine file-level capture_fs_diff (file overrides suite)
     let file_capture_fs_diff = spec.capture_fs_diff.unwrap_or(effective.capture_fs_diff);
 
-    let ctx = match ExecutionContext::new(&merged_sandbox, effective.sandbox_dir.as_ref()) {
+    let sandbox_name = (effective.sandbox_name == SandboxName::Spec)
+        .then(|| spec_sandbox_name(spec.source_path.as_deref()));
+    let mut ctx = match ExecutionContext::new(
+        &merged_sandbox,
+        effective.sandbox_dir.as_ref(),
+        sandbox_name.as_deref(),
+    ) {
         Ok(ctx) => ctx,
         Err(e) => {
             return SpecResult {
                 tests: vec![TestResult {
                     name: "<setup>".to_string(),
+                    description: None,
                     passed: false,
                     skipped: false,
                     skip_reason: None,
                     duration: Duration::ZERO,
                     failures: vec![format!("Failed to create sandbox: {e}")],
                     failed_step: None,
+                    exit_code: None,
+                    signal: None,
                     fs_diff: None,
+                    steps: vec![],
                 }],
+                recordings: vec![],
             };
         }
     };
+    if let Some(grace_ms) = effective.kill_grace_ms {
+        ctx.kill_grace = Duration::from_millis(grace_ms);
+    }
+    if let Some(max) = effective.max_output_bytes {
+        ctx.max_output_bytes = max;
+    }
+    ctx.strip_ansi = effective.strip_ansi;
+    ctx.normalize_newlines = effective.normalize_newlines;
+    ctx.strict_stderr = effective.strict_stderr;
+    ctx.shell_program = effective.shell_program.clone();
+    ctx.no_capture = effective.no_capture;
+    if effective.forbid_outside_writes
+        && let Err(e) = ctx.watch_outside_writes(matches!(merged_sandbox.workdir, WorkDir::Temp))
+    {
+        return SpecResult {
+            tests: vec![TestResult {
+                name: "<setup>".to_string(),
+                description: None,
+                passed: false,
+                skipped: false,
+                skip_reason: None,
+                duration: Duration::ZERO,
+                failures: vec![format!("Failed to create sandbox: {e}")],
+                failed_step: None,
+                exit_code: None,
+                signal: None,
+                fs_diff: None,
+                steps: vec![],
+            }],
+            recordings: vec![],
+        };
+    }
+    if effective.record {
+        ctx.recorder = Some(Mutex::new(Vec::new()));
+    }
 
     // Merge database configurations (file-level overrides suite-level)
     let mut merged_databases = effective.databases.clone();
@@ -371,33 +949,86 @@ fn run_spec_with_config(
         merged_databases.insert(name.clone(), config.clone());
     }
 
-    // Create connection manager (connections are lazy, opened on first use)
-    let db_manager = ConnectionManager::new(merged_databases);
+    // Reuse the shared connections when this file adds no databases of its
+    // own; otherwise create a connection manager (connections are lazy, opened
+    // on first use)
+    let owned_db_manager;
+    let db_manager = match shared_db {
+        Some(shared) if shared.has_configs(&merged_databases) => {
+            shared.clear_snapshots();
+            shared
+        }
+        _ => {
+            owned_db_manager = ConnectionManager::new(merged_databases);
+            &owned_db_manager
+        }
+    };
+
+    let run_setup = effective.fixture_mode != FixtureMode::TeardownOnly;
+    let run_teardown = effective.fixture_mode != FixtureMode::SetupOnly;
+
+    // Seed every configured database before file-level setup
+    if run_setup && let Some(seed_sql) = &effective.seed_sql {
+        for db_name in db_manager.database_names() {
+            if let Err(e) = db_manager.execute_script(&db_name, seed_sql) {
+                return SpecResult {
+                    tests: vec![TestResult {
+                        name: "<setup>".to_string(),
+                        description: None,
+                        passed: false,
+                        skipped: false,
+                        skip_reason: None,
+                        duration: Duration::ZERO,
+                        failures: vec![format!("Seeding database failed: {e}")],
+                        failed_step: None,
+                        exit_code: None,
+                        signal: None,
+                        fs_diff: None,
+                        steps: vec![],
+                    }],
+                    recordings: vec![],
+                };
+            }
+        }
+    }
 
     // Run file-level setup
-    if let Err(e) = run_setup_steps(&spec.setup, &ctx, &db_manager) {
+    let mut captured = HashMap::new();
+    if run_setup && let Err(e) = run_setup_steps(&setup, &ctx, db_manager, &mut captured) {
         return SpecResult {
             tests: vec![TestResult {
                 name: "<setup>".to_string(),
+                description: None,
                 passed: false,
                 skipped: false,
                 skip_reason: None,
                 duration: Duration::ZERO,
                 failures: vec![format!("Setup failed: {e}")],
                 failed_step: None,
+                exit_code: None,
+                signal: None,
                 fs_diff: None,
+                steps: vec![],
             }],
+            recordings: vec![],
         };
     }
 
+    ctx.vars = captured;
+
     // Initialize isolation for databases with per_file isolation
     // This captures the post-setup state that will be restored before each test
-    let isolated_databases = db_manager.get_isolated_databases();
+    let isolated_databases = if effective.fixture_mode == FixtureMode::Full {
+        db_manager.get_isolated_databases()
+    } else {
+        vec![]
+    };
     for db_name in &isolated_databases {
         if let Err(e) = db_manager.init_isolation(db_name) {
             return SpecResult {
                 tests: vec![TestResult {
                     name: "<setup>".to_string(),
+                    description: None,
                     passed: false,
                     skipped: false,
                     skip_reason: None,
@@ -407,52 +1038,148 @@ fn run_spec_with_config(
                         db_name, e
                     )],
                     failed_step: None,
+                    exit_code: None,
+                    signal: None,
                     fs_diff: None,
+                    steps: vec![],
                 }],
+                recordings: vec![],
             };
         }
     }
 
-    // Filter tests by name if a filter is provided
-    let filtered_tests: Vec<(usize, &Test)> = spec
-        .tests
+    // Expand matrix tests into one test per combination of values
+    let mut expanded_tests: Vec<Test> = Vec::with_capacity(spec.tests.len());
+    let mut matrix_errors: Vec<TestResult> = Vec::new();
+    for test in &spec.tests {
+        let expanded = expand_matrix(test)
+            .map_err(|e| format!("Matrix expansion failed: {e}"))
+            .and_then(|tests| {
+                tests
+                    .iter()
+                    .map(|t| substitute_vars(t, &vars))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("Variable expansion failed: {e}"))
+            });
+        match expanded {
+            Ok(tests) => expanded_tests.extend(tests),
+            Err(e) => matrix_errors.push(TestResult {
+                name: test.name.clone(),
+                description: test.description.clone(),
+                passed: false,
+                skipped: false,
+                skip_reason: None,
+                duration: Duration::ZERO,
+                failures: vec![e],
+                failed_step: None,
+                exit_code: None,
+                signal: None,
+                fs_diff: None,
+                steps: vec![],
+            }),
+        }
+    }
+
+    // Fill in each step's unset expectations from the file's default_expect
+    if let Some(default_expect) = &default_expect {
+        for step in expanded_tests.iter_mut().flat_map(|t| &mut t.steps) {
+            apply_default_expect(&mut step.expect, default_expect);
+        }
+    }
+
+    // Replace step expectations with recorded outputs when replaying
+    for test in &mut expanded_tests {
+        for step in &mut test.steps {
+            if let Some(run) = spec
+                .replay
+                .iter()
+                .find(|r| r.test == test.name && r.step == step.name)
+            {
+                run.apply_to(&mut step.expect);
+            }
+        }
+    }
+
+    // Filter tests by name and tags
+    let filtered_tests: Vec<(usize, &Test)> = expanded_tests
         .iter()
         .enumerate()
-        .filter(|(_, test)| filter.map(|f| test.name.contains(f)).unwrap_or(true))
+        .filter(|(_, test)| filter.matches(test))
         .collect();
 
     // If no tests match the filter, return empty results
-    if filtered_tests.is_empty() {
-        return SpecResult { tests: vec![] };
+    if filtered_tests.is_empty() && matrix_errors.is_empty() {
+        return SpecResult {
+            tests: vec![],
+            recordings: vec![],
+        };
     }
 
     // Partition tests into serial and parallel groups, preserving indices
-    let (serial_tests, parallel_tests): (Vec<_>, Vec<_>) = filtered_tests
-        .into_iter()
-        .partition(|(_, test)| test.serial);
+    let force_serial = forces_serial_tests(db_manager.configs(), effective.no_capture);
+    let (serial_tests, mut parallel_tests) = partition_tests(filtered_tests, force_serial);
+
+    // Shuffle the order the parallel group is spawned in, to surface hidden
+    // inter-test dependencies. This only changes which test starts first; the
+    // group still runs concurrently. Serial tests keep their declared order,
+    // which is often intentional.
+    if let Some(seed) = effective.shuffle_seed {
+        shuffle_tests(&mut parallel_tests, seed);
+    }
 
     // Collect results with their indices
-    let mut indexed_results: Vec<(usize, TestResult)> = Vec::with_capacity(spec.tests.len());
+    let mut indexed_results: Vec<(usize, TestResult)> = Vec::with_capacity(expanded_tests.len());
 
     // Run serial tests first, in order
     for (idx, test) in serial_tests {
-        let result = run_test(test, &ctx, &db_manager, file_timeout, file_capture_fs_diff);
+        let result = run_test(
+            test,
+            &fixtures,
+            &ctx,
+            db_manager,
+            file_timeout,
+            file_capture_fs_diff,
+            effective.fixture_mode,
+        );
         indexed_results.push((idx, result));
     }
 
-    // Run parallel tests concurrently
+    // Run parallel tests concurrently. Tests naming the same `resource`
+    // take that resource's lock, so they run one at a time relative to each
+    // other while other tests keep running alongside them.
     if !parallel_tests.is_empty() {
+        let resource_locks: HashMap<&str, Mutex<()>> = parallel_tests
+            .iter()
+            .filter_map(|(_, test)| test.resource.as_deref())
+            .map(|resource| (resource, Mutex::new(())))
+            .collect();
         let ctx_ref = &ctx;
-        let db_ref = &db_manager;
+        let db_ref = db_manager;
+        let locks_ref = &resource_locks;
+        let fixtures_ref = &fixtures;
+        let fixture_mode = effective.fixture_mode;
         thread::scope(|s| {
             let handles: Vec<_> = parallel_tests
                 .iter()
                 .map(|(idx, test)| {
                     let idx = *idx;
                     s.spawn(move || {
+                        let _guard = test.resource.as_deref().map(|resource| {
+                            locks_ref[resource]
+                                .lock()
+                                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        });
                         (
                             idx,
-                            run_test(test, ctx_ref, db_ref, file_timeout, file_capture_fs_diff),
+                            run_test(
+                                test,
+                                fixtures_ref,
+                                ctx_ref,
+                                db_ref,
+                                file_timeout,
+                                file_capture_fs_diff,
+                                fixture_mode,
+                            ),
                         )
                     })
                 })
@@ -468,99 +1195,393 @@ fn run_spec_with_config(
     // Sort by original index to maintain declaration order
     indexed_results.sort_by_key(|(idx, _)| *idx);
     let mut results: Vec<TestResult> = indexed_results.into_iter().map(|(_, r)| r).collect();
+    results.extend(matrix_errors);
 
-    // Run file-level teardown (always runs)
-    if let Err(e) = run_teardown_steps(&spec.teardown, &ctx, &db_manager) {
+    // Run file-level teardown (always runs, unless only running setup)
+    if run_teardown && let Err(e) = run_teardown_steps(&teardown, &ctx, db_manager, &ctx.vars) {
         results.push(TestResult {
             name: "<teardown>".to_string(),
+            description: None,
             passed: false,
             skipped: false,
             skip_reason: None,
             duration: Duration::ZERO,
             failures: vec![format!("Teardown failed: {e}")],
             failed_step: None,
+            exit_code: None,
+            signal: None,
             fs_diff: None,
+            steps: vec![],
         });
     }
 
-    // Close database connections
-    db_manager.close_all();
+    // Close database connections (shared ones stay open for the next file)
+    if !shared_db.is_some_and(|shared| std::ptr::eq(shared, db_manager)) {
+        db_manager.close_all();
+    }
+
+    // Keep the temporary sandbox if the preserve policy asks for it
+    let preserve = spec
+        .sandbox
+        .preserve
+        .or(effective.preserve)
+        .unwrap_or_default();
+    let failed = results.iter().any(|r| !r.passed);
+    let keep = match preserve {
+        // --keep-on-failure wins over the file's policy
+        _ if failed && effective.keep_on_failure => true,
+        PreservePolicy::Never => false,
+        PreservePolicy::OnFailure => failed,
+        PreservePolicy::Always => true,
+    };
+    if keep && let Some(temp_dir) = ctx.temp_dir.take() {
+        eprintln!("Sandbox preserved at: {}", temp_dir.keep().display());
+    }
+
+    // Keep recordings in test order (tests may have run in parallel)
+    let mut recordings = ctx
+        .recorder
+        .take()
+        .map(|r| r.into_inner().expect("recorder poisoned"))
+        .unwrap_or_default();
+    recordings.sort_by_key(|r| expanded_tests.iter().position(|t| t.name == r.test));
 
-    SpecResult { tests: results }
+    SpecResult {
+        tests: results,
+        recordings,
+    }
 }
 
-fn run_test(
-    test: &Test,
-    ctx: &ExecutionContext,
-    db_manager: &ConnectionManager,
-    file_timeout: Option<u64>,
-    file_capture_fs_diff: bool,
-) -> TestResult {
-    let start = Instant::now();
-    let mut failures = Vec::new();
-    let mut failed_step: Option<StepFailure> = None;
+/// Shuffle tests in place; the same seed always produces the same order.
+fn shuffle_tests<T>(tests: &mut [T], seed: u64) {
+    use rand::SeedableRng;
+    use rand::seq::SliceRandom;
 
-    // Check skip_if and require conditions
-    match evaluate_conditions(test) {
-        ConditionResult::Skip(reason) => {
-            return TestResult {
-                name: test.name.clone(),
-                passed: true, // Skipped tests count as passed
-                skipped: true,
-                skip_reason: Some(reason),
-                duration: start.elapsed(),
-                failures: vec![],
-                failed_step: None,
-                fs_diff: None,
-            };
-        }
-        ConditionResult::Run => {}
-    }
+    tests.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
+}
 
-    // Reset database isolation for databases with per_file isolation
-    // This restores the post-file-setup state before each test
-    for db_name in db_manager.get_isolated_databases() {
-        if let Err(e) = db_manager.reset_isolation(&db_name) {
-            return TestResult {
-                name: test.name.clone(),
-                passed: false,
-                skipped: false,
-                skip_reason: None,
-                duration: start.elapsed(),
-                failures: vec![format!(
-                    "Failed to reset database isolation for '{}': {}",
-                    db_name, e
-                )],
-                failed_step: None,
-                fs_diff: None,
-            };
-        }
+/// Fill the fields a step's `expect` leaves unset from the file's
+/// `default_expect`. List assertions (`files`, `sql`) are combined, defaults first.
+fn apply_default_expect(expect: &mut Expect, default: &Expect) {
+    let Expect {
+        exit,
+        signal,
+        stdout,
+        stderr,
+        files,
+        tree,
+        sql,
+        max_duration_ms,
+        stdin_consumed,
+    } = default.clone();
+    expect.exit = expect.exit.or(exit);
+    expect.signal = expect.signal.or(signal);
+    expect.stdout = expect.stdout.take().or(stdout);
+    expect.stderr = expect.stderr.take().or(stderr);
+    expect.files = files.into_iter().chain(expect.files.drain(..)).collect();
+    expect.tree = expect.tree.take().or(tree);
+    expect.sql = sql.into_iter().chain(expect.sql.drain(..)).collect();
+    expect.max_duration_ms = expect.max_duration_ms.or(max_duration_ms);
+    expect.stdin_consumed = expect.stdin_consumed.or(stdin_consumed);
+}
+
+/// Expand a matrix test into one test per combination of dimension values.
+///
+/// Dimensions are combined in sorted key order, and each combination is named
+/// `name[key=value,...]`. Every `${matrix.key}` reference in the test is replaced
+/// with that combination's value. Tests without a matrix are returned unchanged.
+pub fn expand_matrix(test: &Test) -> Result<Vec<Test>, String> {
+    let Some(matrix) = &test.matrix else {
+        return Ok(vec![test.clone()]);
+    };
+
+    let mut keys: Vec<&String> = matrix.keys().collect();
+    keys.sort();
+
+    // Cartesian product of all dimensions, in key order
+    let mut combinations: Vec<Vec<(&str, &str)>> = vec![vec![]];
+    for key in &keys {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combo| {
+                matrix[*key].iter().map(move |value| {
+                    let mut combo = combo.clone();
+                    combo.push((key.as_str(), value.as_str()));
+                    combo
+                })
+            })
+            .collect();
     }
 
-    // Determine if we should capture fs diff (test overrides file)
-    let capture_fs_diff = test.capture_fs_diff.unwrap_or(file_capture_fs_diff);
+    let template = serde_json::to_value(test).map_err(|e| e.to_string())?;
+    combinations
+        .into_iter()
+        .map(|combo| {
+            let mut value = template.clone();
+            substitute_matrix_values(&mut value, &combo);
+            let mut expanded: Test = serde_json::from_value(value).map_err(|e| e.to_string())?;
+            let label: Vec<String> = combo.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            expanded.name = format!("{}[{}]", test.name, label.join(","));
+            expanded.matrix = None;
+            Ok(expanded)
+        })
+        .collect()
+}
 
-    // Test-level setup
-    if let Err(e) = run_setup_steps(&test.setup, ctx, db_manager) {
-        return TestResult {
-            name: test.name.clone(),
-            passed: false,
+/// Whether every test in a file must run serially. Per-test database isolation
+/// resets shared state before each test, and under --no-capture parallel tests
+/// would share the terminal.
+pub fn forces_serial_tests(databases: &HashMap<String, DatabaseConfig>, no_capture: bool) -> bool {
+    no_capture
+        || databases
+            .values()
+            .any(|db| db.isolation == DbIsolation::PerTest)
+}
+
+/// Tests paired with a key, such as their index, that travels with them.
+pub type KeyedTests<'a, K> = Vec<(K, &'a Test)>;
+
+/// Split a file's tests into the group run first, one at a time in the given
+/// order, and the group run in parallel.
+pub fn partition_tests<K>(
+    tests: KeyedTests<'_, K>,
+    force_serial: bool,
+) -> (KeyedTests<'_, K>, KeyedTests<'_, K>) {
+    tests
+        .into_iter()
+        .partition(|(_, test)| test.serial || force_serial)
+}
+
+/// Replace `${matrix.key}` (and same-named `${vars.key}`) references in every
+/// string within a JSON value.
+fn substitute_matrix_values(value: &mut serde_json::Value, combo: &[(&str, &str)]) {
+    match value {
+        serde_json::Value::String(s) => {
+            for (key, val) in combo {
+                // A matrix dimension also shadows a spec/suite variable of the same name
+                for reference in [format!("${{matrix.{key}}}"), format!("${{vars.{key}}}")] {
+                    if s.contains(&reference) {
+                        *s = s.replace(&reference, val);
+                    }
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                substitute_matrix_values(item, combo);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for item in map.values_mut() {
+                substitute_matrix_values(item, combo);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replace `${vars.NAME}` references in every string within `item`.
+///
+/// Items without any reference are returned as a plain clone.
+fn substitute_vars<T>(item: &T, vars: &HashMap<String, String>) -> Result<T, String>
+where
+    T: Clone + serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn walk(value: &mut serde_json::Value, vars: &HashMap<String, String>) -> Result<(), String> {
+        match value {
+            serde_json::Value::String(s) => *s = env::interpolate_vars(s, vars)?,
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    walk(item, vars)?;
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for item in map.values_mut() {
+                    walk(item, vars)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    let mut value = serde_json::to_value(item).map_err(|e| e.to_string())?;
+    if !value.to_string().contains("${vars.") {
+        return Ok(item.clone());
+    }
+    walk(&mut value, vars)?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// `vars` plus a placeholder for every `capture_as` name in `item`.
+///
+/// Each placeholder maps `${vars.NAME}` to itself, so references to captured
+/// values survive up-front substitution and are resolved once captured.
+fn defer_captured<T: serde::Serialize>(
+    vars: &HashMap<String, String>,
+    item: &T,
+) -> HashMap<String, String> {
+    fn walk(value: &serde_json::Value, vars: &mut HashMap<String, String>) {
+        match value {
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    walk(item, vars);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                if let Some(serde_json::Value::String(name)) = map.get("capture_as") {
+                    vars.insert(name.clone(), format!("${{vars.{name}}}"));
+                }
+                if let Some(serde_json::Value::Object(captures)) = map.get("capture") {
+                    for name in captures.keys() {
+                        vars.insert(name.clone(), format!("${{vars.{name}}}"));
+                    }
+                }
+                for item in map.values() {
+                    walk(item, vars);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut vars = vars.clone();
+    if let Ok(value) = serde_json::to_value(item) {
+        walk(&value, &mut vars);
+    }
+    vars
+}
+
+fn run_test(
+    test: &Test,
+    fixtures: &HashMap<String, Vec<SetupStep>>,
+    ctx: &ExecutionContext,
+    db_manager: &ConnectionManager,
+    file_timeout: Option<u64>,
+    file_capture_fs_diff: bool,
+    fixture_mode: FixtureMode,
+) -> TestResult {
+    let start = Instant::now();
+    let mut failures = Vec::new();
+    let mut failed_step: Option<StepFailure> = None;
+    let mut steps = Vec::new();
+
+    // Check skip, platform, skip_if and require conditions
+    match evaluate_conditions(test) {
+        ConditionResult::Skip(reason) => {
+            return TestResult {
+                name: test.name.clone(),
+                description: test.description.clone(),
+                passed: true, // Skipped tests count as passed
+                skipped: true,
+                skip_reason: Some(reason),
+                duration: start.elapsed(),
+                failures: vec![],
+                failed_step: None,
+                exit_code: None,
+                signal: None,
+                fs_diff: None,
+                steps: vec![],
+            };
+        }
+        ConditionResult::Run => {}
+    }
+
+    // Test-level setup: the steps of each referenced fixture, then the test's own
+    let setup: Vec<SetupStep> = test
+        .use_fixtures
+        .iter()
+        .filter_map(|name| fixtures.get(name))
+        .flatten()
+        .chain(&test.setup)
+        .cloned()
+        .collect();
+
+    // Values captured so far: the file's, then those of this test's setup commands
+    let mut vars = ctx.vars.clone();
+
+    // When debugging fixtures, run only the requested phase and skip the steps
+    if fixture_mode != FixtureMode::Full {
+        let phase = if fixture_mode == FixtureMode::SetupOnly {
+            run_setup_steps(&setup, ctx, db_manager, &mut vars)
+                .map_err(|e| format!("Test setup failed: {e}"))
+        } else {
+            run_teardown_steps(&test.teardown, ctx, db_manager, &vars)
+                .map_err(|e| format!("Test teardown failed: {e}"))
+        };
+        return TestResult {
+            name: test.name.clone(),
+            description: test.description.clone(),
+            passed: phase.is_ok(),
+            skipped: false,
+            skip_reason: None,
+            duration: start.elapsed(),
+            failures: phase.err().into_iter().collect(),
+            failed_step: None,
+            exit_code: None,
+            signal: None,
+            fs_diff: None,
+            steps: vec![],
+        };
+    }
+
+    // Reset database isolation for databases with per_file or per_test isolation
+    // This restores the post-file-setup state before each test
+    for db_name in db_manager.get_isolated_databases() {
+        if let Err(e) = db_manager.reset_isolation(&db_name) {
+            return TestResult {
+                name: test.name.clone(),
+                description: test.description.clone(),
+                passed: false,
+                skipped: false,
+                skip_reason: None,
+                duration: start.elapsed(),
+                failures: vec![format!(
+                    "Failed to reset database isolation for '{}': {}",
+                    db_name, e
+                )],
+                failed_step: None,
+                exit_code: None,
+                signal: None,
+                fs_diff: None,
+                steps: vec![],
+            };
+        }
+    }
+
+    // Determine if we should capture fs diff (test overrides file)
+    let capture_fs_diff = test.capture_fs_diff.unwrap_or(file_capture_fs_diff);
+
+    // Test-level setup
+    if let Err(e) = run_setup_steps(&setup, ctx, db_manager, &mut vars) {
+        return TestResult {
+            name: test.name.clone(),
+            description: test.description.clone(),
+            passed: false,
             skipped: false,
             skip_reason: None,
             duration: start.elapsed(),
             failures: vec![format!("Test setup failed: {e}")],
             failed_step: None,
+            exit_code: None,
+            signal: None,
             fs_diff: None,
+            steps: vec![],
         };
     }
 
-    // Capture filesystem state before steps (if enabled)
-    let snapshot_before = if capture_fs_diff {
+    // Capture filesystem state before steps (if enabled, or needed for allowed_changes)
+    let snapshot_before = if capture_fs_diff || !test.allowed_changes.is_empty() {
         Some(snapshot_filesystem(&ctx.sandbox_dir))
     } else {
         None
     };
 
+    let outside_before = ctx
+        .outside_dir
+        .as_ref()
+        .map(|dir| snapshot_outside(dir, &ctx.sandbox_dir));
+
     // Determine timeout - test timeout overrides file timeout overrides default
     let timeout_secs = test
         .timeout
@@ -572,41 +1593,124 @@ fn run_test(
     let is_multi_step = test.steps.len() > 1 || test.steps.first().is_some_and(|s| s.name != "run");
 
     for (step_index, step) in test.steps.iter().enumerate() {
-        // Step-level setup
-        if let Err(e) = run_setup_steps(&step.setup, ctx, db_manager) {
-            let msg = if is_multi_step {
-                format!("Step '{}' [{}] setup failed: {e}", step.name, step_index)
-            } else {
-                format!("Setup failed: {e}")
-            };
-            failures.push(msg);
-            failed_step = Some(StepFailure {
-                name: step.name.clone(),
-                index: step_index,
+        let step_start = Instant::now();
+        let step_result = |passed: bool, output: Option<&CommandOutput>| StepResult {
+            name: step.name.clone(),
+            passed,
+            skipped: false,
+            exit_code: output.and_then(|o| o.exit_code),
+            signal: output.and_then(|o| o.signal),
+            duration: step_start.elapsed(),
+        };
+
+        if !step.runs_on_current_platform() {
+            steps.push(StepResult {
+                skipped: true,
+                ..step_result(true, None)
             });
-            break; // Skip remaining steps
+            continue;
+        }
+
+        // Step-level setup, then resolve values captured by any setup so far
+        let prepared = run_setup_steps(&step.setup, ctx, db_manager, &mut vars).and_then(|()| {
+            Ok((
+                substitute_vars(&step.run, &vars)?,
+                substitute_vars(&step.expect, &vars)?,
+            ))
+        });
+        let (mut run, expect) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                let msg = if is_multi_step {
+                    format!("Step '{}' [{}] setup failed: {e}", step.name, step_index)
+                } else {
+                    format!("Setup failed: {e}")
+                };
+                failures.push(msg);
+                failed_step = Some(StepFailure {
+                    name: step.name.clone(),
+                    description: step.description.clone(),
+                    index: step_index,
+                    phase: StepPhase::Setup,
+                });
+                steps.push(step_result(false, None));
+                break; // Skip remaining steps
+            }
+        };
+
+        // A deterministic test reruns from a copy of the sandbox as it is now
+        let baseline = if test.deterministic {
+            match copy_sandbox(&ctx.sandbox_dir) {
+                Ok(dir) => Some(dir),
+                Err(e) => {
+                    failures.push(format!(
+                        "Failed to copy sandbox for deterministic check: {e}"
+                    ));
+                    failed_step = Some(StepFailure {
+                        name: step.name.clone(),
+                        description: step.description.clone(),
+                        index: step_index,
+                        phase: StepPhase::Setup,
+                    });
+                    steps.push(step_result(false, None));
+                    break;
+                }
+            }
+        } else {
+            None
+        };
+
+        // Steps without their own cwd inherit the test-level cwd
+        if run.cwd.is_none() {
+            run.cwd = test.cwd.clone();
         }
 
         // Run the step command
-        let step_failed = match run_command(&step.run, ctx, &test.env, timeout) {
+        if ctx.no_capture {
+            eprintln!("--- {} / {} ---", test.name, step.name);
+        }
+        let mut step_failures = Vec::new();
+        let command_output = run_command(&run, ctx, &test.env, timeout);
+        let mut failed_phase = match &command_output {
             Ok(output) => {
+                ctx.record(test, step, output);
+
                 // Check step assertions
-                let mut step_failures = Vec::new();
-                check_expectations(&step.expect, &output, ctx, db_manager, &mut step_failures);
+                let mut assertion_failures = Vec::new();
+                if let Some(baseline) = &baseline {
+                    check_deterministic(
+                        &run,
+                        &ctx.with_sandbox_dir(baseline.path()),
+                        &test.env,
+                        timeout,
+                        output,
+                        &ctx.sandbox_dir,
+                        &mut assertion_failures,
+                    );
+                }
+                check_expectations(&expect, output, ctx, db_manager, &mut assertion_failures);
+                for (name, source) in &step.capture {
+                    match capture_step_output(source, output) {
+                        Ok(value) => {
+                            vars.insert(name.clone(), value);
+                        }
+                        Err(e) => assertion_failures.push(format!("capture '{name}': {e}")),
+                    }
+                }
 
-                if !step_failures.is_empty() {
+                if !assertion_failures.is_empty() {
                     // Prefix failures with step info for multi-step tests
-                    for f in step_failures {
+                    for f in assertion_failures {
                         let msg = if is_multi_step {
                             format!("Step '{}' [{}]: {f}", step.name, step_index)
                         } else {
                             f
                         };
-                        failures.push(msg);
+                        step_failures.push(msg);
                     }
-                    true
+                    Some(StepPhase::Assertion)
                 } else {
-                    false
+                    None
                 }
             }
             Err(e) => {
@@ -618,26 +1722,51 @@ fn run_test(
                 } else {
                     format!("Command execution failed: {e}")
                 };
-                failures.push(msg);
-                true
+                step_failures.push(msg);
+                Some(StepPhase::Command)
             }
         };
 
+        // A step expected to fail passes when its command or assertions failed
+        if step.expect_failure {
+            if failed_phase.take().is_some() {
+                step_failures.clear();
+            } else {
+                step_failures.push(if is_multi_step {
+                    format!(
+                        "Step '{}' [{}]: step expected to fail but passed",
+                        step.name, step_index
+                    )
+                } else {
+                    "step expected to fail but passed".to_string()
+                });
+                failed_phase = Some(StepPhase::Assertion);
+            }
+        }
+        failures.extend(step_failures);
+
         // Step-level teardown (always runs for this step, even if assertions failed)
-        if let Err(e) = run_teardown_steps(&step.teardown, ctx, db_manager) {
+        if let Err(e) = run_teardown_steps(&step.teardown, ctx, db_manager, &vars) {
             let msg = if is_multi_step {
                 format!("Step '{}' [{}] teardown failed: {e}", step.name, step_index)
             } else {
                 format!("Teardown failed: {e}")
             };
             failures.push(msg);
+            failed_phase.get_or_insert(StepPhase::Teardown);
         }
+        steps.push(step_result(
+            failed_phase.is_none(),
+            command_output.as_ref().ok(),
+        ));
 
         // If step failed, record it and skip remaining steps
-        if step_failed {
+        if let Some(phase) = failed_phase {
             failed_step = Some(StepFailure {
                 name: step.name.clone(),
+                description: step.description.clone(),
                 index: step_index,
+                phase,
             });
             break;
         }
@@ -649,23 +1778,100 @@ fn run_test(
         compute_fs_diff(&before, &after)
     });
 
+    // Check that every change is covered by the allowlist
+    if !test.allowed_changes.is_empty()
+        && let Some(diff) = &fs_diff
+    {
+        check_allowed_changes(&test.allowed_changes, diff, &mut failures);
+    }
+    let fs_diff = if capture_fs_diff { fs_diff } else { None };
+
+    // Check that nothing escaped the sandbox into its parent directory
+    if let (Some(before), Some(dir)) = (&outside_before, &ctx.outside_dir) {
+        let diff = compute_fs_diff(before, &snapshot_outside(dir, &ctx.sandbox_dir));
+        for (kind, paths) in [
+            ("added", &diff.added),
+            ("removed", &diff.removed),
+            ("modified", &diff.modified),
+        ] {
+            for path in paths {
+                failures.push(format!(
+                    "forbid_outside_writes: {kind} path outside the sandbox: {}",
+                    dir.join(path).display()
+                ));
+            }
+        }
+    }
+
     // Test-level teardown (always runs)
-    if let Err(e) = run_teardown_steps(&test.teardown, ctx, db_manager) {
+    if let Err(e) = run_teardown_steps(&test.teardown, ctx, db_manager, &vars) {
         failures.push(format!("Test teardown failed: {e}"));
     }
 
+    // Report the failing step's exit status, or else the last step that ran
+    let reported_step = match &failed_step {
+        Some(failure) => steps.get(failure.index),
+        None => steps.iter().rev().find(|s| !s.skipped),
+    };
+    let (exit_code, signal) = reported_step.map_or((None, None), |s| (s.exit_code, s.signal));
+
     // For single-step tests (implicit "run" step), don't report failed_step
     let failed_step = if is_multi_step { failed_step } else { None };
 
     TestResult {
         name: test.name.clone(),
+        description: test.description.clone(),
         passed: failures.is_empty(),
         skipped: false,
         skip_reason: None,
         duration: start.elapsed(),
         failures,
         failed_step,
+        exit_code,
+        signal,
         fs_diff,
+        steps,
+    }
+}
+
+/// Shell for `shell: true` commands when none is configured.
+const DEFAULT_SHELL: &str = if cfg!(windows) { "cmd" } else { "sh" };
+
+/// The flag that makes `program` run its next argument as a command: `/C` for
+/// `cmd`, `-c` for `sh`, `bash`, and other POSIX shells.
+fn shell_command_flag(program: &str) -> &'static str {
+    let name = Path::new(program)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_ascii_lowercase());
+    if name.as_deref() == Some("cmd") {
+        "/C"
+    } else {
+        "-c"
+    }
+}
+
+/// Quote `arg` as one word for a POSIX shell. Words made only of safe
+/// characters pass through; others are single-quoted, with each `'` written
+/// as `'\''`.
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote `arg` as one word for `cmd /C`: double-quoted, with each `"` doubled,
+/// if it contains whitespace, quotes, or cmd operators.
+fn cmd_quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"&|<>^()".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("\"{}\"", arg.replace('"', "\"\""))
     }
 }
 
@@ -676,6 +1882,13 @@ struct CommandOutput {
     signal: Option<i32>,
     stdout: String,
     stderr: String,
+    /// Whether stdout/stderr were captured (not streamed to the terminal).
+    stdout_captured: bool,
+    stderr_captured: bool,
+    /// Wall-clock time from spawn until the process exited.
+    duration: Duration,
+    /// Whether all stdin was written before the process closed it (None if no stdin).
+    stdin_consumed: Option<bool>,
 }
 
 fn run_command(
@@ -692,18 +1905,38 @@ fn run_command(
     for (k, v) in &run.env {
         effective_env.insert(k.clone(), v.clone());
     }
+    ctx.apply_locale(&mut effective_env);
 
-    // Interpolate environment variables in cmd using effective env
+    // Interpolate environment variables in cmd and args using effective env
     let cmd_path = env::interpolate_env_with(&run.cmd, &effective_env)?;
+    let visible_env = ctx.visible_env(&effective_env);
+    let args = run
+        .args
+        .iter()
+        .map(|arg| env::interpolate_arg(arg, &visible_env))
+        .collect::<Result<Vec<_>, _>>()?;
 
     let mut cmd = if run.shell {
-        let mut c = Command::new("sh");
-        c.arg("-c");
-        c.arg(format!("{} {}", cmd_path, run.args.join(" ")));
+        let program = run
+            .shell_program
+            .as_deref()
+            .or(ctx.shell_program.as_deref())
+            .unwrap_or(DEFAULT_SHELL);
+        // `cmd` is shell syntax; each argument stays a single word
+        let flag = shell_command_flag(program);
+        let quote = if flag == "/C" { cmd_quote } else { shell_quote };
+        let mut line = cmd_path;
+        for arg in &args {
+            line.push(' ');
+            line.push_str(&quote(arg));
+        }
+        let mut c = Command::new(program);
+        c.arg(flag);
+        c.arg(line);
         c
     } else {
         let mut c = Command::new(&cmd_path);
-        c.args(&run.args);
+        c.args(&args);
         c
     };
 
@@ -716,41 +1949,112 @@ fn run_command(
     cmd.current_dir(&cwd);
 
     // Set environment (using already-merged effective_env)
-    if !ctx.inherit_env {
-        cmd.env_clear();
-    }
-    for (k, v) in &effective_env {
-        cmd.env(k, v);
-    }
+    ctx.apply_env(&mut cmd, &effective_env);
+
+    // Resolve stdin bytes (inline string or file contents)
+    let stdin_data: Option<Vec<u8>> = match (&run.stdin, &run.stdin_file) {
+        (Some(_), Some(_)) => {
+            return Err("stdin and stdin_file are mutually exclusive".to_string());
+        }
+        (Some(data), None) => Some(data.as_bytes().to_vec()),
+        (None, Some(path)) => Some(
+            std::fs::read(ctx.resolve_path(path))
+                .map_err(|e| format!("Failed to read stdin_file {}: {e}", path.display()))?,
+        ),
+        (None, None) => None,
+    };
 
     // Setup stdin
-    if run.stdin.is_some() {
+    if stdin_data.is_some() {
         cmd.stdin(Stdio::piped());
+    } else if ctx.no_capture {
+        cmd.stdin(Stdio::inherit());
     } else {
         cmd.stdin(Stdio::null());
     }
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
+    cmd.stdout(output_stdio(
+        ctx,
+        run.stdout_file.as_deref(),
+        "stdout_file",
+    )?);
+    cmd.stderr(output_stdio(
+        ctx,
+        run.stderr_file.as_deref(),
+        "stderr_file",
+    )?);
+
+    // Run the command in its own process group so a timeout can kill
+    // any grandchildren it spawned along with it. Under --no-capture the child
+    // stays in our session instead, keeping the terminal as its controlling tty
+    // so it can open /dev/tty and receives Ctrl-C along with us.
+    #[cfg(unix)]
+    if !ctx.no_capture {
+        use std::os::unix::process::CommandExt;
+        // SAFETY: setsid(2) is async-signal-safe and touches no parent state.
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
 
     let mut child = cmd.spawn().map_err(|e| format!("Failed to spawn: {e}"))?;
 
-    // Write stdin if provided
-    if let Some(stdin_data) = &run.stdin
-        && let Some(mut stdin) = child.stdin.take()
-    {
-        stdin
-            .write_all(stdin_data.as_bytes())
-            .map_err(|e| format!("Failed to write stdin: {e}"))?;
-    }
+    // Drain stdout and stderr while the child runs so it never blocks on a
+    // full pipe, keeping at most max_output_bytes of each
+    let stdout_reader = child
+        .stdout
+        .take()
+        .map(|pipe| spawn_capped_reader(pipe, ctx.max_output_bytes));
+    let stderr_reader = child
+        .stderr
+        .take()
+        .map(|pipe| spawn_capped_reader(pipe, ctx.max_output_bytes));
+
+    // Write stdin from a separate thread so large inputs don't deadlock
+    // against a child that is blocked writing to a full stdout pipe. The
+    // writer is joined once the child exits, so a background process that
+    // inherited stdin without reading it keeps the step waiting until that
+    // process exits or closes the pipe (as it does for stdout and stderr).
+    let stdin_writer = match (stdin_data, child.stdin.take()) {
+        (Some(data), Some(mut stdin)) => Some(thread::spawn(move || {
+            // A child that exits without reading all input closes the pipe early;
+            // report that as unconsumed input rather than an execution error
+            match stdin.write_all(&data) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+                Err(e) => Err(e),
+            }
+        })),
+        _ => None,
+    };
 
     // Wait with timeout
     let start = Instant::now();
     loop {
         match child.try_wait() {
             Ok(Some(status)) => {
-                let output = child
-                    .wait_with_output()
-                    .map_err(|e| format!("Failed to read output: {e}"))?;
+                let duration = start.elapsed();
+                let stdin_consumed = match stdin_writer {
+                    Some(writer) => Some(
+                        writer
+                            .join()
+                            .map_err(|_| "Stdin writer thread panicked".to_string())?
+                            .map_err(|e| format!("Failed to write stdin: {e}"))?,
+                    ),
+                    None => None,
+                };
+                let stdout = match &run.stdout_file {
+                    Some(path) => read_output_file(ctx, path, "stdout_file")?,
+                    None => join_capped_reader(stdout_reader)?,
+                };
+                let stderr = match &run.stderr_file {
+                    Some(path) => read_output_file(ctx, path, "stderr_file")?,
+                    None => join_capped_reader(stderr_reader)?,
+                };
 
                 // Get exit code and signal
                 let exit_code = status.code();
@@ -765,13 +2069,17 @@ fn run_command(
                 return Ok(CommandOutput {
                     exit_code,
                     signal,
-                    stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                    stdout,
+                    stderr,
+                    stdout_captured: !ctx.no_capture || run.stdout_file.is_some(),
+                    stderr_captured: !ctx.no_capture || run.stderr_file.is_some(),
+                    duration,
+                    stdin_consumed,
                 });
             }
             Ok(None) => {
                 if start.elapsed() > timeout {
-                    let _ = child.kill();
+                    terminate_child(&mut child, ctx.kill_grace, !ctx.no_capture);
                     return Err(format!("Command timed out after {}s", timeout.as_secs()));
                 }
                 std::thread::sleep(Duration::from_millis(10));
@@ -781,6 +2089,109 @@ fn run_command(
     }
 }
 
+/// A pipe reader thread yielding the kept bytes and the number discarded.
+type CappedReader = thread::JoinHandle<std::io::Result<(Vec<u8>, u64)>>;
+
+/// Read to EOF, keeping the first `max_bytes` bytes and counting the rest.
+fn read_capped(mut reader: impl Read, max_bytes: u64) -> std::io::Result<(Vec<u8>, u64)> {
+    let mut kept = Vec::new();
+    (&mut reader).take(max_bytes).read_to_end(&mut kept)?;
+    let discarded = std::io::copy(&mut reader, &mut std::io::sink())?;
+    Ok((kept, discarded))
+}
+
+/// Captured output as text, with a marker for any discarded bytes.
+fn capped_text(kept: &[u8], discarded: u64) -> String {
+    let mut text = String::from_utf8_lossy(kept).into_owned();
+    if discarded > 0 {
+        text.push_str(&format!("…[truncated {discarded} bytes]"));
+    }
+    text
+}
+
+/// Read a pipe to EOF on a separate thread (see `read_capped`).
+fn spawn_capped_reader(pipe: impl Read + Send + 'static, max_bytes: u64) -> CappedReader {
+    thread::spawn(move || read_capped(pipe, max_bytes))
+}
+
+/// Collect a capped reader's output as text, marking any discarded bytes.
+fn join_capped_reader(reader: Option<CappedReader>) -> Result<String, String> {
+    let Some(reader) = reader else {
+        return Ok(String::new());
+    };
+    let (kept, discarded) = reader
+        .join()
+        .map_err(|_| "Output reader thread panicked".to_string())?
+        .map_err(|e| format!("Failed to read output: {e}"))?;
+    Ok(capped_text(&kept, discarded))
+}
+
+/// Where a child's stdout or stderr goes: a pipe, the terminal under
+/// `--no-capture`, or the sandbox file named by `stdout_file` / `stderr_file`
+/// (created or truncated).
+fn output_stdio(ctx: &ExecutionContext, file: Option<&Path>, field: &str) -> Result<Stdio, String> {
+    match file {
+        Some(path) => std::fs::File::create(ctx.resolve_path(path))
+            .map(Stdio::from)
+            .map_err(|e| format!("Failed to create {field} {}: {e}", path.display())),
+        None if ctx.no_capture => Ok(Stdio::inherit()),
+        None => Ok(Stdio::piped()),
+    }
+}
+
+/// Read a `stdout_file` / `stderr_file` back for output matching, capped like
+/// piped output. Only the kept prefix is read; the rest is counted from the size.
+fn read_output_file(ctx: &ExecutionContext, path: &Path, field: &str) -> Result<String, String> {
+    let read = || -> std::io::Result<String> {
+        let file = std::fs::File::open(ctx.resolve_path(path))?;
+        let len = file.metadata()?.len();
+        let mut kept = Vec::new();
+        file.take(ctx.max_output_bytes).read_to_end(&mut kept)?;
+        Ok(capped_text(&kept, len.saturating_sub(kept.len() as u64)))
+    };
+    read().map_err(|e| format!("Failed to read {field} {}: {e}", path.display()))
+}
+
+/// Terminate a timed-out child process.
+///
+/// On Unix, SIGTERM is sent first so processes can clean up, then SIGKILL to
+/// anything still running after the grace period. When the child leads its own
+/// process group (`own_group`), both signals go to the whole group; otherwise
+/// only to the child. Elsewhere, kills the child immediately.
+fn terminate_child(child: &mut std::process::Child, grace: Duration, own_group: bool) {
+    #[cfg(unix)]
+    {
+        let pid = child.id() as libc::pid_t;
+        let pgid = -pid;
+        // SAFETY: kill(2) has no memory-safety preconditions; the target is our
+        // unreaped child (or the group it leads), so its id cannot have been recycled.
+        let target = if own_group { pgid } else { pid };
+        let sent = unsafe { libc::kill(target, libc::SIGTERM) } == 0;
+        if sent {
+            let deadline = Instant::now() + grace;
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        // Grandchildren may outlive the group leader; kill whatever remains.
+        // SAFETY: as above; a reaped leader's group id stays reserved while
+        // any member is still alive.
+        if own_group {
+            unsafe {
+                libc::kill(pgid, libc::SIGKILL);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (grace, own_group);
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
 fn check_expectations(
     expect: &Expect,
     output: &CommandOutput,
@@ -793,9 +2204,10 @@ fn check_expectations(
         // Expecting a signal termination
         match output.signal {
             Some(actual_signal) => {
-                if actual_signal != expected_signal {
+                if actual_signal != expected_signal.0 {
                     failures.push(format!(
-                        "Signal: expected {expected_signal}, got {actual_signal}"
+                        "Signal: expected {expected_signal}, got {}",
+                        Signal(actual_signal)
                     ));
                 }
             }
@@ -811,10 +2223,10 @@ fn check_expectations(
         }
     } else {
         // Expecting normal exit (default behavior)
-        let expected_exit = expect.exit.unwrap_or(0);
+        let expected_exit = expect.exit.unwrap_or(ExitMatch::Code(0));
         match output.exit_code {
             Some(actual_exit) => {
-                if actual_exit != expected_exit {
+                if !expected_exit.matches(actual_exit) {
                     failures.push(format!(
                         "Exit code: expected {expected_exit}, got {actual_exit}"
                     ));
@@ -824,7 +2236,7 @@ fn check_expectations(
                 // Process was killed by a signal when we expected an exit code
                 let signal_info = output
                     .signal
-                    .map(|s| format!("signal {s}"))
+                    .map(|s| format!("signal {}", Signal(s)))
                     .unwrap_or_else(|| "unknown cause".to_string());
                 failures.push(format!(
                     "Exit code: expected {expected_exit}, but process was terminated by {signal_info}"
@@ -833,20 +2245,72 @@ fn check_expectations(
         }
     }
 
-    // Check stdout
+    // Check runtime budget
+    if let Some(max_ms) = expect.max_duration_ms {
+        let took_ms = output.duration.as_millis();
+        if took_ms > u128::from(max_ms) {
+            failures.push(format!(
+                "Duration: expected <= {max_ms}ms, took {took_ms}ms"
+            ));
+        }
+    }
+
+    // Check stdin consumption (no stdin counts as fully consumed)
+    if let Some(expected) = expect.stdin_consumed {
+        let consumed = output.stdin_consumed.unwrap_or(true);
+        if expected && !consumed {
+            failures.push(
+                "Stdin: expected all input to be consumed, but the process closed stdin early"
+                    .to_string(),
+            );
+        } else if !expected && consumed {
+            failures.push(
+                "Stdin: expected the process to close stdin early, but all input was written"
+                    .to_string(),
+            );
+        }
+    }
+
+    // Check stdout (not captured under --no-capture)
     if let Some(matcher) = &expect.stdout
-        && let Err(e) = check_output_match("stdout", &output.stdout, matcher)
+        && output.stdout_captured
+        && let Err(e) = check_output_match(
+            "stdout",
+            &output.stdout,
+            matcher,
+            ctx.strip_ansi,
+            ctx.normalize_newlines,
+        )
     {
         failures.push(e);
     }
 
-    // Check stderr
+    // Check stderr (not captured under --no-capture)
     if let Some(matcher) = &expect.stderr
-        && let Err(e) = check_output_match("stderr", &output.stderr, matcher)
+        && output.stderr_captured
+        && let Err(e) = check_output_match(
+            "stderr",
+            &output.stderr,
+            matcher,
+            ctx.strip_ansi,
+            ctx.normalize_newlines,
+        )
     {
         failures.push(e);
     }
 
+    // Under strict_stderr, stderr must be empty unless the step has a matcher for it
+    if ctx.strict_stderr
+        && expect.stderr.is_none()
+        && output.stderr_captured
+        && !output.stderr.is_empty()
+    {
+        failures.push(format!(
+            "stderr: expected no output (strict_stderr)\n  got: {:?}",
+            output.stderr
+        ));
+    }
+
     // Check files
     for file_expect in &expect.files {
         check_file_expect(file_expect, ctx, failures);
@@ -863,44 +2327,160 @@ fn check_expectations(
     }
 }
 
-fn check_output_match(name: &str, actual: &str, matcher: &OutputMatch) -> Result<(), String> {
-    match matcher {
-        OutputMatch::Exact(expected) => {
-            if actual != expected {
-                Err(format!(
-                    "{name}: expected exact match\n  expected: {expected:?}\n  got: {actual:?}"
-                ))
-            } else {
-                Ok(())
-            }
+/// Check output against a matcher. `strip_ansi` and `normalize_newlines` are
+/// the defaults for matchers that don't set their own.
+fn check_output_match(
+    name: &str,
+    actual: &str,
+    matcher: &OutputMatch,
+    strip_ansi: bool,
+    normalize_newlines: bool,
+) -> Result<(), String> {
+    let (strip_ansi, normalize_newlines) = match matcher {
+        OutputMatch::Structured(s) => (
+            s.strip_ansi.unwrap_or(strip_ansi),
+            s.normalize_newlines.unwrap_or(normalize_newlines),
+        ),
+        OutputMatch::Exact(_) => (strip_ansi, normalize_newlines),
+    };
+    let normalized;
+    let (actual, matcher) = if normalize_newlines {
+        normalized = (
+            normalize_line_endings(actual),
+            normalize_expected_newlines(matcher),
+        );
+        (normalized.0.as_str(), &normalized.1)
+    } else {
+        (actual, matcher)
+    };
+    if strip_ansi {
+        let stripped = strip_ansi_codes(actual);
+        if stripped != actual {
+            return check_output_match(name, &stripped, matcher, false, false).map_err(|e| {
+                format!(
+                    "{e}\n  raw: {actual:?}\n  (ANSI escape codes were stripped before matching)"
+                )
+            });
+        }
+    }
+
+    match matcher {
+        OutputMatch::Exact(expected) => {
+            if actual != expected {
+                Err(format!(
+                    "{name}: expected exact match\n  expected: {expected:?}\n  got: {actual:?}"
+                ))
+            } else {
+                Ok(())
+            }
         }
         OutputMatch::Structured(s) => check_structured_match(name, actual, s),
     }
 }
 
+/// Convert `\r\n` and lone `\r` line endings to `\n`.
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// A copy of `matcher` with line endings normalized in its expected text.
+fn normalize_expected_newlines(matcher: &OutputMatch) -> OutputMatch {
+    match matcher {
+        OutputMatch::Exact(expected) => OutputMatch::Exact(normalize_line_endings(expected)),
+        OutputMatch::Structured(s) => {
+            let normalize = |text: &Option<String>| text.as_deref().map(normalize_line_endings);
+            let normalize_all =
+                |texts: &[String]| texts.iter().map(|t| normalize_line_endings(t)).collect();
+            OutputMatch::Structured(OutputMatchStructured {
+                equals: normalize(&s.equals),
+                contains: normalize(&s.contains),
+                contains_all: normalize_all(&s.contains_all),
+                contains_any: normalize_all(&s.contains_any),
+                matches: normalize(&s.matches),
+                ..s.clone()
+            })
+        }
+    }
+}
+
+/// Remove ANSI escape sequences: CSI (colors, cursor movement), OSC (titles,
+/// hyperlinks), and two-byte escapes.
+fn strip_ansi_codes(text: &str) -> String {
+    static ANSI: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+        regex::Regex::new(
+            r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]",
+        )
+        .expect("valid ANSI regex")
+    });
+    ANSI.replace_all(text, "").into_owned()
+}
+
 fn check_structured_match(
     name: &str,
     actual: &str,
     matcher: &OutputMatchStructured,
 ) -> Result<(), String> {
-    if let Some(expected) = &matcher.equals
-        && actual != expected
-    {
-        return Err(format!(
-            "{name}: expected exact match\n  expected: {expected:?}\n  got: {actual:?}"
-        ));
+    if let Some(expected) = &matcher.equals {
+        let expected = if matcher.dedent {
+            dedent(expected)
+        } else {
+            expected.clone()
+        };
+        if actual != expected {
+            return Err(format!(
+                "{name}: expected exact match\n  expected: {expected:?}\n  got: {actual:?}"
+            ));
+        }
     }
 
+    // Substring checks compare lowercased text when case_insensitive is set
+    let folded_actual;
+    let haystack = if matcher.case_insensitive {
+        folded_actual = actual.to_lowercase();
+        folded_actual.as_str()
+    } else {
+        actual
+    };
+    let contains = |needle: &str| {
+        if matcher.case_insensitive {
+            haystack.contains(&needle.to_lowercase())
+        } else {
+            haystack.contains(needle)
+        }
+    };
+
     if let Some(substring) = &matcher.contains
-        && !actual.contains(substring)
+        && !contains(substring)
     {
         return Err(format!(
             "{name}: expected to contain {substring:?}\n  got: {actual:?}"
         ));
     }
 
+    let missing: Vec<&String> = matcher
+        .contains_all
+        .iter()
+        .filter(|s| !contains(s))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "{name}: expected to contain all of {:?}\n  missing: {missing:?}\n  got: {actual:?}",
+            matcher.contains_all
+        ));
+    }
+
+    if !matcher.contains_any.is_empty() && !matcher.contains_any.iter().any(|s| contains(s)) {
+        return Err(format!(
+            "{name}: expected to contain any of {:?}\n  got: {actual:?}",
+            matcher.contains_any
+        ));
+    }
+
     if let Some(pattern) = &matcher.regex {
-        let re = regex::Regex::new(pattern)
+        let re = regex::RegexBuilder::new(pattern)
+            .case_insensitive(matcher.case_insensitive)
+            .multi_line(matcher.multiline)
+            .build()
             .map_err(|e| format!("{name}: invalid regex {pattern:?}: {e}"))?;
         if !re.is_match(actual) {
             return Err(format!(
@@ -909,6 +2489,149 @@ fn check_structured_match(
         }
     }
 
+    if let Some(pattern) = &matcher.matches {
+        let re = regex::RegexBuilder::new(&wildcard_regex(pattern))
+            .case_insensitive(matcher.case_insensitive)
+            .build()
+            .map_err(|e| format!("{name}: invalid pattern {pattern:?}: {e}"))?;
+        if !re.is_match(actual) {
+            return Err(format!(
+                "{name}: expected to match pattern {pattern:?}\n  got: {actual:?}"
+            ));
+        }
+    }
+
+    if !matcher.only_lines.is_empty() {
+        check_only_lines(name, actual, &matcher.only_lines)?;
+    }
+
+    if let Some(numeric) = &matcher.numeric {
+        check_numeric_match(name, actual, numeric)?;
+    }
+
+    if let Some(expected) = &matcher.toml {
+        let parsed: toml::Value = toml::from_str(actual)
+            .map_err(|e| format!("{name}: output is not valid TOML: {e}\n  got: {actual:?}"))?;
+        check_parsed_match(name, "TOML", &parsed, expected)?;
+    }
+
+    if let Some(expected) = &matcher.yaml {
+        let parsed: serde_yaml::Value = serde_yaml::from_str(actual)
+            .map_err(|e| format!("{name}: output is not valid YAML: {e}\n  got: {actual:?}"))?;
+        check_parsed_match(name, "YAML", &parsed, expected)?;
+    }
+
+    Ok(())
+}
+
+/// Compare a parsed document to the expected value, showing both as JSON on mismatch.
+fn check_parsed_match<T: PartialEq + serde::Serialize>(
+    name: &str,
+    format: &str,
+    actual: &T,
+    expected: &T,
+) -> Result<(), String> {
+    if actual == expected {
+        return Ok(());
+    }
+    let show = |value: &T| serde_json::to_string(value).unwrap_or_default();
+    Err(format!(
+        "{name}: {format} does not match\n  expected: {}\n  got: {}",
+        show(expected),
+        show(actual)
+    ))
+}
+
+/// Translate a `matches` pattern into an anchored regex: `[..]` becomes a lazy
+/// match of any text within a line, `[EXE]` the executable suffix, and
+/// everything else is literal.
+fn wildcard_regex(pattern: &str) -> String {
+    let literal = |text: &str| regex::escape(&text.replace("[EXE]", std::env::consts::EXE_SUFFIX));
+    let parts: Vec<String> = pattern.split("[..]").map(literal).collect();
+    format!("^(?:{})$", parts.join(".*?"))
+}
+
+/// Check that every non-blank line of the output is one of the allowed lines.
+fn check_only_lines(name: &str, actual: &str, allowed: &[AllowedLine]) -> Result<(), String> {
+    let mut exact = Vec::new();
+    let mut patterns = Vec::new();
+    for entry in allowed {
+        match entry {
+            AllowedLine::Exact(line) => exact.push(line.as_str()),
+            AllowedLine::Regex { regex } => patterns.push(
+                regex::Regex::new(&format!("^(?:{regex})$"))
+                    .map_err(|e| format!("{name}: invalid regex {regex:?} in only_lines: {e}"))?,
+            ),
+        }
+    }
+
+    let unexpected: Vec<&str> = actual
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| !exact.contains(line) && !patterns.iter().any(|re| re.is_match(line)))
+        .collect();
+    if !unexpected.is_empty() {
+        return Err(format!(
+            "{name}: unexpected lines not in only_lines: {unexpected:?}\n  got: {actual:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// Remove the leading whitespace common to every non-blank line, like
+/// Python's `textwrap.dedent`. Whitespace-only lines become empty.
+fn dedent(text: &str) -> String {
+    let margin = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .reduce(|common, indent| {
+            let len = common
+                .chars()
+                .zip(indent.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+            &common[..len]
+        })
+        .unwrap_or("");
+
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                &line[line.trim_end_matches(['\r', '\n']).len()..]
+            } else {
+                &line[margin.len()..]
+            }
+        })
+        .collect()
+}
+
+/// Check output parsed as a number against numeric bounds.
+fn check_numeric_match(name: &str, actual: &str, numeric: &NumericMatch) -> Result<(), String> {
+    let trimmed = actual.trim();
+    let value: f64 = trimmed
+        .parse()
+        .map_err(|_| format!("{name}: expected a number, got {actual:?}"))?;
+
+    if let Some(expected) = numeric.equals
+        && value != expected
+    {
+        return Err(format!("{name} '{trimmed}' expected == {expected:?}"));
+    }
+
+    if let Some(min) = numeric.greater_than
+        && value <= min
+    {
+        return Err(format!("{name} '{trimmed}' expected > {min:?}"));
+    }
+
+    if let Some(max) = numeric.less_than
+        && value >= max
+    {
+        return Err(format!("{name} '{trimmed}' expected < {max:?}"));
+    }
+
     Ok(())
 }
 
@@ -931,16 +2654,62 @@ fn check_file_expect(file_expect: &FileExpect, ctx: &ExecutionContext, failures:
     }
 
     if let Some(matcher) = &file_expect.contents {
-        match std::fs::read_to_string(&path) {
+        let name = format!("file:{}", file_expect.path.display());
+        let contents = std::fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {e}", file_expect.path.display()))
+            .and_then(|bytes| {
+                if file_expect.binary == Some(true) {
+                    Ok(to_hex(&bytes))
+                } else {
+                    String::from_utf8(bytes).map_err(|_| {
+                        format!(
+                            "{name}: file is not valid UTF-8 (use `binary: true` to match its hex)"
+                        )
+                    })
+                }
+            });
+        match contents {
             Ok(contents) => {
-                let name = format!("file:{}", file_expect.path.display());
-                if let Err(e) = check_output_match(&name, &contents, matcher) {
+                if let Err(e) = check_output_match(
+                    &name,
+                    &contents,
+                    matcher,
+                    ctx.strip_ansi,
+                    ctx.normalize_newlines,
+                ) {
                     failures.push(e);
                 }
             }
+            Err(e) => failures.push(e),
+        }
+    }
+
+    if let Some(expected) = &file_expect.bytes_equal
+        && let Err(e) = check_bytes_equal(&path, expected)
+    {
+        failures.push(format!("file:{}: {e}", file_expect.path.display()));
+    }
+
+    if let Some(is_dir) = file_expect.is_dir
+        && file_expect.exists != Some(false)
+        && let Err(e) = check_path_kind(&path, is_dir)
+    {
+        failures.push(format!("file:{}: {e}", file_expect.path.display()));
+    }
+
+    if let Some(expected) = &file_expect.sha256 {
+        match sha256_file(&path) {
+            Ok(actual) => {
+                if !actual.eq_ignore_ascii_case(expected.trim()) {
+                    failures.push(format!(
+                        "file:{}: sha256 mismatch\n  expected: {expected}\n  got: {actual}",
+                        file_expect.path.display()
+                    ));
+                }
+            }
             Err(e) => {
                 failures.push(format!(
-                    "Failed to read {}: {e}",
+                    "Failed to hash {}: {e}",
                     file_expect.path.display()
                 ));
             }
@@ -948,6 +2717,80 @@ fn check_file_expect(file_expect: &FileExpect, ctx: &ExecutionContext, failures:
     }
 }
 
+/// Format bytes as lowercase hex, two digits per byte.
+fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// Check that a file's bytes equal `expected_hex`, reporting the first difference.
+fn check_bytes_equal(path: &Path, expected_hex: &str) -> Result<(), String> {
+    let digits: Vec<u8> = expected_hex
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let expected = digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| format!("bytes_equal is not valid hex: {expected_hex}"))?;
+    let actual = std::fs::read(path).map_err(|e| format!("failed to read: {e}"))?;
+
+    if let Some(offset) = expected.iter().zip(&actual).position(|(e, a)| e != a) {
+        return Err(format!(
+            "bytes differ at offset {offset}: expected 0x{:02x}, got 0x{:02x}",
+            expected[offset], actual[offset]
+        ));
+    }
+    if expected.len() != actual.len() {
+        return Err(format!(
+            "expected {} bytes, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a path is a directory (`expect_dir`) or a regular file.
+fn check_path_kind(path: &Path, expect_dir: bool) -> Result<(), String> {
+    let found = if path.is_dir() {
+        "directory"
+    } else if path.is_file() {
+        "file"
+    } else if path.exists() {
+        "special file"
+    } else {
+        "nothing"
+    };
+    let expected = if expect_dir { "directory" } else { "file" };
+    if found == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {expected} but found {found}"))
+    }
+}
+
+/// Compute the hex-encoded SHA-256 digest of a file, streaming its contents.
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
 fn check_tree_expect(tree_expect: &TreeExpect, ctx: &ExecutionContext, failures: &mut Vec<String>) {
     // Determine root directory to check
     let root = tree_expect
@@ -970,13 +2813,26 @@ fn check_tree_expect(tree_expect: &TreeExpect, ctx: &ExecutionContext, failures:
             continue;
         }
 
+        if let Some(is_dir) = entry.is_dir
+            && let Err(e) = check_path_kind(&full_path, is_dir)
+        {
+            failures.push(format!("Tree: {}: {e}", entry.path.display()));
+            continue;
+        }
+
         // Check contents if specified
         if let Some(matcher) = &entry.contents {
             if full_path.is_file() {
                 match std::fs::read_to_string(&full_path) {
                     Ok(contents) => {
                         let name = format!("tree:{}", entry.path.display());
-                        if let Err(e) = check_output_match(&name, &contents, matcher) {
+                        if let Err(e) = check_output_match(
+                            &name,
+                            &contents,
+                            matcher,
+                            ctx.strip_ansi,
+                            ctx.normalize_newlines,
+                        ) {
                             failures.push(e);
                         }
                     }
@@ -1007,6 +2863,36 @@ fn check_tree_expect(tree_expect: &TreeExpect, ctx: &ExecutionContext, failures:
         }
     }
 
+    // Check that listed directories exist and are empty
+    for dir in &tree_expect.empty_dirs {
+        let full_path = root.join(dir);
+        if !full_path.exists() {
+            failures.push(format!(
+                "Tree: expected empty directory does not exist: {}",
+                dir.display()
+            ));
+            continue;
+        }
+        if !full_path.is_dir() {
+            failures.push(format!("Tree: not a directory: {}", dir.display()));
+            continue;
+        }
+        match std::fs::read_dir(&full_path) {
+            Ok(entries) => {
+                let count = entries.count();
+                if count > 0 {
+                    failures.push(format!(
+                        "Tree: directory not empty: {} ({count} entries)",
+                        dir.display()
+                    ));
+                }
+            }
+            Err(e) => {
+                failures.push(format!("Tree: failed to read {}: {e}", dir.display()));
+            }
+        }
+    }
+
     // If exact mode, verify no unexpected files exist
     if tree_expect.exact {
         let expected_paths: std::collections::HashSet<_> = tree_expect
@@ -1045,6 +2931,84 @@ fn check_tree_expect(tree_expect: &TreeExpect, ctx: &ExecutionContext, failures:
     }
 }
 
+/// Check that every path in a filesystem diff matches an `allowed_changes` pattern.
+///
+/// A path is allowed if it or any of its parents matches a pattern. Directories
+/// whose metadata changed only because an allowed path beneath them changed are
+/// also allowed.
+fn check_allowed_changes(patterns: &[PathBuf], diff: &FilesystemDiff, failures: &mut Vec<String>) {
+    let regexes: Vec<regex::Regex> = match patterns
+        .iter()
+        .map(|p| glob_to_regex(&p.to_string_lossy()))
+        .collect()
+    {
+        Ok(r) => r,
+        Err(e) => {
+            failures.push(format!("allowed_changes: invalid pattern: {e}"));
+            return;
+        }
+    };
+
+    let is_allowed = |path: &Path| {
+        path.ancestors()
+            .filter(|a| !a.as_os_str().is_empty())
+            .any(|a| regexes.iter().any(|re| re.is_match(&a.to_string_lossy())))
+    };
+
+    let changes = [
+        ("added", &diff.added),
+        ("removed", &diff.removed),
+        ("modified", &diff.modified),
+    ];
+    let allowed: Vec<&PathBuf> = changes
+        .iter()
+        .flat_map(|(_, paths)| paths.iter())
+        .filter(|p| is_allowed(p))
+        .collect();
+
+    for (kind, paths) in changes {
+        for path in paths {
+            let covered =
+                is_allowed(path) || allowed.iter().any(|a| a.starts_with(path) && *a != path);
+            if !covered {
+                failures.push(format!(
+                    "allowed_changes: unexpected {kind} path: {}",
+                    path.display()
+                ));
+            }
+        }
+    }
+}
+
+/// Convert a glob pattern to an anchored regex.
+///
+/// `**` matches across path separators, `*` matches within a single component,
+/// and `?` matches a single non-separator character.
+fn glob_to_regex(pattern: &str) -> Result<regex::Regex, regex::Error> {
+    let pattern = pattern.trim_end_matches('/');
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // Swallow a following separator so `a/**/b` also matches `a/b`
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    regex::Regex::new(&re)
+}
+
 /// Check a SQL assertion.
 fn check_sql_expect(
     sql_expect: &SqlExpect,
@@ -1104,8 +3068,10 @@ fn check_sql_expect(
 
     // Handle raw query assertions
     if let Some(query) = &sql_expect.query {
-        match db_manager.execute(db_name, query) {
-            Ok(result) => {
+        match db_manager.execute_with_columns(db_name, query) {
+            Ok(query_result) => {
+                let result = query_result.to_text();
+
                 // Check returns_empty
                 if let Some(true) = sql_expect.returns_empty {
                     if !result.is_empty() {
@@ -1143,6 +3109,12 @@ fn check_sql_expect(
                     return;
                 }
 
+                // Check returns_map
+                if let Some(expected) = &sql_expect.returns_map {
+                    check_sql_returns_map(&prefix, query, &query_result, expected, failures);
+                    return;
+                }
+
                 // Check returns
                 if let Some(returns) = &sql_expect.returns
                     && let Err(e) = check_sql_returns(&prefix, query, &result, returns)
@@ -1159,6 +3131,40 @@ fn check_sql_expect(
     }
 }
 
+/// Check a single-row result's columns against expected values by name.
+fn check_sql_returns_map(
+    prefix: &str,
+    query: &str,
+    result: &QueryResult,
+    expected: &HashMap<String, String>,
+    failures: &mut Vec<String>,
+) {
+    let [row] = result.rows.as_slice() else {
+        failures.push(format!(
+            "{prefix}: returns_map expects exactly one row\n  Query: {query}\n  Got: {} row(s)",
+            result.rows.len()
+        ));
+        return;
+    };
+
+    let mut columns: Vec<&String> = expected.keys().collect();
+    columns.sort();
+    for column in columns {
+        let want = &expected[column];
+        match result.columns.iter().position(|c| c == column) {
+            Some(i) if &row[i] == want => {}
+            Some(i) => failures.push(format!(
+                "{prefix}: column '{column}' mismatch\n  Query: {query}\n  Expected: {want:?}\n  Got: {:?}",
+                row[i]
+            )),
+            None => failures.push(format!(
+                "{prefix}: column '{column}' not in result\n  Query: {query}\n  Columns: [{}]",
+                result.columns.join(", ")
+            )),
+        }
+    }
+}
+
 /// Generate a table existence check query appropriate for the database driver.
 fn table_exists_query(db_manager: &ConnectionManager, db_name: &str, table: &str) -> String {
     // Get driver from config if available
@@ -1184,8 +3190,21 @@ fn check_row_count(
     db_manager: &ConnectionManager,
     failures: &mut Vec<String>,
 ) {
-    let table = &row_count.table;
-    let query = format!("SELECT COUNT(*) FROM {table}");
+    let (target, query) = match (&row_count.query, &row_count.table) {
+        (Some(query), _) => {
+            // A trailing `;` would end the statement inside the subquery
+            let inner = query.trim_end().trim_end_matches(';').trim_end();
+            (
+                query.as_str(),
+                format!("SELECT COUNT(*) FROM ({inner}) AS _sub"),
+            )
+        }
+        (None, Some(table)) => (table.as_str(), format!("SELECT COUNT(*) FROM {table}")),
+        (None, None) => {
+            failures.push(format!("{prefix}: row_count requires table or query"));
+            return;
+        }
+    };
 
     match db_manager.execute(db_name, &query) {
         Ok(result) => {
@@ -1204,7 +3223,7 @@ fn check_row_count(
             {
                 failures.push(format!(
                     "{prefix}: row_count for '{}': expected {}, got {}",
-                    table, expected, count
+                    target, expected, count
                 ));
             }
 
@@ -1213,7 +3232,7 @@ fn check_row_count(
             {
                 failures.push(format!(
                     "{prefix}: row_count for '{}': expected > {}, got {}",
-                    table, min, count
+                    target, min, count
                 ));
             }
 
@@ -1222,14 +3241,14 @@ fn check_row_count(
             {
                 failures.push(format!(
                     "{prefix}: row_count for '{}': expected < {}, got {}",
-                    table, max, count
+                    target, max, count
                 ));
             }
         }
         Err(e) => {
             failures.push(format!(
                 "{prefix}: failed to count rows in '{}': {}",
-                table, e
+                target, e
             ));
         }
     }
@@ -1289,21 +3308,142 @@ fn check_sql_returns_structured(
         }
     }
 
+    if let Some(expected_rows) = &matcher.rows {
+        check_sql_rows(prefix, query, actual, expected_rows)?;
+    }
+
     Ok(())
 }
 
-/// Recursively copy a directory and all its contents.
-fn copy_dir_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
-    std::fs::create_dir_all(to)?;
-    for entry in std::fs::read_dir(from)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        let src = entry.path();
-        let dst = to.join(entry.file_name());
-        if file_type.is_dir() {
-            copy_dir_recursive(&src, &dst)?;
-        } else {
-            std::fs::copy(&src, &dst)?;
+/// Compare a query result, parsed back into rows and columns, against expected rows.
+fn check_sql_rows(
+    prefix: &str,
+    query: &str,
+    actual: &str,
+    expected: &[Vec<String>],
+) -> Result<(), String> {
+    let actual_rows: Vec<Vec<&str>> = if actual.is_empty() {
+        vec![]
+    } else {
+        actual
+            .lines()
+            .map(|line| line.split('\t').collect())
+            .collect()
+    };
+
+    for (row, (expected_row, actual_row)) in expected.iter().zip(&actual_rows).enumerate() {
+        if expected_row.len() != actual_row.len() {
+            return Err(format!(
+                "{prefix}: row {row}: expected {} columns, got {}\n  Query: {query}\n  Got: {actual_row:?}",
+                expected_row.len(),
+                actual_row.len()
+            ));
+        }
+        for (column, (expected_cell, actual_cell)) in
+            expected_row.iter().zip(actual_row).enumerate()
+        {
+            if expected_cell != actual_cell {
+                return Err(format!(
+                    "{prefix}: row {row}, column {column}: expected {expected_cell:?}, got {actual_cell:?}\n  Query: {query}"
+                ));
+            }
+        }
+    }
+
+    if expected.len() != actual_rows.len() {
+        return Err(format!(
+            "{prefix}: expected {} rows, got {}\n  Query: {query}\n  Got: {actual:?}",
+            expected.len(),
+            actual_rows.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Copy a sandbox into a new temporary directory.
+fn copy_sandbox(sandbox_dir: &Path) -> std::io::Result<tempfile::TempDir> {
+    let copy = tempfile::tempdir()?;
+    copy_dir_recursive(sandbox_dir, copy.path())?;
+    Ok(copy)
+}
+
+/// Run a command again in `rerun_ctx` and report any difference from the
+/// first run. Occurrences of the rerun's sandbox path in its output are
+/// replaced with the original sandbox path before comparing.
+fn check_deterministic(
+    run: &Run,
+    rerun_ctx: &ExecutionContext,
+    test_env: &HashMap<String, String>,
+    timeout: Duration,
+    first: &CommandOutput,
+    sandbox_dir: &Path,
+    failures: &mut Vec<String>,
+) {
+    let second = match run_command(run, rerun_ctx, test_env, timeout) {
+        Ok(output) => output,
+        Err(e) => {
+            failures.push(format!("deterministic: second run failed: {e}"));
+            return;
+        }
+    };
+
+    let mut replacements = vec![(
+        rerun_ctx.sandbox_dir.display().to_string(),
+        sandbox_dir.display().to_string(),
+    )];
+    if let (Ok(from), Ok(to)) = (
+        rerun_ctx.sandbox_dir.canonicalize(),
+        sandbox_dir.canonicalize(),
+    ) {
+        replacements.insert(0, (from.display().to_string(), to.display().to_string()));
+    }
+    let normalize = |text: &str| {
+        replacements
+            .iter()
+            .fold(text.to_string(), |acc, (from, to)| acc.replace(from, to))
+    };
+
+    if (first.exit_code, first.signal) != (second.exit_code, second.signal) {
+        failures.push(format!(
+            "deterministic: exit status differs between runs\n  first: {}\n  second: {}",
+            describe_exit(first),
+            describe_exit(&second)
+        ));
+    }
+    for (name, a, b) in [
+        ("stdout", &first.stdout, normalize(&second.stdout)),
+        ("stderr", &first.stderr, normalize(&second.stderr)),
+    ] {
+        if *a != b {
+            failures.push(format!(
+                "deterministic: {name} differs between runs\n  first: {a:?}\n  second: {b:?}"
+            ));
+        }
+    }
+}
+
+/// Describe how a process ended, e.g. "exit code 1" or "signal 9".
+fn describe_exit(output: &CommandOutput) -> String {
+    match (output.exit_code, output.signal) {
+        (Some(code), _) => format!("exit code {code}"),
+        (None, Some(signal)) => format!("signal {signal}"),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Recursively copy a directory and all its contents.
+fn copy_dir_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let src = entry.path();
+        let dst = to.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir_recursive(&src, &dst)?;
+        } else {
+            std::fs::copy(&src, &dst)?;
         }
     }
     Ok(())
@@ -1334,6 +3474,31 @@ struct FileState {
     modified: Option<Duration>,
 }
 
+/// Snapshot the entries directly inside `dir`, other than the sandbox itself.
+fn snapshot_outside(dir: &Path, sandbox_dir: &Path) -> HashMap<PathBuf, FileState> {
+    let sandbox_dir = sandbox_dir.canonicalize().unwrap_or_default();
+    let mut snapshot = HashMap::new();
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.path() == sandbox_dir {
+            continue;
+        }
+        if let Ok(metadata) = entry.metadata() {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+            snapshot.insert(
+                PathBuf::from(entry.file_name()),
+                FileState {
+                    size: metadata.len(),
+                    modified,
+                },
+            );
+        }
+    }
+    snapshot
+}
+
 /// Snapshot the filesystem state of a directory.
 fn snapshot_filesystem(root: &Path) -> HashMap<PathBuf, FileState> {
     let mut snapshot = HashMap::new();
@@ -1407,13 +3572,17 @@ fn compute_fs_diff(
     }
 }
 
+/// Run setup steps in order. Each step first has `${vars.NAME}` references to
+/// values in `vars` resolved, and commands with `capture_as` add their output to it.
 fn run_setup_steps(
     steps: &[SetupStep],
     ctx: &ExecutionContext,
     db_manager: &ConnectionManager,
+    vars: &mut HashMap<String, String>,
 ) -> Result<(), String> {
     for step in steps {
-        run_setup_step(step, ctx, db_manager)?;
+        let step = substitute_vars(step, vars)?;
+        run_setup_step(&step, ctx, db_manager, vars)?;
     }
     Ok(())
 }
@@ -1422,6 +3591,7 @@ fn run_setup_step(
     step: &SetupStep,
     ctx: &ExecutionContext,
     db_manager: &ConnectionManager,
+    vars: &mut HashMap<String, String>,
 ) -> Result<(), String> {
     if let Some(write_file) = &step.write_file {
         let path = ctx.resolve_path(&write_file.path);
@@ -1468,7 +3638,7 @@ fn run_setup_step(
     }
 
     if let Some(run) = &step.run {
-        run_simple_command(run, ctx)?;
+        capture(run, run_simple_command(run, ctx)?, vars);
     }
 
     if let Some(sql) = &step.sql {
@@ -1528,26 +3698,54 @@ fn run_sql_file(
     let contents = std::fs::read_to_string(&path)
         .map_err(|e| format!("Failed to read SQL file {}: {e}", sql_file.path.display()))?;
 
-    // Execute the file contents as a single statement
-    // Note: For multiple statements, users should use sql.statements instead
-    if let Err(e) = db_manager.execute(&sql_file.database, &contents) {
-        let err_msg = format!("SQL file {} failed: {e}", sql_file.path.display());
-        if sql_file.on_error == SqlOnError::Fail {
-            return Err(err_msg);
+    // Without `split`, the file contents are executed as a single statement.
+    // Statements are interpolated after splitting, so a `;` in a value can't
+    // start a new statement.
+    let mut statements = if sql_file.split {
+        crate::database::split_statements(&contents)
+    } else {
+        vec![contents]
+    };
+    if sql_file.interpolate {
+        for statement in &mut statements {
+            *statement = env::interpolate_env_with(statement, &ctx.env)
+                .map_err(|e| format!("SQL file {}: {e}", sql_file.path.display()))?;
+        }
+    }
+
+    for (index, statement) in statements.iter().enumerate() {
+        if let Err(e) = db_manager.execute(&sql_file.database, statement)
+            && sql_file.on_error == SqlOnError::Fail
+        {
+            return Err(if sql_file.split {
+                format!(
+                    "SQL file {} statement {} failed: {e}",
+                    sql_file.path.display(),
+                    index + 1
+                )
+            } else {
+                format!("SQL file {} failed: {e}", sql_file.path.display())
+            });
         }
     }
 
     Ok(())
 }
 
+/// Run every teardown step, even after failures, resolving captured values
+/// like `run_setup_steps`.
 fn run_teardown_steps(
     steps: &[TeardownStep],
     ctx: &ExecutionContext,
     db_manager: &ConnectionManager,
+    vars: &HashMap<String, String>,
 ) -> Result<(), String> {
+    let mut vars = vars.clone();
     let mut errors = Vec::new();
     for step in steps {
-        if let Err(e) = run_teardown_step(step, ctx, db_manager) {
+        let result = substitute_vars(step, &vars)
+            .and_then(|step| run_teardown_step(&step, ctx, db_manager, &mut vars));
+        if let Err(e) = result {
             errors.push(e);
         }
     }
@@ -1562,6 +3760,7 @@ fn run_teardown_step(
     step: &TeardownStep,
     ctx: &ExecutionContext,
     db_manager: &ConnectionManager,
+    vars: &mut HashMap<String, String>,
 ) -> Result<(), String> {
     if let Some(dir_path) = &step.remove_dir {
         let path = ctx.resolve_path(dir_path);
@@ -1580,7 +3779,7 @@ fn run_teardown_step(
     }
 
     if let Some(run) = &step.run {
-        run_simple_command(run, ctx)?;
+        capture(run, run_simple_command(run, ctx)?, vars);
     }
 
     if let Some(sql) = &step.sql {
@@ -1607,20 +3806,56 @@ fn run_teardown_step(
     Ok(())
 }
 
-fn run_simple_command(run: &RunStep, ctx: &ExecutionContext) -> Result<(), String> {
-    // Interpolate environment variables in cmd
-    let cmd_path = env::interpolate_env(&run.cmd)?;
-
-    let mut cmd = Command::new(&cmd_path);
-    cmd.args(&run.args);
-    cmd.current_dir(&ctx.sandbox_dir);
+/// Store a setup/teardown command's trimmed stdout under its `capture_as` name.
+fn capture(run: &RunStep, stdout: String, vars: &mut HashMap<String, String>) {
+    if let Some(name) = &run.capture_as {
+        vars.insert(name.clone(), stdout.trim().to_string());
+    }
+}
 
-    if !ctx.inherit_env {
-        cmd.env_clear();
+/// Extract a step `capture` value from the step's command output.
+fn capture_step_output(source: &CaptureSource, output: &CommandOutput) -> Result<String, String> {
+    match source {
+        CaptureSource::Stream(CaptureStream::Stdout) => Ok(output.stdout.trim().to_string()),
+        CaptureSource::Stream(CaptureStream::Stderr) => Ok(output.stderr.trim().to_string()),
+        CaptureSource::Regex { regex } => {
+            let re = regex::Regex::new(regex).map_err(|e| format!("invalid regex: {e}"))?;
+            let caps = re
+                .captures(&output.stdout)
+                .ok_or_else(|| format!("regex '{regex}' did not match stdout"))?;
+            let m = caps.get(1).or_else(|| caps.get(0)).unwrap();
+            Ok(m.as_str().to_string())
+        }
     }
-    for (k, v) in &ctx.env {
-        cmd.env(k, v);
+}
+
+/// Run a setup/teardown command, returning its stdout.
+fn run_simple_command(run: &RunStep, ctx: &ExecutionContext) -> Result<String, String> {
+    // Merge environment: ctx.env < run.env
+    let mut effective_env = ctx.env.clone();
+    for (k, v) in &run.env {
+        effective_env.insert(k.clone(), v.clone());
     }
+    ctx.apply_locale(&mut effective_env);
+
+    // Interpolate environment variables in cmd and args
+    let cmd_path = env::interpolate_env(&run.cmd)?;
+    let visible_env = ctx.visible_env(&effective_env);
+    let args = run
+        .args
+        .iter()
+        .map(|arg| env::interpolate_arg(arg, &visible_env))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut cmd = Command::new(&cmd_path);
+    cmd.args(&args);
+    let cwd = run
+        .cwd
+        .as_ref()
+        .map(|p| ctx.resolve_path(p))
+        .unwrap_or_else(|| ctx.sandbox_dir.clone());
+    cmd.current_dir(&cwd);
+    ctx.apply_env(&mut cmd, &effective_env);
 
     let output = cmd
         .output()
@@ -1636,7 +3871,7 @@ fn run_simple_command(run: &RunStep, ctx: &ExecutionContext) -> Result<(), Strin
         ));
     }
 
-    Ok(())
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
 }
 
 #[cfg(test)]
@@ -1650,12 +3885,17 @@ mod tests {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![test],
             teardown: vec![],
         }
@@ -1682,17 +3922,34 @@ mod tests {
                     cmd: cmd.to_string(),
                     args: args.into_iter().map(String::from).collect(),
                     stdin: None,
+                    stdin_file: None,
+                    stdout_file: None,
+                    stderr_file: None,
                     env: HashMap::new(),
                     cwd: None,
                     shell: false,
+                    shell_program: None,
                 },
                 expect: Expect::default(),
                 teardown: vec![],
+                expect_failure: false,
+                capture: HashMap::new(),
+                description: None,
+                platform: vec![],
             }],
             teardown: vec![],
             timeout: None,
             serial: false,
             capture_fs_diff: None,
+            allowed_changes: vec![],
+            matrix: None,
+            tags: vec![],
+            deterministic: false,
+            cwd: None,
+            use_fixtures: vec![],
+            resource: None,
+            skip: false,
+            only: false,
         }
     }
 
@@ -1731,7 +3988,7 @@ mod tests {
     #[test]
     fn test_exit_code_zero() {
         let mut test = make_test("exit_zero", "true", vec![]);
-        test.expect_mut().exit = Some(0);
+        test.expect_mut().exit = Some(ExitMatch::Code(0));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
@@ -1745,7 +4002,7 @@ mod tests {
     #[test]
     fn test_exit_code_nonzero() {
         let mut test = make_test("exit_one", "false", vec![]);
-        test.expect_mut().exit = Some(1);
+        test.expect_mut().exit = Some(ExitMatch::Code(1));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
@@ -1759,7 +4016,7 @@ mod tests {
     #[test]
     fn test_exit_code_mismatch() {
         let mut test = make_test("exit_mismatch", "true", vec![]);
-        test.expect_mut().exit = Some(1); // Expecting 1 but will get 0
+        test.expect_mut().exit = Some(ExitMatch::Code(1)); // Expecting 1 but will get 0
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
@@ -1767,6 +4024,73 @@ mod tests {
         assert!(result.tests[0].failures[0].contains("Exit code"));
     }
 
+    #[test]
+    fn test_default_expect_fills_unset_fields() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+default_expect:
+  exit: 0
+  stdout: { contains: "ok" }
+  files:
+    - path: log.txt
+      exists: true
+tests:
+  - name: inherits_stdout
+    run: { cmd: sh, args: ["-c", "touch log.txt out.txt; echo ok"] }
+    expect:
+      files:
+        - path: out.txt
+          exists: true
+  - name: own_stdout
+    run: { cmd: sh, args: ["-c", "touch log.txt; echo done"] }
+    expect:
+      stdout: "done\n"
+  - name: missing_default
+    run: { cmd: sh, args: ["-c", "touch log.txt; echo nope"] }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(result.tests[1].passed, "{:?}", result.tests[1].failures);
+        assert!(!result.tests[2].passed);
+        assert!(
+            result.tests[2].failures[0].contains("stdout"),
+            "{:?}",
+            result.tests[2].failures
+        );
+    }
+
+    #[test]
+    fn test_exit_nonzero_keyword() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: fails
+    run: { cmd: "false" }
+    expect: { exit: nonzero }
+  - name: succeeds
+    run: { cmd: "true" }
+    expect: { exit: nonzero }
+  - name: killed
+    run: { cmd: sh, args: ["-c", "kill -9 $$"] }
+    expect: { exit: nonzero }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert_eq!(
+            result.tests[1].failures,
+            vec!["Exit code: expected nonzero, got 0"]
+        );
+        assert!(!result.tests[2].passed);
+    }
+
     // ==================== Stdout Assertion Tests ====================
 
     #[test]
@@ -1801,6 +4125,7 @@ mod tests {
             equals: None,
             contains: Some("world".to_string()),
             regex: None,
+            ..Default::default()
         }));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -1819,6 +4144,7 @@ mod tests {
             equals: None,
             contains: Some("world".to_string()),
             regex: None,
+            ..Default::default()
         }));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -1828,160 +4154,223 @@ mod tests {
     }
 
     #[test]
-    fn test_stdout_regex() {
-        let mut test = make_test("stdout_regex", "echo", vec!["hello123world"]);
+    fn test_stdout_contains_all_lists_missing() {
+        let mut test = make_test("contains_all", "echo", vec!["alpha beta"]);
         test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: None,
-            regex: Some(r"hello\d+world".to_string()),
+            contains_all: vec![
+                "alpha".to_string(),
+                "gamma".to_string(),
+                "delta".to_string(),
+            ],
+            ..Default::default()
         }));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
+        assert!(!result.tests[0].passed);
         assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
+            result.tests[0].failures[0].contains(r#"missing: ["gamma", "delta"]"#),
+            "{:?}",
             result.tests[0].failures
         );
     }
 
     #[test]
-    fn test_stdout_regex_mismatch() {
-        let mut test = make_test("stdout_regex_fail", "echo", vec!["hello"]);
-        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: None,
-            regex: Some(r"\d+".to_string()),
-        }));
-        let spec = make_spec(test);
+    fn test_stdout_contains_any() {
+        let matcher = |options: &[&str]| OutputMatchStructured {
+            contains: Some("status".to_string()),
+            contains_any: options.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        let mut partial = make_test("partial", "echo", vec!["status: degraded"]);
+        partial.expect_mut().stdout = Some(OutputMatch::Structured(matcher(&["ok", "degraded"])));
+        let mut none = make_test("none", "echo", vec!["status: down"]);
+        none.expect_mut().stdout = Some(OutputMatch::Structured(matcher(&["ok", "degraded"])));
+        let mut spec = make_spec(partial);
+        spec.tests.push(none);
         let result = run_spec_standalone(&spec);
 
-        assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("expected to match regex"));
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(!result.tests[1].passed);
+        assert!(
+            result.tests[1].failures[0]
+                .contains(r#"expected to contain any of ["ok", "degraded"]"#)
+        );
     }
 
     #[test]
-    fn test_stdout_invalid_regex() {
-        let mut test = make_test("stdout_invalid_regex", "echo", vec!["hello"]);
-        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: None,
-            regex: Some(r"[invalid".to_string()),
-        }));
-        let spec = make_spec(test);
-        let result = run_spec_standalone(&spec);
-
-        assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("invalid regex"));
+    fn test_dedent() {
+        assert_eq!(dedent("    a\n      b\n    c\n"), "a\n  b\nc\n");
+        assert_eq!(dedent("  a\n\n   \n  b"), "a\n\n\nb");
+        assert_eq!(dedent("\tx\n\t\ty\n"), "x\n\ty\n");
+        assert_eq!(dedent("no indent\n  here\n"), "no indent\n  here\n");
     }
 
-    // ==================== Stderr Assertion Tests ====================
-
     #[test]
-    fn test_stderr_contains() {
-        let mut test = make_test("stderr_test", "sh", vec!["-c", "echo error >&2"]);
-        test.expect_mut().stderr = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: Some("error".to_string()),
-            regex: None,
+    fn test_stdout_equals_dedent() {
+        let mut test = make_test("dedent", "printf", vec!["name: app\n  port: 80\n"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: Some("        name: app\n          port: 80\n".to_string()),
+            dedent: true,
+            ..Default::default()
         }));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
-        assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
-        );
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
     }
 
-    // ==================== File Expectation Tests ====================
-
     #[test]
-    fn test_file_exists() {
-        let mut test = make_test("file_exists", "touch", vec!["output.txt"]);
-        test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("output.txt"),
-            exists: Some(true),
-            contents: None,
-        }];
-        let spec = make_spec(test);
-        let result = run_spec_standalone(&spec);
-
-        assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
+    fn test_strip_ansi_codes() {
+        assert_eq!(strip_ansi_codes("\x1b[31mred\x1b[0m"), "red");
+        assert_eq!(
+            strip_ansi_codes("\x1b[1;32mok\x1b[m done\x1b[2K"),
+            "ok done"
         );
+        assert_eq!(strip_ansi_codes("\x1b]0;title\x07text"), "text");
+        assert_eq!(strip_ansi_codes("plain"), "plain");
     }
 
     #[test]
-    fn test_file_not_exists() {
-        let mut test = make_test("file_not_exists", "true", vec![]);
-        test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("nonexistent.txt"),
-            exists: Some(false),
-            contents: None,
-        }];
-        let spec = make_spec(test);
+    fn test_stdout_equals_strip_ansi() {
+        let mut test = make_test("ansi", "printf", vec!["\x1b[31mred\x1b[0m"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: Some("red".to_string()),
+            strip_ansi: Some(true),
+            ..Default::default()
+        }));
+        let mut failing = make_test("ansi_mismatch", "printf", vec!["\x1b[31mred\x1b[0m"]);
+        failing.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: Some("blue".to_string()),
+            strip_ansi: Some(true),
+            ..Default::default()
+        }));
+        let mut spec = make_spec(test);
+        spec.tests.push(failing);
         let result = run_spec_standalone(&spec);
 
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        let failure = &result.tests[1].failures[0];
+        assert!(failure.contains(r#"got: "red""#), "{failure}");
         assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
+            failure.contains(r#"raw: "\u{1b}[31mred\u{1b}[0m""#),
+            "{failure}"
         );
+        assert!(failure.contains("ANSI escape codes were stripped"));
     }
 
     #[test]
-    fn test_file_exists_failure() {
-        let mut test = make_test("file_exists_fail", "true", vec![]);
-        test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("missing.txt"),
-            exists: Some(true),
-            contents: None,
-        }];
-        let spec = make_spec(test);
-        let result = run_spec_standalone(&spec);
+    fn test_suite_strip_ansi_default() {
+        let mut test = make_test("ansi", "printf", vec!["\x1b[1mbold\x1b[0m"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("bold".to_string()));
+        let mut opted_out = make_test("raw", "printf", vec!["\x1b[1mbold\x1b[0m"]);
+        opted_out.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: Some("\x1b[1mbold\x1b[0m".to_string()),
+            strip_ansi: Some(false),
+            ..Default::default()
+        }));
+        let mut spec = make_spec(test);
+        spec.tests.push(opted_out);
+        let effective = EffectiveConfig {
+            strip_ansi: true,
+            ..Default::default()
+        };
+        let result = run_spec_with_config(&spec, &effective, &TestFilter::default(), None);
 
-        assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("should exist"));
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(result.tests[1].passed, "{:?}", result.tests[1].failures);
     }
 
     #[test]
-    fn test_file_contents() {
-        let mut test = make_test("file_contents", "sh", vec!["-c", "echo hello > output.txt"]);
-        test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("output.txt"),
-            exists: None,
-            contents: Some(OutputMatch::Exact("hello\n".to_string())),
-        }];
-        let spec = make_spec(test);
+    fn test_normalize_newlines_crlf_output() {
+        let mut test = make_test("crlf", "printf", vec!["one\r\ntwo\rthree\r\n"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: Some("one\ntwo\nthree\n".to_string()),
+            normalize_newlines: Some(true),
+            ..Default::default()
+        }));
+        let mut strict = make_test("crlf_strict", "printf", vec!["one\r\n"]);
+        strict.expect_mut().stdout = Some(OutputMatch::Exact("one\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.tests.push(strict);
         let result = run_spec_standalone(&spec);
 
-        assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
-        );
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        // Off by default
+        assert!(!result.tests[1].passed);
     }
 
     #[test]
-    fn test_file_contents_contains() {
+    fn test_suite_normalize_newlines_default() {
         let mut test = make_test(
-            "file_contents_contains",
+            "crlf",
             "sh",
-            vec!["-c", "echo 'hello world' > output.txt"],
+            vec!["-c", "printf 'a\\r\\nb\\r\\n' | tee out.txt"],
         );
+        test.expect_mut().stdout = Some(OutputMatch::Exact("a\nb\n".to_string()));
         test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("output.txt"),
+            path: PathBuf::from("out.txt"),
             exists: None,
+            // Expected text is normalized too
             contents: Some(OutputMatch::Structured(OutputMatchStructured {
-                equals: None,
-                contains: Some("world".to_string()),
-                regex: None,
+                contains: Some("a\r\nb".to_string()),
+                ..Default::default()
             })),
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
         }];
+        let effective = EffectiveConfig {
+            normalize_newlines: true,
+            ..Default::default()
+        };
+        let result =
+            run_spec_with_config(&make_spec(test), &effective, &TestFilter::default(), None);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_strict_stderr() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: clean
+    run: { cmd: echo, args: ["ok"] }
+  - name: warns
+    run: { cmd: sh, args: ["-c", "echo 'warning: deprecated' >&2"] }
+  - name: warns_allowed
+    run: { cmd: sh, args: ["-c", "echo 'warning: deprecated' >&2"] }
+    expect:
+      stderr: { contains: "warning" }
+"#,
+        )
+        .unwrap();
+        let effective = EffectiveConfig {
+            strict_stderr: true,
+            ..Default::default()
+        };
+        let result = run_spec_with_config(&spec, &effective, &TestFilter::default(), None);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert_eq!(
+            result.tests[1].failures,
+            vec!["stderr: expected no output (strict_stderr)\n  got: \"warning: deprecated\\n\""]
+        );
+        assert!(result.tests[2].passed, "{:?}", result.tests[2].failures);
+    }
+
+    #[test]
+    fn test_stdout_regex() {
+        let mut test = make_test("stdout_regex", "echo", vec!["hello123world"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: None,
+            regex: Some(r"hello\d+world".to_string()),
+            ..Default::default()
+        }));
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
@@ -1992,141 +4381,207 @@ mod tests {
         );
     }
 
-    // ==================== Setup/Teardown Tests ====================
-
     #[test]
-    fn test_file_level_setup_write_file() {
-        let mut test = make_test("read_setup_file", "cat", vec!["config.txt"]);
-        test.expect_mut().stdout = Some(OutputMatch::Exact("test config\n".to_string()));
-        let mut spec = make_spec(test);
-        spec.setup = vec![SetupStep {
-            write_file: Some(WriteFile {
-                path: PathBuf::from("config.txt"),
-                contents: "test config\n".to_string(),
-            }),
-            create_dir: None,
-            copy_file: None,
-            copy_dir: None,
-            run: None,
+    fn test_stdout_regex_mismatch() {
+        let mut test = make_test("stdout_regex_fail", "echo", vec!["hello"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: None,
+            regex: Some(r"\d+".to_string()),
             ..Default::default()
-        }];
+        }));
+        let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
-        assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
-        );
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("expected to match regex"));
     }
 
     #[test]
-    fn test_file_level_setup_create_dir() {
-        let mut test = make_test("check_dir", "test", vec!["-d", "subdir"]);
-        test.expect_mut().exit = Some(0);
-        let mut spec = make_spec(test);
-        spec.setup = vec![SetupStep {
-            write_file: None,
-            create_dir: Some(PathBuf::from("subdir")),
-            copy_file: None,
-            copy_dir: None,
-            run: None,
+    fn test_case_insensitive_match() {
+        let matcher = OutputMatchStructured {
+            regex: Some("^build (ok|passed)$".to_string()),
+            contains: Some("BUILD".to_string()),
+            contains_all: vec!["Ok".to_string()],
+            contains_any: vec!["OK".to_string(), "FAILED".to_string()],
+            case_insensitive: true,
             ..Default::default()
-        }];
-        let result = run_spec_standalone(&spec);
+        };
+        assert!(check_structured_match("stdout", "Build OK", &matcher).is_ok());
+
+        let case_sensitive = OutputMatchStructured {
+            case_insensitive: false,
+            ..matcher
+        };
+        assert!(check_structured_match("stdout", "Build OK", &case_sensitive).is_err());
+    }
 
+    #[test]
+    fn test_only_lines() {
+        let matcher: OutputMatchStructured = serde_yaml::from_str(
+            r#"
+only_lines:
+  - "Compiling app"
+  - regex: "Finished in \\d+ms"
+"#,
+        )
+        .unwrap();
+        let clean = "Compiling app\n\nFinished in 42ms\nCompiling app\n";
+        assert!(check_structured_match("stdout", clean, &matcher).is_ok());
+
+        let noisy = "Compiling app\nwarning: unused variable\nFinished in 42ms extra\n";
+        let err = check_structured_match("stdout", noisy, &matcher).unwrap_err();
         assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
+            err.contains(
+                r#"unexpected lines not in only_lines: ["warning: unused variable", "Finished in 42ms extra"]"#
+            ),
+            "{err}"
         );
     }
 
     #[test]
-    fn test_test_level_setup() {
-        let mut test = make_test("read_test_setup_file", "cat", vec!["test_config.txt"]);
-        test.expect_mut().stdout = Some(OutputMatch::Exact("per-test config\n".to_string()));
-        test.setup = vec![SetupStep {
-            write_file: Some(WriteFile {
-                path: PathBuf::from("test_config.txt"),
-                contents: "per-test config\n".to_string(),
-            }),
-            create_dir: None,
-            copy_file: None,
-            copy_dir: None,
-            run: None,
+    fn test_matches_wildcard_masks_timestamp() {
+        let matcher = OutputMatchStructured {
+            matches: Some("[..] INFO started (pid [..])\ndone\n".to_string()),
             ..Default::default()
-        }];
-        let spec = make_spec(test);
-        let result = run_spec_standalone(&spec);
+        };
+        let output = "2024-05-01T12:00:00Z INFO started (pid 4242)\ndone\n";
+        assert!(check_structured_match("stdout", output, &matcher).is_ok());
 
+        // Literal text, including regex metacharacters, must match exactly
+        let err = check_structured_match("stdout", "12:00 INFO started [pid 1]\ndone\n", &matcher)
+            .unwrap_err();
+        assert!(err.contains("expected to match pattern"), "{err}");
+
+        // `[..]` does not span lines, and the whole output must match
         assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
+            check_structured_match("stdout", "a\nb INFO started (pid 1)\ndone\n", &matcher)
+                .is_err()
         );
+        assert!(check_structured_match("stdout", &format!("{output}extra"), &matcher).is_err());
     }
 
     #[test]
-    fn test_setup_copy_file() {
-        let mut test = make_test("read_copied_file", "cat", vec!["dest.txt"]);
-        test.expect_mut().stdout = Some(OutputMatch::Exact("original content\n".to_string()));
-        let mut spec = make_spec(test);
-        spec.setup = vec![
-            SetupStep {
-                write_file: Some(WriteFile {
-                    path: PathBuf::from("source.txt"),
-                    contents: "original content\n".to_string(),
-                }),
-                create_dir: None,
-                copy_file: None,
-                copy_dir: None,
-                run: None,
-                ..Default::default()
-            },
-            SetupStep {
-                write_file: None,
-                create_dir: None,
-                copy_file: Some(CopyFile {
-                    from: PathBuf::from("source.txt"),
-                    to: PathBuf::from("dest.txt"),
-                }),
-                copy_dir: None,
-                run: None,
-                ..Default::default()
-            },
-        ];
+    fn test_matches_exe_suffix() {
+        let matcher = OutputMatchStructured {
+            matches: Some("built target/app[EXE]\n".to_string()),
+            ..Default::default()
+        };
+        let output = format!("built target/app{}\n", std::env::consts::EXE_SUFFIX);
+        assert!(check_structured_match("stdout", &output, &matcher).is_ok());
+        if cfg!(windows) {
+            assert!(check_structured_match("stdout", "built target/app\n", &matcher).is_err());
+        } else {
+            assert!(check_structured_match("stdout", "built target/app.exe\n", &matcher).is_err());
+        }
+    }
+
+    #[test]
+    fn test_toml_file_contents_ignore_key_order() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: writes_config
+    run:
+      cmd: sh
+      args: ["-c", "printf '[server]\\nport = 8080\\nhost = \"localhost\"\\n\\n[log]\\nlevel = \"info\"\\n' > app.toml"]
+    expect:
+      files:
+        - path: app.toml
+          contents:
+            toml:
+              log: { level: info }
+              server: { host: localhost, port: 8080 }
+"#,
+        )
+        .unwrap();
         let result = run_spec_standalone(&spec);
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
 
+    #[test]
+    fn test_toml_and_yaml_matchers() {
+        let toml_matcher = OutputMatchStructured {
+            toml: Some(toml::from_str("a = 1\nb = [\"x\"]").unwrap()),
+            ..Default::default()
+        };
+        assert!(check_structured_match("stdout", "b = ['x']\na = 1\n", &toml_matcher).is_ok());
+        assert_eq!(
+            check_structured_match("stdout", "a = 2\nb = ['x']\n", &toml_matcher).unwrap_err(),
+            "stdout: TOML does not match\n  expected: {\"a\":1,\"b\":[\"x\"]}\n  got: {\"a\":2,\"b\":[\"x\"]}"
+        );
         assert!(
-            result.tests[0].passed,
-            "failures: {:?}",
-            result.tests[0].failures
+            check_structured_match("stdout", "a = ", &toml_matcher)
+                .unwrap_err()
+                .starts_with("stdout: output is not valid TOML:")
+        );
+
+        let yaml_matcher = OutputMatchStructured {
+            yaml: Some(serde_yaml::from_str("name: app\nports: [80, 443]").unwrap()),
+            ..Default::default()
+        };
+        assert!(
+            check_structured_match(
+                "stdout",
+                "ports:\n  - 80\n  - 443\nname: app\n",
+                &yaml_matcher
+            )
+            .is_ok()
+        );
+        assert!(
+            check_structured_match("stdout", "name: [unclosed", &yaml_matcher)
+                .unwrap_err()
+                .starts_with("stdout: output is not valid YAML:")
         );
     }
 
     #[test]
-    fn test_setup_run_command() {
-        let mut test = make_test("check_setup_command", "cat", vec!["created_by_setup.txt"]);
+    fn test_multiline_regex_anchors() {
+        let output = "Compiling app\nFinished release\nDone\n";
+        let matcher = OutputMatchStructured {
+            regex: Some("^Finished release$".to_string()),
+            multiline: true,
+            ..Default::default()
+        };
+        assert!(check_structured_match("stdout", output, &matcher).is_ok());
+
+        let single_line = OutputMatchStructured {
+            multiline: false,
+            ..matcher
+        };
+        let err = check_structured_match("stdout", output, &single_line).unwrap_err();
+        assert!(err.contains("expected to match regex"), "{err}");
+    }
+
+    #[test]
+    fn test_stdout_invalid_regex() {
+        let mut test = make_test("stdout_invalid_regex", "echo", vec!["hello"]);
         test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
             equals: None,
-            contains: Some("setup ran".to_string()),
-            regex: None,
+            contains: None,
+            regex: Some(r"[invalid".to_string()),
+            ..Default::default()
         }));
-        let mut spec = make_spec(test);
-        spec.setup = vec![SetupStep {
-            write_file: None,
-            create_dir: None,
-            copy_file: None,
-            copy_dir: None,
-            run: Some(RunStep {
-                cmd: "sh".to_string(),
-                args: vec![
-                    "-c".to_string(),
-                    "echo 'setup ran' > created_by_setup.txt".to_string(),
-                ],
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("invalid regex"));
+    }
+
+    #[test]
+    fn test_stdout_numeric() {
+        let mut test = make_test("stdout_numeric", "echo", vec!["3.14"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            numeric: Some(NumericMatch {
+                equals: None,
+                greater_than: Some(3.0),
+                less_than: Some(4.0),
             }),
             ..Default::default()
-        }];
+        }));
+        let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2137,63 +4592,51 @@ mod tests {
     }
 
     #[test]
-    fn test_setup_copy_dir() {
-        // Test that copy_dir recursively copies a directory
-        let mut test = make_test(
-            "read_copied_dir",
-            "sh",
-            vec!["-c", "cat dest/sub/nested.txt && cat dest/root.txt"],
-        );
+    fn test_stdout_numeric_mismatch() {
+        let mut test = make_test("stdout_numeric_fail", "echo", vec!["3.14"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            numeric: Some(NumericMatch {
+                greater_than: Some(4.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert_eq!(result.tests[0].failures[0], "stdout '3.14' expected > 4.0");
+    }
+
+    #[test]
+    fn test_stdout_numeric_not_a_number() {
+        let mut test = make_test("stdout_not_numeric", "echo", vec!["many"]);
         test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            numeric: Some(NumericMatch {
+                equals: Some(1.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("expected a number"));
+    }
+
+    // ==================== Stderr Assertion Tests ====================
+
+    #[test]
+    fn test_stderr_contains() {
+        let mut test = make_test("stderr_test", "sh", vec!["-c", "echo error >&2"]);
+        test.expect_mut().stderr = Some(OutputMatch::Structured(OutputMatchStructured {
             equals: None,
-            contains: Some("nested content".to_string()),
+            contains: Some("error".to_string()),
             regex: None,
+            ..Default::default()
         }));
-        let mut spec = make_spec(test);
-        // First create source directory structure, then copy it
-        spec.setup = vec![
-            SetupStep {
-                write_file: None,
-                create_dir: Some(PathBuf::from("source/sub")),
-                copy_file: None,
-                copy_dir: None,
-                run: None,
-                ..Default::default()
-            },
-            SetupStep {
-                write_file: Some(WriteFile {
-                    path: PathBuf::from("source/root.txt"),
-                    contents: "root content\n".to_string(),
-                }),
-                create_dir: None,
-                copy_file: None,
-                copy_dir: None,
-                run: None,
-                ..Default::default()
-            },
-            SetupStep {
-                write_file: Some(WriteFile {
-                    path: PathBuf::from("source/sub/nested.txt"),
-                    contents: "nested content\n".to_string(),
-                }),
-                create_dir: None,
-                copy_file: None,
-                copy_dir: None,
-                run: None,
-                ..Default::default()
-            },
-            SetupStep {
-                write_file: None,
-                create_dir: None,
-                copy_file: None,
-                copy_dir: Some(CopyDir {
-                    from: PathBuf::from("source"),
-                    to: PathBuf::from("dest"),
-                }),
-                run: None,
-                ..Default::default()
-            },
-        ];
+        let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2203,48 +4646,115 @@ mod tests {
         );
     }
 
+    // ==================== Output Limit Tests ====================
+
     #[test]
-    fn test_teardown_removes_file() {
-        let mut test = make_test("create_file", "touch", vec!["to_remove.txt"]);
-        test.expect_mut().files = vec![FileExpect {
-            path: PathBuf::from("to_remove.txt"),
-            exists: Some(true),
-            contents: None,
-        }];
-        let mut spec = make_spec(test);
-        spec.teardown = vec![TeardownStep {
-            remove_dir: None,
-            remove_file: Some(PathBuf::from("to_remove.txt")),
-            run: None,
+    fn test_output_over_limit_is_truncated() {
+        let mut test = make_test(
+            "flood",
+            "sh",
+            vec!["-c", "head -c 100000 /dev/zero | tr '\\0' x; echo oops >&2"],
+        );
+        test.expect_mut().stdout = Some(OutputMatch::Exact(format!(
+            "{}…[truncated 99990 bytes]",
+            "x".repeat(10)
+        )));
+        test.expect_mut().stderr = Some(OutputMatch::Exact("oops\n".to_string()));
+        let effective = EffectiveConfig {
+            max_output_bytes: Some(10),
             ..Default::default()
-        }];
-        let result = run_spec_standalone(&spec);
+        };
+        let result =
+            run_spec_with_config(&make_spec(test), &effective, &TestFilter::default(), None);
 
-        // Test should pass (file existed when checked)
         assert!(
             result.tests[0].passed,
             "failures: {:?}",
             result.tests[0].failures
         );
-        // No teardown errors
+    }
+
+    #[test]
+    fn test_output_larger_than_pipe_buffer() {
+        // More than a pipe's capacity must not block the child before it exits
+        let mut test = make_test("large", "sh", vec!["-c", "head -c 1000000 /dev/zero"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("\0".repeat(1_000_000)));
+        let result = run_spec_standalone(&make_spec(test));
+
         assert!(
-            result
-                .tests
-                .iter()
-                .all(|t| t.name != "<teardown>" || t.passed)
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
         );
     }
 
-    // ==================== Environment Variable Tests ====================
+    #[test]
+    fn test_stdout_and_stderr_files() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: redirected
+    run:
+      cmd: sh
+      args: ["-c", "echo out; echo err >&2"]
+      stdout_file: out.log
+      stderr_file: err.log
+    expect:
+      stdout: "out\n"
+      stderr: { contains: err }
+      files:
+        - path: out.log
+          contents: "out\n"
+        - path: err.log
+          contents: "err\n"
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
 
     #[test]
-    fn test_sandbox_env() {
-        let mut test = make_test("env_test", "sh", vec!["-c", "echo $MY_VAR"]);
-        test.expect_mut().stdout = Some(OutputMatch::Exact("test_value\n".to_string()));
-        let mut spec = make_spec(test);
-        spec.sandbox
-            .env
-            .insert("MY_VAR".to_string(), "test_value".to_string());
+    fn test_stdout_file_is_truncated_for_matching() {
+        let mut test = make_test("flood", "sh", vec!["-c", "printf 0123456789abcdef"]);
+        test.run_mut().stdout_file = Some(PathBuf::from("out.log"));
+        test.expect_mut().stdout =
+            Some(OutputMatch::Exact("0123…[truncated 12 bytes]".to_string()));
+        let effective = EffectiveConfig {
+            max_output_bytes: Some(4),
+            ..Default::default()
+        };
+        let result =
+            run_spec_with_config(&make_spec(test), &effective, &TestFilter::default(), None);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== File Expectation Tests ====================
+
+    #[test]
+    fn test_file_exists() {
+        let mut test = make_test("file_exists", "touch", vec!["output.txt"]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("output.txt"),
+            exists: Some(true),
+            contents: None,
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2255,16 +4765,18 @@ mod tests {
     }
 
     #[test]
-    fn test_command_env_override() {
-        let mut test = make_test("env_override", "sh", vec!["-c", "echo $MY_VAR"]);
-        test.expect_mut().stdout = Some(OutputMatch::Exact("overridden\n".to_string()));
-        test.run_mut()
-            .env
-            .insert("MY_VAR".to_string(), "overridden".to_string());
-        let mut spec = make_spec(test);
-        spec.sandbox
-            .env
-            .insert("MY_VAR".to_string(), "original".to_string());
+    fn test_file_not_exists() {
+        let mut test = make_test("file_not_exists", "true", vec![]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("nonexistent.txt"),
+            exists: Some(false),
+            contents: None,
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2275,17 +4787,68 @@ mod tests {
     }
 
     #[test]
-    fn test_env_cleared_without_inherit() {
-        // When inherit_env is false (default), custom host env vars should not be visible
-        // Note: We use a custom var because sh may set default PATH even with cleared env
+    fn test_file_exists_failure() {
+        let mut test = make_test("file_exists_fail", "true", vec![]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("missing.txt"),
+            exists: Some(true),
+            contents: None,
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("should exist"));
+    }
+
+    #[test]
+    fn test_file_contents() {
+        let mut test = make_test("file_contents", "sh", vec!["-c", "echo hello > output.txt"]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("output.txt"),
+            exists: None,
+            contents: Some(OutputMatch::Exact("hello\n".to_string())),
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_file_contents_contains() {
         let mut test = make_test(
-            "env_cleared",
+            "file_contents_contains",
             "sh",
-            vec!["-c", "echo ${BINTEST_CUSTOM_VAR:-empty}"],
+            vec!["-c", "echo 'hello world' > output.txt"],
         );
-        test.expect_mut().stdout = Some(OutputMatch::Exact("empty\n".to_string()));
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("output.txt"),
+            exists: None,
+            contents: Some(OutputMatch::Structured(OutputMatchStructured {
+                equals: None,
+                contains: Some("world".to_string()),
+                regex: None,
+                ..Default::default()
+            })),
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
         let spec = make_spec(test);
-        // Even if BINTEST_CUSTOM_VAR is set in the host, it shouldn't be inherited
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2295,16 +4858,77 @@ mod tests {
         );
     }
 
-    // ==================== Stdin Tests ====================
+    #[test]
+    fn test_file_sha256() {
+        let mut test = make_test("file_sha256", "sh", vec!["-c", "printf abc > out.bin"]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("out.bin"),
+            exists: None,
+            contents: None,
+            // SHA-256 of "abc", upper-cased to check case-insensitivity
+            sha256: Some(
+                "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD".to_string(),
+            ),
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
 
     #[test]
-    fn test_stdin() {
-        let mut test = make_test("stdin_test", "cat", vec![]);
-        test.run_mut().stdin = Some("input data".to_string());
-        test.expect_mut().stdout = Some(OutputMatch::Exact("input data".to_string()));
+    fn test_file_sha256_mismatch() {
+        let mut test = make_test("file_sha256_fail", "sh", vec!["-c", "printf abd > out.bin"]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("out.bin"),
+            exists: None,
+            contents: None,
+            sha256: Some(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            ),
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("sha256 mismatch"));
+        assert!(result.tests[0].failures[0].contains("ba7816bf"));
+    }
+
+    /// A test whose command writes the non-UTF-8 bytes ff fe 00 41 to out.bin.
+    fn binary_file_test(expect: FileExpect) -> Test {
+        let mut test = make_test(
+            "binary_file",
+            "sh",
+            vec!["-c", "printf '\\377\\376\\000A' > out.bin"],
+        );
+        test.expect_mut().files = vec![expect];
+        test
+    }
+
+    #[test]
+    fn test_file_binary_contents_as_hex() {
+        let test = binary_file_test(FileExpect {
+            path: PathBuf::from("out.bin"),
+            exists: None,
+            contents: Some(OutputMatch::Exact("fffe0041".to_string())),
+            sha256: None,
+            is_dir: None,
+            binary: Some(true),
+            bytes_equal: Some("FF FE 00 41".to_string()),
+        });
+        let result = run_spec_standalone(&make_spec(test));
+
         assert!(
             result.tests[0].passed,
             "failures: {:?}",
@@ -2312,17 +4936,113 @@ mod tests {
         );
     }
 
-    // ==================== Shell Mode Tests ====================
+    #[test]
+    fn test_file_non_utf8_text_contents_fails_clearly() {
+        let test = binary_file_test(FileExpect {
+            path: PathBuf::from("out.bin"),
+            exists: None,
+            contents: Some(OutputMatch::Exact("A".to_string())),
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        });
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0].contains("file:out.bin: file is not valid UTF-8"),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
 
     #[test]
-    fn test_shell_mode() {
-        let mut test = make_test("shell_test", "echo", vec!["hello", "&&", "echo", "world"]);
-        test.run_mut().shell = true;
-        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: Some("hello".to_string()),
-            regex: None,
-        }));
+    fn test_file_bytes_equal_reports_first_difference() {
+        let test = binary_file_test(FileExpect {
+            path: PathBuf::from("out.bin"),
+            exists: None,
+            contents: None,
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: Some("fffe0142".to_string()),
+        });
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0]
+                .contains("bytes differ at offset 2: expected 0x01, got 0x00"),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== Setup/Teardown Tests ====================
+
+    #[test]
+    fn test_file_level_setup_write_file() {
+        let mut test = make_test("read_setup_file", "cat", vec!["config.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("test config\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("config.txt"),
+                contents: "test config\n".to_string(),
+            }),
+            create_dir: None,
+            copy_file: None,
+            copy_dir: None,
+            run: None,
+            ..Default::default()
+        }];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_file_level_setup_create_dir() {
+        let mut test = make_test("check_dir", "test", vec!["-d", "subdir"]);
+        test.expect_mut().exit = Some(ExitMatch::Code(0));
+        let mut spec = make_spec(test);
+        spec.setup = vec![SetupStep {
+            write_file: None,
+            create_dir: Some(PathBuf::from("subdir")),
+            copy_file: None,
+            copy_dir: None,
+            run: None,
+            ..Default::default()
+        }];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_test_level_setup() {
+        let mut test = make_test("read_test_setup_file", "cat", vec!["test_config.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("per-test config\n".to_string()));
+        test.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("test_config.txt"),
+                contents: "per-test config\n".to_string(),
+            }),
+            create_dir: None,
+            copy_file: None,
+            copy_dir: None,
+            run: None,
+            ..Default::default()
+        }];
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
 
@@ -2333,32 +5053,2147 @@ mod tests {
         );
     }
 
-    // ==================== Timeout Tests ====================
-
     #[test]
-    fn test_timeout() {
-        let mut test = make_test("timeout_test", "sleep", vec!["10"]);
-        test.timeout = Some(1); // 1 second timeout
-        let spec = make_spec(test);
-        let result = run_spec_standalone(&spec);
-
+    fn test_setup_copy_file() {
+        let mut test = make_test("read_copied_file", "cat", vec!["dest.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("original content\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.setup = vec![
+            SetupStep {
+                write_file: Some(WriteFile {
+                    path: PathBuf::from("source.txt"),
+                    contents: "original content\n".to_string(),
+                }),
+                create_dir: None,
+                copy_file: None,
+                copy_dir: None,
+                run: None,
+                ..Default::default()
+            },
+            SetupStep {
+                write_file: None,
+                create_dir: None,
+                copy_file: Some(CopyFile {
+                    from: PathBuf::from("source.txt"),
+                    to: PathBuf::from("dest.txt"),
+                }),
+                copy_dir: None,
+                run: None,
+                ..Default::default()
+            },
+        ];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_setup_run_command() {
+        let mut test = make_test("check_setup_command", "cat", vec!["created_by_setup.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: Some("setup ran".to_string()),
+            regex: None,
+            ..Default::default()
+        }));
+        let mut spec = make_spec(test);
+        spec.setup = vec![SetupStep {
+            write_file: None,
+            create_dir: None,
+            copy_file: None,
+            copy_dir: None,
+            run: Some(RunStep {
+                cmd: "sh".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "echo 'setup ran' > created_by_setup.txt".to_string(),
+                ],
+                env: HashMap::new(),
+                cwd: None,
+                capture_as: None,
+            }),
+            ..Default::default()
+        }];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_setup_copy_dir() {
+        // Test that copy_dir recursively copies a directory
+        let mut test = make_test(
+            "read_copied_dir",
+            "sh",
+            vec!["-c", "cat dest/sub/nested.txt && cat dest/root.txt"],
+        );
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: Some("nested content".to_string()),
+            regex: None,
+            ..Default::default()
+        }));
+        let mut spec = make_spec(test);
+        // First create source directory structure, then copy it
+        spec.setup = vec![
+            SetupStep {
+                write_file: None,
+                create_dir: Some(PathBuf::from("source/sub")),
+                copy_file: None,
+                copy_dir: None,
+                run: None,
+                ..Default::default()
+            },
+            SetupStep {
+                write_file: Some(WriteFile {
+                    path: PathBuf::from("source/root.txt"),
+                    contents: "root content\n".to_string(),
+                }),
+                create_dir: None,
+                copy_file: None,
+                copy_dir: None,
+                run: None,
+                ..Default::default()
+            },
+            SetupStep {
+                write_file: Some(WriteFile {
+                    path: PathBuf::from("source/sub/nested.txt"),
+                    contents: "nested content\n".to_string(),
+                }),
+                create_dir: None,
+                copy_file: None,
+                copy_dir: None,
+                run: None,
+                ..Default::default()
+            },
+            SetupStep {
+                write_file: None,
+                create_dir: None,
+                copy_file: None,
+                copy_dir: Some(CopyDir {
+                    from: PathBuf::from("source"),
+                    to: PathBuf::from("dest"),
+                }),
+                run: None,
+                ..Default::default()
+            },
+        ];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_teardown_removes_file() {
+        let mut test = make_test("create_file", "touch", vec!["to_remove.txt"]);
+        test.expect_mut().files = vec![FileExpect {
+            path: PathBuf::from("to_remove.txt"),
+            exists: Some(true),
+            contents: None,
+            sha256: None,
+            is_dir: None,
+            binary: None,
+            bytes_equal: None,
+        }];
+        let mut spec = make_spec(test);
+        spec.teardown = vec![TeardownStep {
+            remove_dir: None,
+            remove_file: Some(PathBuf::from("to_remove.txt")),
+            run: None,
+            ..Default::default()
+        }];
+        let result = run_spec_standalone(&spec);
+
+        // Test should pass (file existed when checked)
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+        // No teardown errors
+        assert!(
+            result
+                .tests
+                .iter()
+                .all(|t| t.name != "<teardown>" || t.passed)
+        );
+    }
+
+    #[test]
+    fn test_setup_only_skips_commands_and_teardown() {
+        let workdir = tempfile::tempdir().unwrap();
+        let mut test = make_test("fixture", "touch", vec!["ran.marker"]);
+        test.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("test.txt"),
+                contents: "test fixture\n".to_string(),
+            }),
+            ..Default::default()
+        }];
+        let mut spec = make_spec(test);
+        spec.sandbox.workdir = WorkDir::Path(workdir.path().to_path_buf());
+        spec.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("file.txt"),
+                contents: "file fixture\n".to_string(),
+            }),
+            ..Default::default()
+        }];
+        spec.teardown = vec![TeardownStep {
+            remove_file: Some(PathBuf::from("file.txt")),
+            ..Default::default()
+        }];
+        let suite_config = SuiteConfig {
+            fixture_mode: FixtureMode::SetupOnly,
+            ..Default::default()
+        };
+        let result = run_spec(&spec, Some(&suite_config));
+
+        assert!(
+            result.tests.iter().all(|t| t.passed),
+            "results: {:?}",
+            result.tests
+        );
+        assert!(workdir.path().join("file.txt").exists());
+        assert!(workdir.path().join("test.txt").exists());
+        assert!(!workdir.path().join("ran.marker").exists());
+    }
+
+    #[test]
+    fn test_teardown_only_skips_setup_and_commands() {
+        let workdir = tempfile::tempdir().unwrap();
+        std::fs::write(workdir.path().join("test.txt"), "").unwrap();
+        std::fs::write(workdir.path().join("file.txt"), "").unwrap();
+
+        let mut test = make_test("fixture", "touch", vec!["ran.marker"]);
+        test.teardown = vec![TeardownStep {
+            remove_file: Some(PathBuf::from("test.txt")),
+            ..Default::default()
+        }];
+        let mut spec = make_spec(test);
+        spec.sandbox.workdir = WorkDir::Path(workdir.path().to_path_buf());
+        spec.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("setup.txt"),
+                contents: String::new(),
+            }),
+            ..Default::default()
+        }];
+        spec.teardown = vec![TeardownStep {
+            remove_file: Some(PathBuf::from("file.txt")),
+            ..Default::default()
+        }];
+        let suite_config = SuiteConfig {
+            fixture_mode: FixtureMode::TeardownOnly,
+            ..Default::default()
+        };
+        let result = run_spec(&spec, Some(&suite_config));
+
+        assert!(
+            result.tests.iter().all(|t| t.passed),
+            "results: {:?}",
+            result.tests
+        );
+        assert!(!workdir.path().join("test.txt").exists());
+        assert!(!workdir.path().join("file.txt").exists());
+        assert!(!workdir.path().join("setup.txt").exists());
+        assert!(!workdir.path().join("ran.marker").exists());
+    }
+
+    /// Run a one-test spec that records its sandbox path, returning the path.
+    fn run_recording_sandbox(preserve: Option<PreservePolicy>, passing: bool) -> PathBuf {
+        let record = tempfile::tempdir().unwrap();
+        let record_path = record.path().join("sandbox_path");
+        let script = format!(
+            "pwd > {}; {}",
+            record_path.display(),
+            if passing { "true" } else { "false" }
+        );
+        let test = make_test("record_sandbox", "sh", vec!["-c", &script]);
+        let mut spec = make_spec(test);
+        spec.sandbox.preserve = preserve;
+        let result = run_spec_standalone(&spec);
+        assert_eq!(result.tests[0].passed, passing);
+
+        PathBuf::from(std::fs::read_to_string(&record_path).unwrap().trim())
+    }
+
+    #[test]
+    fn test_preserve_never_removes_sandbox() {
+        let sandbox = run_recording_sandbox(None, false);
+        assert!(!sandbox.exists());
+    }
+
+    #[test]
+    fn test_preserve_on_failure() {
+        let sandbox = run_recording_sandbox(Some(PreservePolicy::OnFailure), true);
+        assert!(!sandbox.exists());
+
+        let sandbox = run_recording_sandbox(Some(PreservePolicy::OnFailure), false);
+        assert!(sandbox.exists());
+        std::fs::remove_dir_all(sandbox).unwrap();
+    }
+
+    #[test]
+    fn test_preserve_always_from_suite() {
+        let record = tempfile::tempdir().unwrap();
+        let record_path = record.path().join("sandbox_path");
+        let script = format!("pwd > {}", record_path.display());
+        let spec = make_spec(make_test("record_sandbox", "sh", vec!["-c", &script]));
+        let suite_config = SuiteConfig {
+            preserve: Some(PreservePolicy::Always),
+            ..Default::default()
+        };
+        let result = run_spec(&spec, Some(&suite_config));
+        assert!(result.tests[0].passed);
+
+        let sandbox = PathBuf::from(std::fs::read_to_string(&record_path).unwrap().trim());
+        assert!(sandbox.exists());
+        std::fs::remove_dir_all(sandbox).unwrap();
+    }
+
+    #[test]
+    fn test_keep_on_failure_overrides_file_preserve_never() {
+        let record = tempfile::tempdir().unwrap();
+        let record_path = record.path().join("sandbox_path");
+        let script = format!("pwd > {}; false", record_path.display());
+        let mut spec = make_spec(make_test("record_sandbox", "sh", vec!["-c", &script]));
+        spec.sandbox.preserve = Some(PreservePolicy::Never);
+        let suite_config = SuiteConfig {
+            keep_on_failure: true,
+            ..Default::default()
+        };
+        let result = run_spec(&spec, Some(&suite_config));
+        assert!(!result.tests[0].passed);
+
+        let sandbox = PathBuf::from(std::fs::read_to_string(&record_path).unwrap().trim());
+        assert!(sandbox.exists());
+        std::fs::remove_dir_all(sandbox).unwrap();
+    }
+
+    #[test]
+    fn test_forbid_outside_writes() {
+        // Serial, since a parallel test would also see the other's escaped write
+        let mut escapes = make_test("escapes", "sh", vec!["-c", "echo x > ../escaped.txt"]);
+        escapes.serial = true;
+        let mut stays = make_test("stays", "sh", vec!["-c", "echo x > inside.txt"]);
+        stays.serial = true;
+        let mut spec = make_spec(escapes);
+        spec.tests.push(stays);
+        let effective = EffectiveConfig {
+            forbid_outside_writes: true,
+            ..Default::default()
+        };
+        let result = run_spec_with_config(&spec, &effective, &TestFilter::default(), None);
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0]
+                .starts_with("forbid_outside_writes: added path outside the sandbox: ")
+                && result.tests[0].failures[0].ends_with("escaped.txt"),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+        assert!(
+            result.tests[1].passed,
+            "failures: {:?}",
+            result.tests[1].failures
+        );
+    }
+
+    #[test]
+    fn test_fixtures_run_before_test_setup() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+fixtures:
+  config:
+    - create_dir: conf
+    - write_file: { path: conf/app.toml, contents: "mode = 1" }
+tests:
+  - name: reads_config
+    serial: true
+    use_fixtures: [config]
+    run: { cmd: cat, args: [conf/app.toml] }
+    expect:
+      stdout: "mode = 1"
+  - name: overrides_config
+    serial: true
+    use_fixtures: [config]
+    setup:
+      - write_file: { path: conf/app.toml, contents: "mode = 2" }
+    run: { cmd: cat, args: [conf/app.toml] }
+    expect:
+      stdout: "mode = 2"
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        for test in &result.tests {
+            assert!(test.passed, "{}: {:?}", test.name, test.failures);
+        }
+    }
+
+    // ==================== Environment Variable Tests ====================
+
+    #[test]
+    fn test_sandbox_env() {
+        let mut test = make_test("env_test", "sh", vec!["-c", "echo $MY_VAR"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("test_value\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox
+            .env
+            .insert("MY_VAR".to_string(), "test_value".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_command_env_override() {
+        let mut test = make_test("env_override", "sh", vec!["-c", "echo $MY_VAR"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("overridden\n".to_string()));
+        test.run_mut()
+            .env
+            .insert("MY_VAR".to_string(), "overridden".to_string());
+        let mut spec = make_spec(test);
+        spec.sandbox
+            .env
+            .insert("MY_VAR".to_string(), "original".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_setup_run_env_and_cwd() {
+        let mut test = make_test("setup_env", "cat", vec!["out/greeting.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("hello from setup\n".to_string()));
+        test.setup = vec![
+            SetupStep {
+                create_dir: Some(PathBuf::from("out")),
+                ..Default::default()
+            },
+            SetupStep {
+                run: Some(RunStep {
+                    cmd: "sh".to_string(),
+                    args: vec![
+                        "-c".to_string(),
+                        "echo \"$GREETING\" > greeting.txt".to_string(),
+                    ],
+                    env: HashMap::from([("GREETING".to_string(), "hello from setup".to_string())]),
+                    cwd: Some(PathBuf::from("out")),
+                    capture_as: None,
+                }),
+                ..Default::default()
+            },
+        ];
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_env_interpolated_in_args() {
+        // Neither `touch` nor `ls` runs a shell, so only bintest can expand the
+        // reference; `ls` fails unless setup created the interpolated name.
+        let mut test = make_test("arg_env", "ls", vec!["${MY_VAR}.txt"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("test_value.txt\n".to_string()));
+        test.setup = vec![SetupStep {
+            run: Some(RunStep {
+                cmd: "touch".to_string(),
+                args: vec!["${MY_VAR}.txt".to_string()],
+                env: HashMap::new(),
+                cwd: None,
+                capture_as: None,
+            }),
+            ..Default::default()
+        }];
+        let mut spec = make_spec(test);
+        spec.sandbox
+            .env
+            .insert("MY_VAR".to_string(), "test_value".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_unset_env_in_args_is_kept() {
+        let mut test = make_test("unset", "echo", vec!["${BINTEST_UNSET_ARG_12345}"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact(
+            "${BINTEST_UNSET_ARG_12345}\n".to_string(),
+        ));
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_shell_local_variable_in_args_reaches_shell() {
+        let mut test = make_test("loop", "sh", vec!["-c", "for f in a b; do echo ${f}; done"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("a\nb\n".to_string()));
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_host_env_not_interpolated_in_args_without_inherit_env() {
+        // HOME is set on the host, but an isolated sandbox must not see it
+        let mut test = make_test("isolated", "echo", vec!["${HOME}"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("${HOME}\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox.inherit_env = false;
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_env_cleared_without_inherit() {
+        // When inherit_env is false (default), custom host env vars should not be visible
+        // Note: We use a custom var because sh may set default PATH even with cleared env
+        let mut test = make_test(
+            "env_cleared",
+            "sh",
+            vec!["-c", "echo ${BINTEST_CUSTOM_VAR:-empty}"],
+        );
+        test.expect_mut().stdout = Some(OutputMatch::Exact("empty\n".to_string()));
+        let spec = make_spec(test);
+        // Even if BINTEST_CUSTOM_VAR is set in the host, it shouldn't be inherited
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_normalize_locale() {
+        let mut test = make_test("locale", "sh", vec!["-c", "echo $LANG/$LC_ALL"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("C/C\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox.normalize_locale = Some("C".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_normalize_locale_user_override() {
+        let mut test = make_test("locale_override", "sh", vec!["-c", "echo $LANG/$LC_ALL"]);
+        test.run_mut()
+            .env
+            .insert("LC_ALL".to_string(), "en_US.UTF-8".to_string());
+        test.expect_mut().stdout = Some(OutputMatch::Exact("C/en_US.UTF-8\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox.normalize_locale = Some("C".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_inherit_env_except() {
+        if std::env::var_os("HOME").is_none() {
+            return;
+        }
+        let mut test = make_test(
+            "inherit_except",
+            "sh",
+            vec!["-c", "echo ${HOME-unset} ${PATH:+path}"],
+        );
+        test.expect_mut().stdout = Some(OutputMatch::Exact("unset path\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox.inherit_env = true;
+        spec.sandbox.inherit_env_except = vec!["HOME".to_string()];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_inherit_env_except_keeps_explicit_env() {
+        let mut test = make_test("inherit_except_explicit", "sh", vec!["-c", "echo $HOME"]);
+        test.expect_mut().stdout = Some(OutputMatch::Exact("/sandbox/home\n".to_string()));
+        let mut spec = make_spec(test);
+        spec.sandbox.inherit_env = true;
+        spec.sandbox.inherit_env_except = vec!["HOME".to_string()];
+        spec.sandbox
+            .env
+            .insert("HOME".to_string(), "/sandbox/home".to_string());
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== Stdin Tests ====================
+
+    #[test]
+    fn test_stdin() {
+        let mut test = make_test("stdin_test", "cat", vec![]);
+        test.run_mut().stdin = Some("input data".to_string());
+        test.expect_mut().stdout = Some(OutputMatch::Exact("input data".to_string()));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_stdin_file() {
+        let mut test = make_test("stdin_file_test", "cat", vec![]);
+        test.setup = vec![SetupStep {
+            write_file: Some(WriteFile {
+                path: PathBuf::from("input.txt"),
+                contents: "from file\n".to_string(),
+            }),
+            ..Default::default()
+        }];
+        test.run_mut().stdin_file = Some(PathBuf::from("input.txt"));
+        test.expect_mut().stdout = Some(OutputMatch::Exact("from file\n".to_string()));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_stdin_file_large_binary() {
+        // 1 MiB of zero bytes: larger than a pipe buffer in both directions
+        let mut test = make_test("stdin_file_large", "sh", vec!["-c", "cat | wc -c"]);
+        test.setup = vec![SetupStep {
+            run: Some(RunStep {
+                cmd: "sh".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "head -c 1048576 /dev/zero > big.bin".to_string(),
+                ],
+                env: HashMap::new(),
+                cwd: None,
+                capture_as: None,
+            }),
+            ..Default::default()
+        }];
+        test.run_mut().stdin_file = Some(PathBuf::from("big.bin"));
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: Some("1048576".to_string()),
+            regex: None,
+            ..Default::default()
+        }));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_stdin_not_consumed_is_not_an_error() {
+        // `true` exits without reading; a large input hits a broken pipe
+        let mut test = make_test("stdin_ignored", "true", vec![]);
+        test.run_mut().stdin = Some("x".repeat(1 << 20));
+        test.expect_mut().stdin_consumed = Some(false);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_stdin_consumed_fails_on_early_close() {
+        let mut test = make_test("stdin_early_close", "true", vec![]);
+        test.run_mut().stdin = Some("x".repeat(1 << 20));
+        test.expect_mut().stdin_consumed = Some(true);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("closed stdin early"));
+    }
+
+    #[test]
+    fn test_stdin_consumed_passes_when_read() {
+        let mut test = make_test("stdin_read", "sh", vec!["-c", "cat > /dev/null"]);
+        test.run_mut().stdin = Some("x".repeat(1 << 20));
+        test.expect_mut().stdin_consumed = Some(true);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== Shell Mode Tests ====================
+
+    #[test]
+    fn test_shell_mode() {
+        let mut test = make_test("shell_test", "echo hello && echo", vec!["world"]);
+        test.run_mut().shell = true;
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: Some("hello\nworld".to_string()),
+            regex: None,
+            ..Default::default()
+        }));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_program_selects_bash() {
+        // `[[` is bash-only; sh has no such command
+        let mut test = make_test("pattern", "[[ abc == a* ]] && echo", vec!["b"]);
+        test.run_mut().shell = true;
+        test.expect_mut().stdout = Some(OutputMatch::Exact("b\n".to_string()));
+
+        let mut per_run = test.clone();
+        per_run.run_mut().shell_program = Some("bash".to_string());
+        let result = run_spec(&make_spec(per_run), None);
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+
+        let suite = SuiteConfig {
+            shell_program: Some("/bin/bash".to_string()),
+            ..Default::default()
+        };
+        let result = run_spec(&make_spec(test), Some(&suite));
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_shell_mode_keeps_arguments_whole() {
+        let mut test = make_test(
+            "quoted",
+            "printf '[%s]'",
+            vec!["a b", "it's", "$HOME", "x;y", ""],
+        );
+        test.run_mut().shell = true;
+        test.expect_mut().stdout =
+            Some(OutputMatch::Exact("[a b][it's][$HOME][x;y][]".to_string()));
+        let result = run_spec(&make_spec(test), None);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain-word_1.txt"), "plain-word_1.txt");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(r#"say "hi""#), r#"'say "hi"'"#);
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_cmd_quote() {
+        assert_eq!(cmd_quote("plain"), "plain");
+        assert_eq!(cmd_quote("a b"), r#""a b""#);
+        assert_eq!(cmd_quote(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(cmd_quote("a&b"), r#""a&b""#);
+    }
+
+    #[test]
+    fn test_shell_command_flag() {
+        assert_eq!(shell_command_flag("sh"), "-c");
+        assert_eq!(shell_command_flag("/usr/bin/bash"), "-c");
+        assert_eq!(shell_command_flag("cmd"), "/C");
+        assert_eq!(shell_command_flag("CMD.EXE"), "/C");
+    }
+
+    // ==================== Timeout Tests ====================
+
+    #[test]
+    fn test_timeout() {
+        let mut test = make_test("timeout_test", "sleep", vec!["10"]);
+        test.timeout = Some(1); // 1 second timeout
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("timed out"));
+    }
+
+    #[test]
+    fn test_max_duration_exceeded() {
+        let mut test = make_test("slow_command", "sleep", vec!["0.2"]);
+        test.expect_mut().max_duration_ms = Some(50);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0].starts_with("Duration: expected <= 50ms, took "),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_max_duration_within_budget() {
+        let mut test = make_test("fast_command", "true", vec![]);
+        test.expect_mut().max_duration_ms = Some(2000);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_timeout_sends_sigterm_before_kill() {
+        let workdir = tempfile::tempdir().unwrap();
+        let mut test = make_test(
+            "graceful_timeout",
+            "sh",
+            vec![
+                "-c",
+                "trap 'touch terminated.marker; exit 0' TERM; while true; do sleep 0.05; done",
+            ],
+        );
+        test.timeout = Some(1);
+        let mut spec = make_spec(test);
+        spec.sandbox.workdir = WorkDir::Path(workdir.path().to_path_buf());
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("timed out"));
+        assert!(
+            workdir.path().join("terminated.marker").exists(),
+            "SIGTERM handler should have run"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_timeout_kills_process_group() {
+        let workdir = tempfile::tempdir().unwrap();
+        let mut test = make_test(
+            "background_child",
+            "sh",
+            vec!["-c", "sleep 30 & echo $! > bg.pid; wait"],
+        );
+        test.timeout = Some(1);
+        let mut spec = make_spec(test);
+        spec.sandbox.workdir = WorkDir::Path(workdir.path().to_path_buf());
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("timed out"));
+
+        let pid: libc::pid_t = std::fs::read_to_string(workdir.path().join("bg.pid"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // The orphaned sleep is reaped by init asynchronously; allow it a moment
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut alive = true;
+        while alive && Instant::now() < deadline {
+            alive = unsafe { libc::kill(pid, 0) } == 0;
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(
+            !alive,
+            "backgrounded sleep should be killed with its parent"
+        );
+    }
+
+    // ==================== Signal Tests ====================
+
+    #[test]
+    #[cfg(unix)]
+    fn test_signal_assertion_passes() {
+        // Test that expects SIGKILL (9) - use sh to send kill signal to itself
+        let mut test = make_test(
+            "signal_test",
+            "sh",
+            vec!["-c", "kill -9 $$"], // $$ is the shell's PID
+        );
+        test.expect_mut().signal = Some(Signal(9)); // SIGKILL
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_signal_assertion_wrong_signal() {
+        // Test expects SIGTERM (15) but gets SIGKILL (9)
+        let mut test = make_test("signal_mismatch", "sh", vec!["-c", "kill -9 $$"]);
+        test.expect_mut().signal = Some(Signal(15)); // SIGTERM
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0].contains("Signal: expected SIGTERM(15), got SIGKILL(9)")
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_signal_assertion_by_name() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: by_name
+    run: { cmd: sh, args: ["-c", "kill -9 $$"] }
+    expect: { signal: SIGKILL }
+  - name: short_lowercase
+    run: { cmd: sh, args: ["-c", "kill -15 $$"] }
+    expect: { signal: term }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        for test in &result.tests {
+            assert!(test.passed, "{}: {:?}", test.name, test.failures);
+        }
+
+        let err = serde_yaml::from_str::<Expect>("signal: SIGNOPE").unwrap_err();
+        assert!(
+            err.to_string().contains("unknown signal name: SIGNOPE"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_signal_expected_but_normal_exit() {
+        // Test expects a signal but process exits normally
+        let mut test = make_test("signal_expected_normal_exit", "true", vec![]);
+        test.expect_mut().signal = Some(Signal(9));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("but process exited with exit code 0"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_exit_expected_but_signal_received() {
+        // Test expects exit code 0 but gets killed by signal
+        let mut test = make_test("exit_expected_signal", "sh", vec!["-c", "kill -9 $$"]);
+        test.expect_mut().exit = Some(ExitMatch::Code(0));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("terminated by signal SIGKILL(9)"));
+    }
+
+    // ==================== Multiple Tests ====================
+
+    #[test]
+    fn test_multiple_tests_in_spec() {
+        let test1 = make_test("test_one", "echo", vec!["one"]);
+        let test2 = make_test("test_two", "echo", vec!["two"]);
+        let spec = TestSpec {
+            version: 1,
+            binary: None,
+            resolved_binary: None,
+            source_path: None,
+            replay: vec![],
+            env: HashMap::new(),
+            vars: HashMap::new(),
+            sandbox: Sandbox::default(),
+            timeout: None,
+            capture_fs_diff: None,
+            databases: HashMap::new(),
+            setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
+            tests: vec![test1, test2],
+            teardown: vec![],
+        };
+        let result = run_spec_standalone(&spec);
+
+        assert_eq!(result.tests.len(), 2);
+        assert!(result.tests[0].passed);
+        assert!(result.tests[1].passed);
+        assert_eq!(result.tests[0].name, "test_one");
+        assert_eq!(result.tests[1].name, "test_two");
+    }
+
+    #[test]
+    fn test_shared_sandbox_between_tests() {
+        // First test creates a file, second test reads it
+        // Both must be serial since test2 depends on test1's output
+        let mut test1 = make_test("create_file", "sh", vec!["-c", "echo shared > shared.txt"]);
+        test1.expect_mut().exit = Some(ExitMatch::Code(0));
+        test1.serial = true;
+        let mut test2 = make_test("read_file", "cat", vec!["shared.txt"]);
+        test2.expect_mut().stdout = Some(OutputMatch::Exact("shared\n".to_string()));
+        test2.serial = true;
+        let spec = TestSpec {
+            version: 1,
+            binary: None,
+            resolved_binary: None,
+            source_path: None,
+            replay: vec![],
+            env: HashMap::new(),
+            vars: HashMap::new(),
+            sandbox: Sandbox::default(),
+            timeout: None,
+            capture_fs_diff: None,
+            databases: HashMap::new(),
+            setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
+            tests: vec![test1, test2],
+            teardown: vec![],
+        };
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "test1 failures: {:?}",
+            result.tests[0].failures
+        );
+        assert!(
+            result.tests[1].passed,
+            "test2 failures: {:?}",
+            result.tests[1].failures
+        );
+    }
+
+    // ==================== Working Directory Tests ====================
+
+    #[test]
+    fn test_custom_cwd() {
+        let mut test = make_test("cwd_test", "pwd", vec![]);
+        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
+            equals: None,
+            contains: Some("subdir".to_string()),
+            regex: None,
+            ..Default::default()
+        }));
+        test.run_mut().cwd = Some(PathBuf::from("subdir"));
+        let mut spec = make_spec(test);
+        spec.setup = vec![SetupStep {
+            write_file: None,
+            create_dir: Some(PathBuf::from("subdir")),
+            copy_file: None,
+            copy_dir: None,
+            run: None,
+            ..Default::default()
+        }];
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_test_level_cwd_applies_to_steps() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: workflow
+    cwd: work
+    setup:
+      - create_dir: work/nested
+    steps:
+      - name: inherited
+        run: { cmd: pwd }
+        expect:
+          stdout: { regex: "/work\n$" }
+      - name: overridden
+        run: { cmd: pwd, cwd: work/nested }
+        expect:
+          stdout: { regex: "/work/nested\n$" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== Command Not Found ====================
+
+    #[test]
+    fn test_command_not_found() {
+        let test = make_test("not_found", "nonexistent_command_12345", vec![]);
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert!(result.tests[0].failures[0].contains("Failed to spawn"));
+    }
+
+    // ==================== Multi-Step Tests ====================
+
+    #[test]
+    fn test_multi_step_failure_phase() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: setup_fails
+    steps:
+      - name: first
+        setup:
+          - run: { cmd: "false" }
+        run: { cmd: "true" }
+  - name: command_fails
+    steps:
+      - name: first
+        run: { cmd: "true" }
+      - name: second
+        run: { cmd: "/nonexistent/bintest-command" }
+  - name: assertion_fails
+    steps:
+      - name: first
+        run: { cmd: "true" }
+        expect: { exit: 1 }
+      - name: never_runs
+        run: { cmd: "true" }
+  - name: teardown_fails
+    steps:
+      - name: first
+        run: { cmd: "true" }
+        teardown:
+          - run: { cmd: "false" }
+      - name: never_runs
+        run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        let phases: Vec<_> = result
+            .tests
+            .iter()
+            .map(|t| {
+                let step = t.failed_step.as_ref().expect("failed_step should be set");
+                (t.name.as_str(), step.name.as_str(), step.index, step.phase)
+            })
+            .collect();
+        assert_eq!(
+            phases,
+            vec![
+                ("setup_fails", "first", 0, StepPhase::Setup),
+                ("command_fails", "second", 1, StepPhase::Command),
+                ("assertion_fails", "first", 0, StepPhase::Assertion),
+                ("teardown_fails", "first", 0, StepPhase::Teardown),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_step_timeout_reports_step() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: hangs
+    timeout: 1
+    steps:
+      - name: quick
+        run: { cmd: "true" }
+      - name: sleeps
+        run: { cmd: sleep, args: ["5"] }
+      - name: never_runs
+        run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        let test = &result.tests[0];
+        let step = test
+            .failed_step
+            .as_ref()
+            .expect("failed_step should be set");
+        assert_eq!(
+            (step.name.as_str(), step.index, step.phase),
+            ("sleeps", 1, StepPhase::Command)
+        );
+        assert_eq!(
+            test.failures,
+            vec!["Step 'sleeps' [1] execution failed: Command timed out after 1s"]
+        );
+    }
+
+    #[test]
+    fn test_step_results() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: workflow
+    steps:
+      - name: ok
+        run: { cmd: "true" }
+      - name: exits_3
+        run: { cmd: sh, args: ["-c", "exit 3"] }
+      - name: never_runs
+        run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        let steps: Vec<_> = result.tests[0]
+            .steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.passed, s.exit_code))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![("ok", true, Some(0)), ("exits_3", false, Some(3))]
+        );
+    }
+
+    #[test]
+    fn test_step_expect_failure() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: assertion_fails
+    steps:
+      - name: rejected
+        run: { cmd: "false" }
+        expect: { exit: 0 }
+        expect_failure: true
+      - name: continues
+        run: { cmd: "true" }
+  - name: spawn_fails
+    steps:
+      - name: missing
+        run: { cmd: "/nonexistent/bintest-command" }
+        expect_failure: true
+  - name: unexpectedly_passes
+    steps:
+      - name: accepted
+        run: { cmd: "true" }
+        expect_failure: true
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(result.tests[1].passed, "{:?}", result.tests[1].failures);
+
+        let unexpected = &result.tests[2];
+        assert!(!unexpected.passed);
+        assert_eq!(
+            unexpected.failures,
+            vec!["Step 'accepted' [0]: step expected to fail but passed"]
+        );
+        assert_eq!(
+            unexpected.failed_step.as_ref().map(|s| s.phase),
+            Some(StepPhase::Assertion)
+        );
+    }
+
+    #[test]
+    fn test_deterministic_reruns_from_same_sandbox_state() {
+        // The second run starts from the pre-run sandbox (no marker yet), and
+        // its sandbox path in the output is normalized to the original
+        let mut test = make_test(
+            "stable",
+            "sh",
+            vec!["-c", "pwd; test ! -e marker && touch marker"],
+        );
+        test.deterministic = true;
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_deterministic_detects_differing_output() {
+        let mut test = make_test("unstable", "sh", vec!["-c", "echo $$"]);
+        test.deterministic = true;
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert_eq!(result.tests[0].failures.len(), 1);
+        assert!(
+            result.tests[0].failures[0].starts_with("deterministic: stdout differs between runs"),
+            "{:?}",
+            result.tests[0].failures
+        );
+    }
+
+    // ==================== Matrix Tests ====================
+
+    #[test]
+    fn test_matrix_expands_cartesian_product() {
+        let mut test = make_test(
+            "build",
+            "sh",
+            vec!["-c", "echo ${matrix.os}-${matrix.arch}-$TARGET"],
+        );
+        test.run_mut()
+            .env
+            .insert("TARGET".to_string(), "${matrix.arch}".to_string());
+        test.expect_mut().stdout = Some(OutputMatch::Exact(
+            "${matrix.os}-${matrix.arch}-${matrix.arch}\n".to_string(),
+        ));
+        test.matrix = Some(HashMap::from([
+            (
+                "os".to_string(),
+                vec!["linux".to_string(), "macos".to_string()],
+            ),
+            (
+                "arch".to_string(),
+                vec!["x64".to_string(), "arm64".to_string()],
+            ),
+        ]));
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        let names: Vec<_> = result.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "build[arch=x64,os=linux]",
+                "build[arch=x64,os=macos]",
+                "build[arch=arm64,os=linux]",
+                "build[arch=arm64,os=macos]",
+            ]
+        );
+        for test in &result.tests {
+            assert!(test.passed, "{}: {:?}", test.name, test.failures);
+        }
+    }
+
+    #[test]
+    fn test_matrix_filter_selects_combination() {
+        let mut test = make_test("greet", "echo", vec!["${matrix.lang}"]);
+        test.matrix = Some(HashMap::from([(
+            "lang".to_string(),
+            vec!["en".to_string(), "fr".to_string()],
+        )]));
+        let spec = make_spec(test);
+        let filter = TestFilter {
+            name: Some("lang=fr".to_string()),
+            ..Default::default()
+        };
+        let result = run_spec_filtered(&spec, None, &filter, None);
+
+        assert_eq!(result.tests.len(), 1);
+        assert_eq!(result.tests[0].name, "greet[lang=fr]");
+    }
+
+    // ==================== Variable Tests ====================
+
+    #[test]
+    fn test_vars_expand_in_args_and_matchers() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+vars:
+  port: "8080"
+tests:
+  - name: listens
+    run:
+      cmd: echo
+      args: ["--port=${vars.port}"]
+    expect:
+      stdout: { contains: "port=${vars.port}" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_vars_precedence() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+vars:
+  host: file-host
+  lang: file-lang
+tests:
+  - name: greet
+    matrix:
+      lang: [fr]
+    run:
+      cmd: echo
+      args: ["${vars.host} ${vars.region} ${vars.lang}"]
+    expect:
+      stdout: "file-host suite-region fr\n"
+"#,
+        )
+        .unwrap();
+        let suite = SuiteConfig {
+            vars: HashMap::from([
+                ("host".to_string(), "suite-host".to_string()),
+                ("region".to_string(), "suite-region".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let result = run_spec(&spec, Some(&suite));
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_setup_capture_as_feeds_later_steps() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+setup:
+  - run:
+      cmd: echo
+      args: ["  3f2b9c1e-7d4a-4e21-9b0f-5c6d8e7a1b2c  "]
+      capture_as: session
+tests:
+  - name: uses_file_capture
+    run:
+      cmd: echo
+      args: ["session=${vars.session}"]
+    expect:
+      stdout: "session=3f2b9c1e-7d4a-4e21-9b0f-5c6d8e7a1b2c\n"
+  - name: uses_test_capture
+    setup:
+      - run:
+          cmd: sh
+          args: ["-c", "echo token-${vars.session} > token.txt; cat token.txt"]
+          capture_as: token
+    steps:
+      - name: read
+        run:
+          cmd: cat
+          args: ["token.txt"]
+        expect:
+          stdout: { contains: "${vars.token}" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        for test in &result.tests {
+            assert!(test.passed, "{}: {:?}", test.name, test.failures);
+        }
+    }
+
+    #[test]
+    fn test_step_platform_limits_where_it_runs() {
+        let (here, elsewhere) = if cfg!(target_os = "macos") {
+            ("macos", "linux")
+        } else if cfg!(target_os = "windows") {
+            ("windows", "linux")
+        } else {
+            ("linux", "macos")
+        };
+        let spec: TestSpec = serde_yaml::from_str(&format!(
+            r#"
+version: 1
+tests:
+  - name: per_platform
+    steps:
+      - name: native
+        platform: [{elsewhere}, {here}]
+        run:
+          cmd: echo
+          args: ["{here}"]
+        expect:
+          stdout: "{here}\n"
+      - name: foreign
+        platform: [{elsewhere}]
+        run:
+          cmd: echo
+          args: ["{elsewhere}"]
+        expect:
+          stdout: "{here}\n"
+  - name: foreign_only
+    steps:
+      - name: foreign
+        platform: [{elsewhere}]
+        run:
+          cmd: "false"
+"#
+        ))
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        let test = &result.tests[0];
+        assert!(test.passed && !test.skipped, "{:?}", test.failures);
+        let steps: Vec<_> = test.steps.iter().map(|s| (s.passed, s.skipped)).collect();
+        assert_eq!(steps, vec![(true, false), (true, true)]);
+
+        let test = &result.tests[1];
+        assert!(test.passed && test.skipped, "{test:?}");
+        assert_eq!(
+            test.skip_reason,
+            Some(format!(
+                "platform: no step runs on {}",
+                std::env::consts::OS
+            ))
+        );
+    }
+
+    #[test]
+    fn test_env_applies_to_every_step() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+env:
+  LEVEL: file
+sandbox:
+  env:
+    SANDBOX_ONLY: sandbox
+tests:
+  - name: shared_env
+    env:
+      LEVEL: test
+    steps:
+      - name: first
+        run:
+          cmd: sh
+          args: ["-c", "echo $LEVEL $SANDBOX_ONLY"]
+        expect:
+          stdout: "test sandbox\n"
+      - name: second
+        run:
+          cmd: sh
+          args: ["-c", "echo $LEVEL"]
+        expect:
+          stdout: "test\n"
+      - name: command_wins
+        run:
+          cmd: sh
+          args: ["-c", "echo $LEVEL"]
+          env:
+            LEVEL: command
+        expect:
+          stdout: "command\n"
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+    }
+
+    #[test]
+    fn test_step_capture_feeds_later_steps() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: login_then_use
+    steps:
+      - name: login
+        run:
+          cmd: sh
+          args: ["-c", "echo 'token: abc123'; echo warn-1 >&2"]
+        capture:
+          line: stdout
+          warning: stderr
+          token:
+            regex: "token: (\\w+)"
+      - name: use
+        run:
+          cmd: echo
+          args: ["${vars.token}", "${vars.warning}", "${vars.line}"]
+        expect:
+          stdout: "abc123 warn-1 token: abc123\n"
+  - name: unmatched
+    steps:
+      - name: login
+        run:
+          cmd: echo
+          args: ["denied"]
+        capture:
+          token: { regex: "token: (\\w+)" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(!result.tests[1].passed);
+        assert!(
+            result.tests[1].failures[0].contains("capture 'token': regex"),
+            "{:?}",
+            result.tests[1].failures
+        );
+    }
+
+    #[test]
+    fn test_capture_from_another_test_is_undefined() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: captures
+    serial: true
+    setup:
+      - run: { cmd: echo, args: ["x"], capture_as: bintest_private_12345 }
+    run: { cmd: "true" }
+  - name: reads
+    serial: true
+    run:
+      cmd: echo
+      args: ["${vars.bintest_private_12345}"]
+"#,
+        )
+        .unwrap();
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(!result.tests[1].passed);
+        assert!(
+            result.tests[1].failures[0]
+                .contains("Variable 'vars.bintest_private_12345' is not defined"),
+            "{:?}",
+            result.tests[1].failures
+        );
+    }
+
+    #[test]
+    fn test_undefined_var_fails_test() {
+        let test = make_test("missing", "echo", vec!["${vars.bintest_undefined_12345}"]);
+        let result = run_spec_standalone(&make_spec(test));
+
+        assert!(!result.tests[0].passed);
+        assert!(
+            result.tests[0].failures[0]
+                .contains("Variable 'vars.bintest_undefined_12345' is not defined"),
+            "{:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_replay_applies_to_expanded_matrix_tests() {
+        let mut test = make_test("greet", "echo", vec!["${matrix.lang}"]);
+        test.matrix = Some(HashMap::from([(
+            "lang".to_string(),
+            vec!["en".to_string(), "fr".to_string()],
+        )]));
+        let mut spec = make_spec(test);
+        spec.replay = vec![RecordedRun {
+            test: "greet[lang=fr]".to_string(),
+            step: "run".to_string(),
+            cmd: "echo".to_string(),
+            args: vec!["bonjour".to_string()],
+            stdin: None,
+            exit_code: Some(0),
+            signal: None,
+            stdout: "bonjour\n".to_string(),
+            stderr: String::new(),
+        }];
+        let result = run_spec_standalone(&spec);
+
+        assert!(result.tests[0].passed, "{:?}", result.tests[0].failures);
+        assert!(!result.tests[1].passed);
+        assert!(result.tests[1].failures[0].starts_with("stdout: expected exact match"));
+    }
+
+    // ==================== Shuffle Tests ====================
+
+    #[test]
+    fn test_shuffle_is_reproducible_for_a_seed() {
+        let declared: Vec<usize> = (0..20).collect();
+        let shuffled = |seed| {
+            let mut order = declared.clone();
+            shuffle_tests(&mut order, seed);
+            order
+        };
+
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), declared);
+        assert_ne!(shuffled(42), shuffled(43));
+    }
+
+    #[test]
+    fn test_shuffled_results_keep_declaration_order() {
+        let mut spec = make_spec(make_test("first", "true", vec![]));
+        for name in ["second", "third", "fourth"] {
+            spec.tests.push(make_test(name, "true", vec![]));
+        }
+        spec.tests[0].serial = true;
+        let effective = EffectiveConfig {
+            shuffle_seed: Some(7),
+            ..Default::default()
+        };
+        let result = run_spec_with_config(&spec, &effective, &TestFilter::default(), None);
+
+        let names: Vec<_> = result.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["first", "second", "third", "fourth"]);
+    }
+
+    #[test]
+    fn test_spec_sandbox_names_are_distinct_in_parallel() {
+        let root = tempfile::tempdir().unwrap();
+        let effective = EffectiveConfig {
+            sandbox_dir: Some(SandboxDir::Path(root.path().to_path_buf())),
+            sandbox_name: SandboxName::Spec,
+            ..Default::default()
+        };
+        let specs: Vec<_> = ["a/login.yaml", "b/login.yaml", "c/sign up.yaml"]
+            .into_iter()
+            .map(|path| {
+                let mut spec = make_spec(make_test("pwd", "pwd", vec![]));
+                spec.source_path = Some(PathBuf::from(path));
+                spec
+            })
+            .collect();
+
+        let results: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = specs
+                .iter()
+                .map(|spec| {
+                    s.spawn(|| run_spec_with_config(spec, &effective, &TestFilter::default(), None))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(results.iter().all(|r| r.tests[0].passed));
+
+        let mut names: Vec<_> = std::fs::read_dir(root.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["login", "login-2", "sign_up"]);
+    }
+
+    // ==================== Tag Filter Tests ====================
+
+    fn tagged_spec() -> TestSpec {
+        let mut spec = make_spec(make_test("untagged", "true", vec![]));
+        for (name, tags) in [
+            ("fast", vec!["fast"]),
+            ("slow", vec!["slow"]),
+            ("fast_network", vec!["fast", "network"]),
+        ] {
+            let mut test = make_test(name, "true", vec![]);
+            test.tags = tags.into_iter().map(String::from).collect();
+            spec.tests.push(test);
+        }
+        spec
+    }
+
+    fn filtered_names(filter: &TestFilter) -> Vec<String> {
+        run_spec_filtered(&tagged_spec(), None, filter, None)
+            .tests
+            .into_iter()
+            .map(|t| t.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_tag_filter_include() {
+        let filter = TestFilter {
+            tags: vec!["fast".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(filtered_names(&filter), vec!["fast", "fast_network"]);
+    }
+
+    #[test]
+    fn test_tag_filter_exclude() {
+        let filter = TestFilter {
+            exclude_tags: vec!["network".to_string(), "slow".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(filtered_names(&filter), vec!["untagged", "fast"]);
+    }
+
+    #[test]
+    fn test_tag_filter_include_and_exclude() {
+        let filter = TestFilter {
+            name: None,
+            tags: vec!["fast".to_string(), "slow".to_string()],
+            exclude_tags: vec!["network".to_string()],
+            names: None,
+            only: false,
+        };
+        assert_eq!(filtered_names(&filter), vec!["fast", "slow"]);
+    }
+
+    #[test]
+    fn test_names_filter_combines_with_tags() {
+        let filter = TestFilter {
+            tags: vec!["fast".to_string()],
+            names: Some(vec!["fast".to_string(), "untagged".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(filtered_names(&filter), vec!["fast"]);
+    }
+
+    #[test]
+    fn test_only_filter_selects_marked_tests() {
+        let mut spec = tagged_spec();
+        spec.tests[2].only = true;
+        spec.tests[3].only = true;
+        let filter = TestFilter {
+            only: true,
+            exclude_tags: vec!["network".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<_> = run_spec_filtered(&spec, None, &filter, None)
+            .tests
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["slow"]);
+
+        // Without the run-wide flag `only` has no effect on its own
+        let result = run_spec_filtered(&spec, None, &TestFilter::default(), None);
+        assert_eq!(result.tests.len(), 4);
+    }
+
+    #[test]
+    fn test_explicit_skip() {
+        let mut test = make_test("skipped", "false", vec![]);
+        test.skip = true;
+        let result = run_spec(&make_spec(test), None);
+
+        let test = &result.tests[0];
+        assert!(test.passed && test.skipped, "{test:?}");
+        assert_eq!(test.skip_reason.as_deref(), Some("explicitly skipped"));
+        assert!(test.steps.is_empty());
+    }
+
+    #[test]
+    fn test_file_conditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().join("built-binary");
+        std::fs::write(&present, "").unwrap();
+        let missing = dir.path().join("missing");
+        let spec: TestSpec = serde_yaml::from_str(&format!(
+            r#"
+version: 1
+tests:
+  - name: require_present
+    require: [{{ file: "{present}" }}]
+    run: {{ cmd: "true" }}
+  - name: require_missing
+    require: [{{ file: "{missing}" }}]
+    run: {{ cmd: "false" }}
+  - name: skip_if_present
+    skip_if: [{{ file: "{present}" }}]
+    run: {{ cmd: "false" }}
+"#,
+            present = present.display(),
+            missing = missing.display(),
+        ))
+        .unwrap();
+        let result = run_spec(&spec, None);
+
+        let outcomes: Vec<_> = result
+            .tests
+            .iter()
+            .map(|t| (t.passed, t.skip_reason.clone()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (true, None),
+                (
+                    true,
+                    Some(format!(
+                        "require: file '{}' does not exist",
+                        missing.display()
+                    ))
+                ),
+                (
+                    true,
+                    Some(format!("skip_if: file '{}' exists", present.display()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_equals_conditions() {
+        // SAFETY: only this test reads or writes BINTEST_CONDITION_MODE
+        unsafe {
+            std::env::set_var("BINTEST_CONDITION_MODE", "ci");
+        }
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+tests:
+  - name: require_matching
+    require: [{ env: BINTEST_CONDITION_MODE, equals: ci }]
+    run: { cmd: "true" }
+  - name: require_other
+    require: [{ env: BINTEST_CONDITION_MODE, equals: local }]
+    run: { cmd: "false" }
+  - name: skip_if_matching
+    skip_if: [{ env: BINTEST_CONDITION_MODE, equals: ci }]
+    run: { cmd: "false" }
+  - name: skip_if_other
+    skip_if: [{ env: BINTEST_CONDITION_MODE, equals: local }]
+    run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+        let result = run_spec(&spec, None);
+
+        let outcomes: Vec<_> = result
+            .tests
+            .iter()
+            .map(|t| (t.passed, t.skip_reason.as_deref()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (true, None),
+                (
+                    true,
+                    Some(
+                        "require: environment variable 'BINTEST_CONDITION_MODE' does not equal 'local'"
+                    )
+                ),
+                (
+                    true,
+                    Some("skip_if: environment variable 'BINTEST_CONDITION_MODE' equals 'ci'")
+                ),
+                (true, None),
+            ]
+        );
+    }
+
+    // ==================== SQL Assertion Tests ====================
+
+    fn sql_rows_spec(rows: &str) -> TestSpec {
+        serde_yaml::from_str(&format!(
+            r#"
+version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+setup:
+  - sql:
+      statements:
+        - "CREATE TABLE users (id INTEGER, name TEXT)"
+        - "INSERT INTO users VALUES (1, 'alice'), (2, 'bob')"
+tests:
+  - name: rows
+    run: {{ cmd: "true" }}
+    expect:
+      sql:
+        - query: "SELECT id, name FROM users ORDER BY id"
+          returns:
+            rows: {rows}
+"#
+        ))
+        .unwrap()
+    }
+
+    fn sql_file_spec(sql: &str, options: &str) -> TestSpec {
+        serde_yaml::from_str(&format!(
+            r#"
+version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+sandbox:
+  env:
+    SEED_NAME: alice
+setup:
+  - write_file:
+      path: seed.sql
+      contents: {sql:?}
+  - sql_file: {{ path: seed.sql, {options} }}
+tests:
+  - name: seeded
+    run: {{ cmd: "true" }}
+    expect:
+      sql:
+        - query: "SELECT name FROM users ORDER BY name"
+          returns:
+            rows: [[alice]]
+"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sql_file_split_and_interpolate() {
+        let spec = sql_file_spec(
+            "CREATE TABLE users (name TEXT);\nINSERT INTO users VALUES ('${SEED_NAME}');\n",
+            "split: true, interpolate: true",
+        );
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_sql_file_split_keeps_literals_and_trigger_bodies_whole() {
+        let sql = "CREATE TABLE users (name TEXT);\n\
+                   CREATE TABLE log (entry TEXT);\n\
+                   CREATE TRIGGER log_users AFTER INSERT ON users BEGIN\n\
+                   INSERT INTO log VALUES ('added;' || new.name);\n\
+                   END;\n\
+                   INSERT INTO users VALUES ('a;b');\n\
+                   DELETE FROM users WHERE name = 'a;b' AND (SELECT COUNT(*) FROM log) = 1;\n\
+                   INSERT INTO users VALUES ('${SEED_NAME}');\n";
+        let mut spec = sql_file_spec(sql, "split: true, interpolate: true");
+        // A `;` in an interpolated value stays inside its statement
+        spec.sandbox
+            .env
+            .insert("SEED_NAME".to_string(), "bob; carol".to_string());
+        spec.tests[0].steps[0].expect.sql[0].returns = Some(crate::schema::SqlReturns::Structured(
+            crate::schema::SqlReturnsStructured {
+                rows: Some(vec![vec!["bob; carol".into()]]),
+                ..Default::default()
+            },
+        ));
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
+
+    #[test]
+    fn test_sql_file_split_reports_failing_statement() {
+        let sql = "CREATE TABLE users (name TEXT);\nINSERT INTO missing VALUES (1);\nINSERT INTO users VALUES ('alice');\n";
+        let result = run_spec_standalone(&sql_file_spec(sql, "split: true"));
         assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("timed out"));
-    }
+        assert!(
+            result.tests[0].failures[0].contains("SQL file seed.sql statement 2 failed"),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
 
-    // ==================== Signal Tests ====================
+        // With on_error: continue, the remaining statements still run
+        let result = run_spec_standalone(&sql_file_spec(sql, "split: true, on_error: continue"));
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+    }
 
     #[test]
-    #[cfg(unix)]
-    fn test_signal_assertion_passes() {
-        // Test that expects SIGKILL (9) - use sh to send kill signal to itself
-        let mut test = make_test(
-            "signal_test",
-            "sh",
-            vec!["-c", "kill -9 $$"], // $$ is the shell's PID
-        );
-        test.expect_mut().signal = Some(9); // SIGKILL
-        let spec = make_spec(test);
+    fn test_sql_returns_rows() {
+        let spec = sql_rows_spec(r#"[[1, alice], ["2", "bob"]]"#);
         let result = run_spec_standalone(&spec);
 
         assert!(
@@ -2369,148 +7204,197 @@ mod tests {
     }
 
     #[test]
-    #[cfg(unix)]
-    fn test_signal_assertion_wrong_signal() {
-        // Test expects SIGTERM (15) but gets SIGKILL (9)
-        let mut test = make_test("signal_mismatch", "sh", vec!["-c", "kill -9 $$"]);
-        test.expect_mut().signal = Some(15); // SIGTERM
-        let spec = make_spec(test);
+    fn test_row_count_of_query() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+setup:
+  - sql:
+      statements:
+        - "CREATE TABLE t (id INTEGER, active INTEGER)"
+        - "INSERT INTO t VALUES (1, 1), (2, 0), (3, 1)"
+tests:
+  - name: active
+    run: { cmd: "true" }
+    expect:
+      sql:
+        - row_count: { query: "SELECT * FROM t WHERE active = 1", equals: 2 }
+        - row_count: { query: "SELECT * FROM t WHERE active = 0; \n", equals: 1 }
+        - row_count: { table: t, equals: 3 }
+  - name: wrong_count
+    run: { cmd: "true" }
+    expect:
+      sql:
+        - row_count: { query: "SELECT * FROM t WHERE active = 1", equals: 3 }
+"#,
+        )
+        .unwrap();
         let result = run_spec_standalone(&spec);
 
-        assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("Signal: expected 15, got 9"));
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+        assert!(!result.tests[1].passed);
+        assert!(
+            result.tests[1].failures[0]
+                .contains("row_count for 'SELECT * FROM t WHERE active = 1': expected 3, got 2"),
+            "failures: {:?}",
+            result.tests[1].failures
+        );
     }
 
     #[test]
-    #[cfg(unix)]
-    fn test_signal_expected_but_normal_exit() {
-        // Test expects a signal but process exits normally
-        let mut test = make_test("signal_expected_normal_exit", "true", vec![]);
-        test.expect_mut().signal = Some(9);
-        let spec = make_spec(test);
+    fn test_sql_returns_rows_cell_mismatch() {
+        let spec = sql_rows_spec("[[1, alice], [2, carol]]");
         let result = run_spec_standalone(&spec);
 
         assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("but process exited with exit code 0"));
+        assert!(
+            result.tests[0].failures[0]
+                .contains("row 1, column 1: expected \"carol\", got \"bob\""),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
     }
 
     #[test]
-    #[cfg(unix)]
-    fn test_exit_expected_but_signal_received() {
-        // Test expects exit code 0 but gets killed by signal
-        let mut test = make_test("exit_expected_signal", "sh", vec!["-c", "kill -9 $$"]);
-        test.expect_mut().exit = Some(0);
-        let spec = make_spec(test);
+    fn test_sql_returns_rows_count_mismatch() {
+        let spec = sql_rows_spec("[[1, alice]]");
         let result = run_spec_standalone(&spec);
 
         assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("terminated by signal 9"));
+        assert!(
+            result.tests[0].failures[0].contains("expected 1 rows, got 2"),
+            "failures: {:?}",
+            result.tests[0].failures
+        );
     }
 
-    // ==================== Multiple Tests ====================
-
-    #[test]
-    fn test_multiple_tests_in_spec() {
-        let test1 = make_test("test_one", "echo", vec!["one"]);
-        let test2 = make_test("test_two", "echo", vec!["two"]);
-        let spec = TestSpec {
-            version: 1,
-            binary: None,
-            resolved_binary: None,
-            env: HashMap::new(),
-            sandbox: Sandbox::default(),
-            timeout: None,
-            capture_fs_diff: None,
-            databases: HashMap::new(),
-            setup: vec![],
-            tests: vec![test1, test2],
-            teardown: vec![],
-        };
-        let result = run_spec_standalone(&spec);
-
-        assert_eq!(result.tests.len(), 2);
-        assert!(result.tests[0].passed);
-        assert!(result.tests[1].passed);
-        assert_eq!(result.tests[0].name, "test_one");
-        assert_eq!(result.tests[1].name, "test_two");
+    fn sql_map_spec(query: &str, returns_map: &str) -> TestSpec {
+        serde_yaml::from_str(&format!(
+            r#"
+version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+setup:
+  - sql:
+      statements:
+        - "CREATE TABLE users (id INTEGER, name TEXT)"
+        - "INSERT INTO users VALUES (1, 'alice'), (2, 'bob')"
+tests:
+  - name: map
+    run: {{ cmd: "true" }}
+    expect:
+      sql:
+        - query: "{query}"
+          returns_map: {returns_map}
+"#
+        ))
+        .unwrap()
     }
 
     #[test]
-    fn test_shared_sandbox_between_tests() {
-        // First test creates a file, second test reads it
-        // Both must be serial since test2 depends on test1's output
-        let mut test1 = make_test("create_file", "sh", vec!["-c", "echo shared > shared.txt"]);
-        test1.expect_mut().exit = Some(0);
-        test1.serial = true;
-        let mut test2 = make_test("read_file", "cat", vec!["shared.txt"]);
-        test2.expect_mut().stdout = Some(OutputMatch::Exact("shared\n".to_string()));
-        test2.serial = true;
-        let spec = TestSpec {
-            version: 1,
-            binary: None,
-            resolved_binary: None,
-            env: HashMap::new(),
-            sandbox: Sandbox::default(),
-            timeout: None,
-            capture_fs_diff: None,
-            databases: HashMap::new(),
-            setup: vec![],
-            tests: vec![test1, test2],
-            teardown: vec![],
-        };
+    fn test_sql_returns_map() {
+        let spec = sql_map_spec(
+            "SELECT id, name FROM users WHERE id = 2",
+            "{ name: bob, id: 2 }",
+        );
         let result = run_spec_standalone(&spec);
 
         assert!(
             result.tests[0].passed,
-            "test1 failures: {:?}",
+            "failures: {:?}",
             result.tests[0].failures
         );
-        assert!(
-            result.tests[1].passed,
-            "test2 failures: {:?}",
-            result.tests[1].failures
-        );
     }
 
-    // ==================== Working Directory Tests ====================
+    #[test]
+    fn test_sql_returns_map_mismatch_and_missing_column() {
+        let spec = sql_map_spec(
+            "SELECT id, name FROM users WHERE id = 1",
+            "{ name: bob, email: x }",
+        );
+        let result = run_spec_standalone(&spec);
+
+        let failures = &result.tests[0].failures;
+        assert_eq!(failures.len(), 2, "failures: {failures:?}");
+        assert!(failures[0].contains("column 'email' not in result"));
+        assert!(failures[0].contains("Columns: [id, name]"));
+        assert!(failures[1].contains("column 'name' mismatch"));
+        assert!(failures[1].contains("Got: \"alice\""));
+    }
 
     #[test]
-    fn test_custom_cwd() {
-        let mut test = make_test("cwd_test", "pwd", vec![]);
-        test.expect_mut().stdout = Some(OutputMatch::Structured(OutputMatchStructured {
-            equals: None,
-            contains: Some("subdir".to_string()),
-            regex: None,
-        }));
-        test.run_mut().cwd = Some(PathBuf::from("subdir"));
-        let mut spec = make_spec(test);
-        spec.setup = vec![SetupStep {
-            write_file: None,
-            create_dir: Some(PathBuf::from("subdir")),
-            copy_file: None,
-            copy_dir: None,
-            run: None,
-            ..Default::default()
-        }];
+    fn test_sql_returns_map_rejects_multiple_rows() {
+        let spec = sql_map_spec("SELECT id, name FROM users", "{ id: 1 }");
         let result = run_spec_standalone(&spec);
 
+        assert!(!result.tests[0].passed);
         assert!(
-            result.tests[0].passed,
+            result.tests[0].failures[0]
+                .contains("returns_map expects exactly one row\n  Query: SELECT id, name FROM users\n  Got: 2 row(s)"),
             "failures: {:?}",
             result.tests[0].failures
         );
     }
 
-    // ==================== Command Not Found ====================
+    // ==================== Database Isolation Tests ====================
 
     #[test]
-    fn test_command_not_found() {
-        let test = make_test("not_found", "nonexistent_command_12345", vec![]);
-        let spec = make_spec(test);
+    fn test_per_test_isolation_runs_tests_serially_from_baseline() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+    isolation: per_test
+setup:
+  - sql:
+      statements: ["CREATE TABLE events (id INTEGER PRIMARY KEY)"]
+tests:
+  - name: first
+    run: { cmd: "true" }
+    expect:
+      sql:
+        - query: "SELECT COUNT(*) FROM events"
+          returns: "0"
+    teardown:
+      - sql:
+          statements: ["INSERT INTO events DEFAULT VALUES"]
+  - name: second
+    run: { cmd: "true" }
+    expect:
+      sql:
+        - query: "SELECT COUNT(*) FROM events"
+          returns: "0"
+    teardown:
+      - sql:
+          statements: ["INSERT INTO events DEFAULT VALUES"]
+  - name: third
+    run: { cmd: "true" }
+    expect:
+      sql:
+        - query: "SELECT COUNT(*) FROM events"
+          returns: "0"
+"#,
+        )
+        .unwrap();
         let result = run_spec_standalone(&spec);
 
-        assert!(!result.tests[0].passed);
-        assert!(result.tests[0].failures[0].contains("Failed to spawn"));
+        assert_eq!(result.tests.len(), 3);
+        for test in &result.tests {
+            assert!(test.passed, "{}: {:?}", test.name, test.failures);
+        }
     }
 
     // ==================== Suite Config Tests ====================
@@ -2523,14 +7407,32 @@ mod tests {
             binary: None,
             resolved_binary: None,
             timeout: Some(1),
+            kill_grace_ms: None,
+            max_output_bytes: None,
             env: HashMap::new(),
+            env_file: None,
+            vars: HashMap::new(),
             inherit_env: None,
             serial: false,
+            reuse_connections: false,
             capture_fs_diff: false,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            forbid_outside_writes: false,
             sandbox_dir: None,
+            sandbox_name: SandboxName::Timestamp,
+            preserve: None,
             databases: HashMap::new(),
             setup: vec![],
             teardown: vec![],
+            seed_sql: None,
+            fixture_mode: FixtureMode::Full,
+            record: false,
+            shuffle_seed: None,
+            no_capture: false,
+            keep_on_failure: false,
         };
 
         let test = make_test("slow_test", "sleep", vec!["10"]);
@@ -2552,14 +7454,32 @@ mod tests {
             binary: None,
             resolved_binary: None,
             timeout: None,
+            kill_grace_ms: None,
+            max_output_bytes: None,
             env: suite_env,
+            env_file: None,
+            vars: HashMap::new(),
             inherit_env: None,
             serial: false,
+            reuse_connections: false,
             capture_fs_diff: false,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            forbid_outside_writes: false,
             sandbox_dir: None,
+            sandbox_name: SandboxName::Timestamp,
+            preserve: None,
             databases: HashMap::new(),
             setup: vec![],
             teardown: vec![],
+            seed_sql: None,
+            fixture_mode: FixtureMode::Full,
+            record: false,
+            shuffle_seed: None,
+            no_capture: false,
+            keep_on_failure: false,
         };
 
         let mut test = make_test("env_test", "sh", vec!["-c", "echo $SUITE_VAR"]);
@@ -2585,14 +7505,32 @@ mod tests {
             binary: None,
             resolved_binary: None,
             timeout: None,
+            kill_grace_ms: None,
+            max_output_bytes: None,
             env: suite_env,
+            env_file: None,
+            vars: HashMap::new(),
             inherit_env: None,
             serial: false,
+            reuse_connections: false,
             capture_fs_diff: false,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            forbid_outside_writes: false,
             sandbox_dir: None,
+            sandbox_name: SandboxName::Timestamp,
+            preserve: None,
             databases: HashMap::new(),
             setup: vec![],
             teardown: vec![],
+            seed_sql: None,
+            fixture_mode: FixtureMode::Full,
+            record: false,
+            shuffle_seed: None,
+            no_capture: false,
+            keep_on_failure: false,
         };
 
         let mut test = make_test("env_override", "sh", vec!["-c", "echo $MY_VAR"]);
@@ -2618,14 +7556,32 @@ mod tests {
             binary: None,
             resolved_binary: None,
             timeout: Some(10), // Suite says 10 seconds
+            kill_grace_ms: None,
+            max_output_bytes: None,
             env: HashMap::new(),
+            env_file: None,
+            vars: HashMap::new(),
             inherit_env: None,
             serial: false,
+            reuse_connections: false,
             capture_fs_diff: false,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            forbid_outside_writes: false,
             sandbox_dir: None,
+            sandbox_name: SandboxName::Timestamp,
+            preserve: None,
             databases: HashMap::new(),
             setup: vec![],
             teardown: vec![],
+            seed_sql: None,
+            fixture_mode: FixtureMode::Full,
+            record: false,
+            shuffle_seed: None,
+            no_capture: false,
+            keep_on_failure: false,
         };
 
         let test = make_test("timeout_test", "sleep", vec!["5"]);
@@ -2664,12 +7620,17 @@ mod tests {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![test1, test2],
             teardown: vec![],
         };
@@ -2702,12 +7663,17 @@ mod tests {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![test1, test2],
             teardown: vec![],
         };
@@ -2727,6 +7693,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shared_resource_tests_do_not_overlap() {
+        // Tests sharing a resource each hold a lock directory for 0.2s; an
+        // overlapping run would fail `mkdir`. The first to hold it also waits for
+        // the unrelated test, which only starts once the lock is held, to check
+        // in. Both can only finish if the unrelated test runs alongside.
+        let wait_for = |condition: &str| {
+            format!(
+                "i=0; while [ ! {condition} ] && [ $i -lt 100 ]; do sleep 0.05; i=$((i+1)); done; [ {condition} ] || exit 1"
+            )
+        };
+        let hold = format!(
+            "mkdir port.lock || exit 1; {}; sleep 0.2; rmdir port.lock",
+            wait_for("-e other.started")
+        );
+        let mut test1 = make_test("port_1", "sh", vec!["-c", &hold]);
+        test1.resource = Some("port".to_string());
+        let mut test2 = make_test("port_2", "sh", vec!["-c", &hold]);
+        test2.resource = Some("port".to_string());
+        let check_in = format!("{}; touch other.started", wait_for("-d port.lock"));
+        let mut test3 = make_test("other", "sh", vec!["-c", &check_in]);
+        test3.resource = Some("db".to_string());
+
+        let mut spec = make_spec(test1);
+        spec.tests.extend([test2, test3]);
+
+        let start = std::time::Instant::now();
+        let result = run_spec_standalone(&spec);
+        let elapsed = start.elapsed();
+
+        assert!(result.tests.iter().all(|t| t.passed), "{:?}", result.tests);
+        assert!(
+            elapsed.as_secs_f64() >= 0.35,
+            "Shared-resource tests overlapped: {:.2}s (expected >= 0.35s)",
+            elapsed.as_secs_f64()
+        );
+    }
+
     #[test]
     fn test_serial_tests_run_before_parallel() {
         // Serial test creates a file, parallel test reads it
@@ -2744,18 +7748,24 @@ mod tests {
             equals: None,
             contains: Some("created".to_string()),
             regex: None,
+            ..Default::default()
         }));
 
         let spec = TestSpec {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![serial_test, parallel_test],
             teardown: vec![],
         };
@@ -2788,12 +7798,17 @@ mod tests {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![test1, test2, test3],
             teardown: vec![],
         };
@@ -2822,12 +7837,17 @@ mod tests {
             version: 1,
             binary: None,
             resolved_binary: None,
+            source_path: None,
+            replay: vec![],
             env: HashMap::new(),
+            vars: HashMap::new(),
             sandbox: Sandbox::default(),
             timeout: None,
             capture_fs_diff: None,
             databases: HashMap::new(),
             setup: vec![],
+            fixtures: HashMap::new(),
+            default_expect: None,
             tests: vec![s1, p1, s2, p2],
             teardown: vec![],
         };
@@ -2954,6 +7974,55 @@ mod tests {
         assert!(result.tests[0].fs_diff.is_some());
     }
 
+    #[test]
+    fn test_allowed_changes_passes() {
+        let mut test = make_test(
+            "allowed_write",
+            "sh",
+            vec!["-c", "mkdir -p out && touch out/a.log out/b.log"],
+        );
+        test.allowed_changes = vec![PathBuf::from("out/*.log")];
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(
+            result.tests[0].passed,
+            "failures: {:?}",
+            result.tests[0].failures
+        );
+        // Diff is only reported when capture_fs_diff is enabled
+        assert!(result.tests[0].fs_diff.is_none());
+    }
+
+    #[test]
+    fn test_allowed_changes_fails_on_unexpected() {
+        let mut test = make_test(
+            "unexpected_write",
+            "sh",
+            vec!["-c", "touch allowed.txt stray.txt"],
+        );
+        test.allowed_changes = vec![PathBuf::from("allowed.txt")];
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert!(!result.tests[0].passed);
+        assert_eq!(result.tests[0].failures.len(), 1);
+        assert!(result.tests[0].failures[0].contains("unexpected added path: stray.txt"));
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        let re = glob_to_regex("src/**/*.rs").unwrap();
+        assert!(re.is_match("src/main.rs"));
+        assert!(re.is_match("src/a/b/lib.rs"));
+        assert!(!re.is_match("tests/main.rs"));
+
+        let re = glob_to_regex("out/?.txt").unwrap();
+        assert!(re.is_match("out/a.txt"));
+        assert!(!re.is_match("out/ab.txt"));
+        assert!(!re.is_match("out/sub/a.txt"));
+    }
+
     // ==================== Tree Expectation Tests ====================
 
     #[test]
@@ -2971,14 +8040,17 @@ mod tests {
                 TreeEntry {
                     path: PathBuf::from("src/main.rs"),
                     contents: None,
+                    is_dir: None,
                 },
                 TreeEntry {
                     path: PathBuf::from("Cargo.toml"),
                     contents: None,
+                    is_dir: None,
                 },
             ],
             excludes: vec![],
             exact: false,
+            empty_dirs: vec![],
         });
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -3000,9 +8072,11 @@ mod tests {
             contains: vec![TreeEntry {
                 path: PathBuf::from("missing.txt"),
                 contents: None,
+                is_dir: None,
             }],
             excludes: vec![],
             exact: false,
+            empty_dirs: vec![],
         });
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -3026,6 +8100,7 @@ mod tests {
             contains: vec![],
             excludes: vec![PathBuf::from("target/")],
             exact: false,
+            empty_dirs: vec![],
         });
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -3047,6 +8122,7 @@ mod tests {
             contains: vec![],
             excludes: vec![PathBuf::from("forbidden")],
             exact: false,
+            empty_dirs: vec![],
         });
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -3077,10 +8153,13 @@ mod tests {
                     equals: None,
                     contains: Some("hello".to_string()),
                     regex: None,
+                    ..Default::default()
                 })),
+                is_dir: None,
             }],
             excludes: vec![],
             exact: false,
+            empty_dirs: vec![],
         });
         let spec = make_spec(test);
         let result = run_spec_standalone(&spec);
@@ -3092,6 +8171,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tree_is_dir() {
+        use crate::schema::{TreeEntry, TreeExpect};
+
+        let entry = |path: &str, is_dir| TreeEntry {
+            path: PathBuf::from(path),
+            contents: None,
+            is_dir: Some(is_dir),
+        };
+        let mut test = make_test(
+            "generate",
+            "sh",
+            vec!["-c", "mkdir -p out/assets && touch out/index.html"],
+        );
+        test.expect_mut().tree = Some(TreeExpect {
+            root: None,
+            contains: vec![
+                entry("out/assets", true),
+                entry("out/index.html", false),
+                entry("out/index.html", true),
+                entry("out/assets", false),
+            ],
+            excludes: vec![],
+            exact: false,
+            empty_dirs: vec![],
+        });
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert_eq!(
+            result.tests[0].failures,
+            vec![
+                "Tree: out/index.html: expected directory but found file",
+                "Tree: out/assets: expected file but found directory",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_file_timeout_overrides_suite() {
+        let spec: TestSpec = serde_yaml::from_str(
+            r#"
+version: 1
+timeout: 9
+env:
+  MODE: file
+databases:
+  main:
+    driver: postgres
+    url: "postgres://u:secret@h/db"
+tests:
+  - name: inherits
+    run: { cmd: "true" }
+  - name: own
+    timeout: 2
+    run: { cmd: "true" }
+"#,
+        )
+        .unwrap();
+        let suite = SuiteConfig {
+            timeout: Some(5),
+            env: HashMap::from([
+                ("MODE".to_string(), "suite".to_string()),
+                ("LEVEL".to_string(), "suite".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let explained = explain_spec(&spec, Some(&suite));
+
+        assert_eq!(explained["timeout"], 9);
+        assert_eq!(explained["env"]["MODE"], "file");
+        assert_eq!(
+            explained["databases"]["main"]["url"],
+            "postgres://u:****@h/db"
+        );
+        assert_eq!(explained["env"]["LEVEL"], "suite");
+        assert_eq!(
+            explained["tests"],
+            serde_json::json!([
+                { "name": "inherits", "timeout": 9 },
+                { "name": "own", "timeout": 2 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tree_empty_dirs() {
+        use crate::schema::TreeExpect;
+
+        let mut test = make_test(
+            "cleanup",
+            "sh",
+            vec!["-c", "mkdir -p cache logs && touch logs/a logs/b"],
+        );
+        test.expect_mut().tree = Some(TreeExpect {
+            root: None,
+            contains: vec![],
+            excludes: vec![],
+            exact: false,
+            empty_dirs: vec![
+                PathBuf::from("cache"),
+                PathBuf::from("logs"),
+                PathBuf::from("missing"),
+            ],
+        });
+        let spec = make_spec(test);
+        let result = run_spec_standalone(&spec);
+
+        assert_eq!(
+            result.tests[0].failures,
+            vec![
+                "Tree: directory not empty: logs (2 entries)",
+                "Tree: expected empty directory does not exist: missing",
+            ]
+        );
+    }
+
     #[test]
     fn test_sandbox_dir_local_creates_bintest_directory() {
         // Create a temp directory to use as working directory
@@ -3105,19 +8302,37 @@ mod tests {
             binary: None,
             resolved_binary: None,
             timeout: None,
+            kill_grace_ms: None,
+            max_output_bytes: None,
             env: HashMap::new(),
+            env_file: None,
+            vars: HashMap::new(),
             inherit_env: None,
             serial: false,
+            reuse_connections: false,
             capture_fs_diff: false,
+            strip_ansi: false,
+            normalize_newlines: false,
+            strict_stderr: false,
+            shell_program: None,
+            forbid_outside_writes: false,
             sandbox_dir: Some(SandboxDir::Local),
+            sandbox_name: SandboxName::Timestamp,
+            preserve: None,
             databases: HashMap::new(),
             setup: vec![],
             teardown: vec![],
+            seed_sql: None,
+            fixture_mode: FixtureMode::Full,
+            record: false,
+            shuffle_seed: None,
+            no_capture: false,
+            keep_on_failure: false,
         };
 
         // Run a simple test
         let mut test = make_test("sandbox_test", "sh", vec!["-c", "pwd"]);
-        test.expect_mut().exit = Some(0);
+        test.expect_mut().exit = Some(ExitMatch::Code(0));
         let spec = make_spec(test);
 
         let result = run_spec(&spec, Some(&suite_config));
@@ -3159,6 +8374,7 @@ mod tests {
             equals: None,
             contains: Some("/bin/echo".to_string()),
             regex: None,
+            ..Default::default()
         }));
 
         let mut spec = make_spec(test);
@@ -3,6 +3,7 @@
 //! This module defines the structure of test specification files.
 //! Specs are written in YAML and validated against these types.
 
+use crate::record::RecordedRun;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -12,6 +13,7 @@ use std::path::PathBuf;
 ///
 /// Provides defaults that apply to all spec files in the suite.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SuiteConfig {
     /// Schema version (must match crate major version).
     #[serde(default = "default_version")]
@@ -32,10 +34,28 @@ pub struct SuiteConfig {
     #[serde(default)]
     pub timeout: Option<u64>,
 
+    /// Grace period in milliseconds between SIGTERM and SIGKILL when a command
+    /// times out (default: 2000). Unix only; elsewhere the process is killed immediately.
+    #[serde(default)]
+    pub kill_grace_ms: Option<u64>,
+
+    /// Maximum bytes of stdout and of stderr kept per command (default: 10 MiB).
+    /// Output beyond this is discarded and replaced by a truncation marker.
+    #[serde(default)]
+    pub max_output_bytes: Option<u64>,
+
     /// Default environment variables for all tests.
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// Dotenv file (relative to this config) loaded beneath `env`.
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
+    /// Variables referenced as `${vars.NAME}` in every spec (file-level `vars` override).
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+
     /// Whether to inherit environment from host by default.
     #[serde(default)]
     pub inherit_env: Option<bool>,
@@ -45,17 +65,60 @@ pub struct SuiteConfig {
     #[serde(default)]
     pub serial: bool,
 
+    /// With `serial`, open each database once and share the connection across
+    /// spec files whose merged databases match the suite's (default: false).
+    /// Files then see each other's changes, even to in-memory databases.
+    /// Ignored when files run in parallel.
+    #[serde(default)]
+    pub reuse_connections: bool,
+
     /// Capture filesystem diffs for all tests (default: false).
     /// Shows what files were added, removed, or modified during test execution.
     #[serde(default)]
     pub capture_fs_diff: bool,
 
+    /// Strip ANSI escape codes from output before matching (default: false).
+    /// A matcher's own `strip_ansi` overrides this.
+    #[serde(default)]
+    pub strip_ansi: bool,
+
+    /// Convert `\r\n` and lone `\r` line endings to `\n` in output and expected
+    /// text before matching (default: false). Useful for suites that also run
+    /// on Windows. A matcher's own `normalize_newlines` overrides this.
+    #[serde(default)]
+    pub normalize_newlines: bool,
+
+    /// Fail any step that writes to stderr without an `expect.stderr` matcher
+    /// (default: false). A step opts out by setting any stderr matcher.
+    #[serde(default)]
+    pub strict_stderr: bool,
+
+    /// Shell for commands with `shell: true`, e.g. `bash` (default: `sh`, or
+    /// `cmd` on Windows). A command's own `shell_program` overrides this.
+    #[serde(default)]
+    pub shell_program: Option<String>,
+
+    /// Fail a test whose commands add, modify, or remove entries next to the
+    /// sandbox, e.g. by writing to `..` or an absolute path (default: false).
+    /// Temporary sandboxes get a private parent directory so the check is exact.
+    #[serde(default)]
+    pub forbid_outside_writes: bool,
+
     /// Directory for test sandboxes. If set, sandboxes are created here instead of system temp.
     /// Use "local" for `.bintest/<timestamp>/`, or specify a custom path.
     /// When not set, uses system temp directory (auto-deleted after tests).
     #[serde(default)]
     pub sandbox_dir: Option<SandboxDir>,
 
+    /// How sandboxes under `sandbox_dir` are named: `timestamp` (default) or
+    /// `spec`, the spec file's stem, so external tooling can predict the path.
+    #[serde(default)]
+    pub sandbox_name: SandboxName,
+
+    /// Default policy for keeping temporary sandboxes (file-level `sandbox.preserve` overrides).
+    #[serde(default)]
+    pub preserve: Option<PreservePolicy>,
+
     /// Database connections available for SQL assertions and setup/teardown.
     /// Keys are connection names (e.g., "default", "root").
     #[serde(default)]
@@ -68,12 +131,54 @@ pub struct SuiteConfig {
     /// Teardown steps run after the entire suite.
     #[serde(default)]
     pub teardown: Vec<TeardownStep>,
+
+    /// SQL run against every configured database before each file's setup
+    /// (set from `--seed-db`, not from YAML).
+    #[serde(skip)]
+    pub seed_sql: Option<String>,
+
+    /// Which fixture phases to run (set from `--setup-only` / `--teardown-only`,
+    /// not from YAML).
+    #[serde(skip)]
+    pub fixture_mode: FixtureMode,
+
+    /// Capture each step's command and output into the results (set from
+    /// `--record`, not from YAML).
+    #[serde(skip)]
+    pub record: bool,
+
+    /// Seed for shuffling the spawn order of each file's parallel tests (set from
+    /// `--shuffle` / `--seed`, not from YAML).
+    #[serde(skip)]
+    pub shuffle_seed: Option<u64>,
+
+    /// Connect commands to the terminal instead of capturing their output, and
+    /// run everything serially (set from `--no-capture`, not from YAML).
+    #[serde(skip)]
+    pub no_capture: bool,
+
+    /// Keep the sandbox of a failing file whatever the preserve policy (set
+    /// from `--keep-on-failure`, not from YAML).
+    #[serde(skip)]
+    pub keep_on_failure: bool,
 }
 
 fn default_version() -> u32 {
     1
 }
 
+/// Which phases of a run to execute, for debugging fixtures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FixtureMode {
+    /// Run setup, test commands, assertions, and teardown.
+    #[default]
+    Full,
+    /// Run only suite/file/test setup steps, leaving the sandbox in place.
+    SetupOnly,
+    /// Run only suite/file/test teardown steps.
+    TeardownOnly,
+}
+
 /// Directory configuration for test sandboxes.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(from = "String", into = "String")]
@@ -103,8 +208,21 @@ impl From<SandboxDir> for String {
     }
 }
 
+/// How sandbox directories under `sandbox_dir` are named.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxName {
+    /// The creation time, e.g. `20250102_150405_123`.
+    #[default]
+    Timestamp,
+    /// The spec file's stem, sanitized; `-2`, `-3`, ... is appended if the
+    /// directory already exists. The suite sandbox is named `suite`.
+    Spec,
+}
+
 /// Root document for a test specification file.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct TestSpec {
     /// Schema version (must match crate major version).
     pub version: u32,
@@ -120,11 +238,26 @@ pub struct TestSpec {
     #[serde(skip)]
     pub resolved_binary: Option<PathBuf>,
 
+    /// Path of the file this spec was loaded from (set by loader, not from YAML).
+    #[serde(skip)]
+    pub source_path: Option<PathBuf>,
+
+    /// Recorded runs whose outputs replace step expectations (set from
+    /// `--replay`, not from YAML).
+    #[serde(skip)]
+    pub replay: Vec<RecordedRun>,
+
     /// Environment variables for all tests in this file.
     /// Merges with suite-level env (file-level overrides suite-level).
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// Variables referenced as `${vars.NAME}` anywhere in this file's setup,
+    /// tests, and teardown. Overrides suite-level `vars`; a matrix dimension of
+    /// the same name overrides both.
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+
     /// Sandbox configuration for this spec file.
     #[serde(default)]
     pub sandbox: Sandbox,
@@ -146,6 +279,16 @@ pub struct TestSpec {
     #[serde(default)]
     pub setup: Vec<SetupStep>,
 
+    /// Named setup sequences that tests include with `use_fixtures`.
+    #[serde(default)]
+    pub fixtures: HashMap<String, Vec<SetupStep>>,
+
+    /// Expectations applied to every step of every test in this file. A step's
+    /// own `expect` fields take precedence; `files` and `sql` assertions from
+    /// both are checked.
+    #[serde(default)]
+    pub default_expect: Option<Expect>,
+
     /// The tests defined in this file.
     pub tests: Vec<Test>,
 
@@ -156,6 +299,7 @@ pub struct TestSpec {
 
 /// Sandbox configuration controlling the test execution environment.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Sandbox {
     /// Working directory mode: "temp" creates a temp dir, or a path for explicit location.
     #[serde(default)]
@@ -165,9 +309,41 @@ pub struct Sandbox {
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// Dotenv file (relative to the spec file) loaded beneath `env`.
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
     /// Whether to inherit environment from host (default: false).
     #[serde(default)]
     pub inherit_env: bool,
+
+    /// Host variables to withhold when inheriting the environment.
+    /// Variables explicitly set in `env` are still passed.
+    #[serde(default)]
+    pub inherit_env_except: Vec<String>,
+
+    /// Locale (e.g. "C") to set as `LANG` and `LC_ALL` for every command.
+    /// Explicitly configured `LANG`/`LC_ALL` values take precedence.
+    #[serde(default)]
+    pub normalize_locale: Option<String>,
+
+    /// When to keep a temporary sandbox after the file finishes
+    /// (default: suite setting, else `never`).
+    #[serde(default)]
+    pub preserve: Option<PreservePolicy>,
+}
+
+/// When to retain a temporary sandbox directory after a test file runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PreservePolicy {
+    /// Always delete the sandbox (default).
+    #[default]
+    Never,
+    /// Keep the sandbox if any test in the file failed.
+    OnFailure,
+    /// Always keep the sandbox.
+    Always,
 }
 
 /// Working directory configuration.
@@ -205,6 +381,7 @@ impl From<WorkDir> for Option<String> {
 /// Each step is a single-key map where the key determines the action.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
 pub struct SetupStep {
     /// Write a file with the given contents.
     #[serde(default)]
@@ -248,6 +425,7 @@ pub struct SetupStep {
 /// Each step is a single-key map where the key determines the action.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
 pub struct TeardownStep {
     /// Remove a directory.
     #[serde(default)]
@@ -272,6 +450,7 @@ pub struct TeardownStep {
 
 /// Write a file with specific contents.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct WriteFile {
     /// Path to the file (relative to sandbox).
     pub path: PathBuf,
@@ -282,6 +461,7 @@ pub struct WriteFile {
 
 /// Copy a file from one location to another.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct CopyFile {
     /// Source path.
     pub from: PathBuf,
@@ -292,6 +472,7 @@ pub struct CopyFile {
 
 /// Copy a directory recursively from one location to another.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct CopyDir {
     /// Source directory path.
     pub from: PathBuf,
@@ -302,6 +483,7 @@ pub struct CopyDir {
 
 /// SQL statements to execute.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SqlStatements {
     /// Database connection name (defaults to "default").
     #[serde(default = "default_database_name")]
@@ -317,6 +499,7 @@ pub struct SqlStatements {
 
 /// SQL file to execute.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SqlFile {
     /// Database connection name (defaults to "default").
     #[serde(default = "default_database_name")]
@@ -325,7 +508,17 @@ pub struct SqlFile {
     /// Path to the SQL file (relative to sandbox).
     pub path: PathBuf,
 
+    /// Split the file on `;` and execute each statement separately (default: false).
+    #[serde(default)]
+    pub split: bool,
+
+    /// Replace `${VAR}` references in the file contents with environment
+    /// variables before executing (default: false).
+    #[serde(default)]
+    pub interpolate: bool,
+
     /// Error handling: "fail" (default) or "continue".
+    /// With `split`, "continue" skips failing statements and runs the rest.
     #[serde(default)]
     pub on_error: SqlOnError,
 }
@@ -346,6 +539,7 @@ pub enum SqlOnError {
 /// Snapshots are stored in memory and can be restored later within the same file.
 /// Currently only supported for SQLite databases.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DbSnapshot {
     /// Database connection name (defaults to "default").
     #[serde(default = "default_database_name")]
@@ -359,6 +553,7 @@ pub struct DbSnapshot {
 ///
 /// The snapshot must have been created earlier in the same file using `db_snapshot`.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DbRestore {
     /// Database connection name (defaults to "default").
     #[serde(default = "default_database_name")]
@@ -370,6 +565,7 @@ pub struct DbRestore {
 
 /// A command to run (used in setup/teardown).
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct RunStep {
     /// The command/binary to execute.
     pub cmd: String,
@@ -377,14 +573,33 @@ pub struct RunStep {
     /// Command arguments.
     #[serde(default)]
     pub args: Vec<String>,
+
+    /// Additional environment variables for this command.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Working directory (relative to sandbox, defaults to sandbox root).
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+
+    /// Store the command's trimmed stdout as `${vars.<name>}` for the steps
+    /// that follow (the rest of the file for file-level setup, otherwise the
+    /// rest of the test).
+    #[serde(default)]
+    pub capture_as: Option<String>,
 }
 
 /// A single step within a multi-step test.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Step {
     /// Step name (used in failure reporting).
     pub name: String,
 
+    /// What the step does (shown for a failing step with `--verbose`).
+    #[serde(default)]
+    pub description: Option<String>,
+
     /// Step-level setup steps.
     #[serde(default)]
     pub setup: Vec<SetupStep>,
@@ -399,6 +614,68 @@ pub struct Step {
     /// Step-level teardown steps.
     #[serde(default)]
     pub teardown: Vec<TeardownStep>,
+
+    /// Invert the step's outcome: it passes only if its command or
+    /// assertions fail. Setup and teardown failures still fail the step.
+    #[serde(default)]
+    pub expect_failure: bool,
+
+    /// Values taken from this step's output, available to the steps after it
+    /// as `${vars.<name>}`.
+    #[serde(default)]
+    pub capture: HashMap<String, CaptureSource>,
+
+    /// Operating systems this step runs on. On any other, the step is skipped;
+    /// empty runs it everywhere.
+    #[serde(default)]
+    pub platform: Vec<Platform>,
+}
+
+impl Step {
+    /// Whether this step runs on the current operating system.
+    pub fn runs_on_current_platform(&self) -> bool {
+        self.platform.is_empty() || self.platform.iter().any(|p| p.is_current())
+    }
+}
+
+/// Where a step `capture` takes its value from.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum CaptureSource {
+    /// The whole stream, trimmed: `stdout` or `stderr`.
+    Stream(CaptureStream),
+    /// The first capture group of a regex matched against stdout (the whole
+    /// match if the regex has no groups).
+    Regex { regex: String },
+}
+
+/// An output stream of a step command.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureStream {
+    Stdout,
+    Stderr,
+}
+
+/// An operating system a step can be limited to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    Linux,
+    Macos,
+    Windows,
+}
+
+impl Platform {
+    /// Whether this is the operating system bintest is running on.
+    pub fn is_current(self) -> bool {
+        let os = match self {
+            Platform::Linux => "linux",
+            Platform::Macos => "macos",
+            Platform::Windows => "windows",
+        };
+        os == std::env::consts::OS
+    }
 }
 
 // ============================================================================
@@ -407,23 +684,35 @@ pub struct Step {
 
 /// A condition for conditional test execution.
 ///
-/// Conditions can check environment variables or command availability.
+/// Conditions can check environment variables, command availability, or
+/// file existence.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
 pub struct Condition {
     /// Environment variable that must be set (for require) or must be set (for skip_if).
     #[serde(default)]
     pub env: Option<String>,
 
+    /// With `env`: the value the variable must equal, instead of merely being
+    /// set and non-empty.
+    #[serde(default)]
+    pub equals: Option<String>,
+
     /// Command that must succeed (exit 0) for require, or must succeed for skip_if.
     #[serde(default)]
     pub cmd: Option<String>,
+
+    /// Path that must exist for require, or must exist for skip_if. Relative to
+    /// the current directory; `${VAR}` and a leading `~` are expanded.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
 }
 
 /// Helper enum for deserializing both test formats.
 /// Only used during deserialization, not stored, so the size difference is acceptable.
 #[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
+#[serde(deny_unknown_fields)]
 #[allow(clippy::large_enum_variant)]
 enum TestFormat {
     /// New format with explicit steps.
@@ -448,6 +737,24 @@ enum TestFormat {
         serial: bool,
         #[serde(default)]
         capture_fs_diff: Option<bool>,
+        #[serde(default)]
+        allowed_changes: Vec<PathBuf>,
+        #[serde(default)]
+        matrix: Option<HashMap<String, Vec<String>>>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        deterministic: bool,
+        #[serde(default)]
+        cwd: Option<PathBuf>,
+        #[serde(default)]
+        use_fixtures: Vec<String>,
+        #[serde(default)]
+        resource: Option<String>,
+        #[serde(default)]
+        skip: bool,
+        #[serde(default)]
+        only: bool,
     },
     /// Old format with single run/expect (implicit single step).
     SingleStep {
@@ -473,6 +780,24 @@ enum TestFormat {
         serial: bool,
         #[serde(default)]
         capture_fs_diff: Option<bool>,
+        #[serde(default)]
+        allowed_changes: Vec<PathBuf>,
+        #[serde(default)]
+        matrix: Option<HashMap<String, Vec<String>>>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        deterministic: bool,
+        #[serde(default)]
+        cwd: Option<PathBuf>,
+        #[serde(default)]
+        use_fixtures: Vec<String>,
+        #[serde(default)]
+        resource: Option<String>,
+        #[serde(default)]
+        skip: bool,
+        #[serde(default)]
+        only: bool,
     },
 }
 
@@ -529,6 +854,52 @@ pub struct Test {
     /// Capture filesystem diff for this test (overrides file/suite setting).
     #[serde(default)]
     pub capture_fs_diff: Option<bool>,
+
+    /// Glob patterns for sandbox paths this test is allowed to add, modify, or remove.
+    /// If non-empty, any filesystem change outside these patterns fails the test.
+    /// Supports `*`, `?`, and `**`; a pattern also allows everything beneath it.
+    /// Combine with `serial: true`, since parallel tests share the sandbox.
+    #[serde(default)]
+    pub allowed_changes: Vec<PathBuf>,
+
+    /// Matrix dimensions: the test runs once per combination of values, named
+    /// `name[key=value,...]` with keys sorted. Reference values in steps as `${matrix.key}`.
+    #[serde(default)]
+    pub matrix: Option<HashMap<String, Vec<String>>>,
+
+    /// Labels for selecting tests with `--tag` / `--exclude-tag`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Run the command a second time from the same starting sandbox and fail
+    /// if exit status, stdout, or stderr differ. Single-step tests only.
+    #[serde(default)]
+    pub deterministic: bool,
+
+    /// Default working directory for every step (relative to sandbox).
+    /// A step's own `run.cwd` takes precedence.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+
+    /// Names of file-level `fixtures` whose steps run, in order, before this
+    /// test's own `setup`.
+    #[serde(default)]
+    pub use_fixtures: Vec<String>,
+
+    /// Name of a shared resource (e.g. a port) this test holds while it runs.
+    /// Tests naming the same resource never run at the same time; they still run
+    /// in parallel with tests using other resources or none.
+    #[serde(default)]
+    pub resource: Option<String>,
+
+    /// Skip this test unconditionally; it is reported as skipped.
+    #[serde(default)]
+    pub skip: bool,
+
+    /// Focus on this test. When any test in the run sets `only`, tests without
+    /// it are filtered out across all spec files.
+    #[serde(default)]
+    pub only: bool,
 }
 
 impl<'de> Deserialize<'de> for Test {
@@ -536,7 +907,19 @@ impl<'de> Deserialize<'de> for Test {
     where
         D: serde::Deserializer<'de>,
     {
-        let format = TestFormat::deserialize(deserializer)?;
+        // Pick the format by the `steps` key rather than trying each one, so a
+        // misspelled field is reported by name
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        let format = if value.get("steps").is_some() {
+            "MultiStep"
+        } else {
+            "SingleStep"
+        };
+        let tagged = serde_yaml::Value::Tagged(Box::new(serde_yaml::value::TaggedValue {
+            tag: serde_yaml::value::Tag::new(format),
+            value,
+        }));
+        let format = TestFormat::deserialize(tagged).map_err(serde::de::Error::custom)?;
         Ok(match format {
             TestFormat::MultiStep {
                 name,
@@ -550,6 +933,15 @@ impl<'de> Deserialize<'de> for Test {
                 timeout,
                 serial,
                 capture_fs_diff,
+                allowed_changes,
+                matrix,
+                tags,
+                deterministic,
+                cwd,
+                use_fixtures,
+                resource,
+                skip,
+                only,
             } => Test {
                 name,
                 description,
@@ -562,6 +954,15 @@ impl<'de> Deserialize<'de> for Test {
                 timeout,
                 serial,
                 capture_fs_diff,
+                allowed_changes,
+                matrix,
+                tags,
+                deterministic,
+                cwd,
+                use_fixtures,
+                resource,
+                skip,
+                only,
             },
             TestFormat::SingleStep {
                 name,
@@ -576,6 +977,15 @@ impl<'de> Deserialize<'de> for Test {
                 timeout,
                 serial,
                 capture_fs_diff,
+                allowed_changes,
+                matrix,
+                tags,
+                deterministic,
+                cwd,
+                use_fixtures,
+                resource,
+                skip,
+                only,
             } => {
                 // Convert single run/expect to a single step named "run"
                 Test {
@@ -591,11 +1001,24 @@ impl<'de> Deserialize<'de> for Test {
                         run,
                         expect,
                         teardown: vec![],
+                        expect_failure: false,
+                        capture: HashMap::new(),
+                        description: None,
+                        platform: vec![],
                     }],
                     teardown,
                     timeout,
                     serial,
                     capture_fs_diff,
+                    allowed_changes,
+                    matrix,
+                    tags,
+                    deterministic,
+                    cwd,
+                    use_fixtures,
+                    resource,
+                    skip,
+                    only,
                 }
             }
         })
@@ -604,6 +1027,7 @@ impl<'de> Deserialize<'de> for Test {
 
 /// Command execution configuration.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Run {
     /// The command/binary to execute.
     pub cmd: String,
@@ -616,10 +1040,25 @@ pub struct Run {
     #[serde(default)]
     pub stdin: Option<String>,
 
+    /// File whose raw bytes are piped to standard input (relative to sandbox).
+    /// Mutually exclusive with `stdin`.
+    #[serde(default)]
+    pub stdin_file: Option<PathBuf>,
+
     /// Additional environment variables for this command.
     #[serde(default)]
     pub env: HashMap<String, String>,
 
+    /// Write stdout to this file (relative to sandbox) instead of buffering it.
+    /// `expect.stdout` is checked against the file's contents.
+    #[serde(default)]
+    pub stdout_file: Option<PathBuf>,
+
+    /// Write stderr to this file (relative to sandbox) instead of buffering it.
+    /// `expect.stderr` is checked against the file's contents.
+    #[serde(default)]
+    pub stderr_file: Option<PathBuf>,
+
     /// Working directory (relative to sandbox, defaults to sandbox root).
     #[serde(default)]
     pub cwd: Option<PathBuf>,
@@ -627,19 +1066,27 @@ pub struct Run {
     /// Run through shell (default: false).
     #[serde(default)]
     pub shell: bool,
+
+    /// Shell used when `shell` is true, e.g. `bash` (default: the suite
+    /// `shell_program`, else `sh`, or `cmd` on Windows).
+    #[serde(default)]
+    pub shell_program: Option<String>,
 }
 
 /// Expected outcomes from a test execution.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Expect {
-    /// Expected exit code (default: 0 if no signal expected).
+    /// Expected exit code, or `nonzero` for any failing code (default: 0 if no
+    /// signal expected).
     #[serde(default)]
-    pub exit: Option<i32>,
+    pub exit: Option<ExitMatch>,
 
-    /// Expected signal that terminated the process (e.g., 9 for SIGKILL, 15 for SIGTERM).
+    /// Expected signal that terminated the process, as a number (e.g., 9) or a
+    /// case-insensitive name (e.g., `SIGKILL`).
     /// If set, exit code is ignored and the process must have been killed by this signal.
     #[serde(default)]
-    pub signal: Option<i32>,
+    pub signal: Option<Signal>,
 
     /// Expected stdout content.
     #[serde(default)]
@@ -660,11 +1107,24 @@ pub struct Expect {
     /// Expected database state (SQL assertions).
     #[serde(default)]
     pub sql: Vec<SqlExpect>,
+
+    /// Maximum wall-clock runtime of the command in milliseconds.
+    /// Unlike `timeout`, the command is allowed to finish, but the step fails if it took longer.
+    #[serde(default)]
+    pub max_duration_ms: Option<u64>,
+
+    /// Whether all of stdin was written before the process closed it.
+    /// `false` asserts the process exited (or closed stdin) without reading all input.
+    /// Input small enough to fit in the pipe buffer always counts as consumed.
+    #[serde(default)]
+    pub stdin_consumed: Option<bool>,
 }
 
 /// Matching rules for stdout/stderr.
+/// Built once per spec, so the size difference between variants is acceptable.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum OutputMatch {
     /// Exact string match.
     Exact(String),
@@ -675,22 +1135,228 @@ pub enum OutputMatch {
 
 /// Structured output matching with multiple match types.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct OutputMatchStructured {
     /// Exact string match.
     #[serde(default)]
     pub equals: Option<String>,
 
+    /// Remove whitespace common to the start of every non-blank line of `equals`
+    /// before comparing, so expected blocks can be indented in YAML.
+    #[serde(default)]
+    pub dedent: bool,
+
+    /// Remove ANSI escape sequences (colors, cursor movement) from the output
+    /// before matching. Defaults to the suite's `strip_ansi`.
+    #[serde(default)]
+    pub strip_ansi: Option<bool>,
+
+    /// Convert `\r\n` and lone `\r` to `\n` in the output and in the expected
+    /// text (`equals`, `contains*`, `matches`) before matching. Defaults to the
+    /// suite's `normalize_newlines`.
+    #[serde(default)]
+    pub normalize_newlines: Option<bool>,
+
     /// Substring match.
     #[serde(default)]
     pub contains: Option<String>,
 
+    /// Substrings that must all be present.
+    #[serde(default)]
+    pub contains_all: Vec<String>,
+
+    /// Substrings of which at least one must be present.
+    #[serde(default)]
+    pub contains_any: Vec<String>,
+
     /// Regular expression match.
     #[serde(default)]
     pub regex: Option<String>,
+
+    /// Match the whole output against literal text in which `[..]` stands for
+    /// any text within a line and `[EXE]` for the platform's executable suffix
+    /// (`.exe` on Windows, empty elsewhere).
+    #[serde(default)]
+    pub matches: Option<String>,
+
+    /// Every non-blank output line must match one of these entries, in any order.
+    #[serde(default)]
+    pub only_lines: Vec<AllowedLine>,
+
+    /// Match `regex` and the `contains` checks ignoring case.
+    #[serde(default)]
+    pub case_insensitive: bool,
+
+    /// Let `^` and `$` in `regex` match at line boundaries instead of only
+    /// at the start and end of the output.
+    #[serde(default)]
+    pub multiline: bool,
+
+    /// Numeric comparison of the trimmed output parsed as a number.
+    #[serde(default)]
+    pub numeric: Option<NumericMatch>,
+
+    /// Parse the output as TOML and compare it to this value, ignoring key
+    /// order and formatting.
+    #[serde(default)]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub toml: Option<toml::Value>,
+
+    /// Parse the output as YAML and compare it to this value, ignoring key
+    /// order and formatting.
+    #[serde(default)]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub yaml: Option<serde_yaml::Value>,
+}
+
+/// An expected exit status: an exact code, or a keyword such as `nonzero`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ExitMatch {
+    /// The process must exit with this code.
+    Code(i32),
+    /// The process must exit in the way the keyword describes.
+    Keyword(ExitKeyword),
+}
+
+/// A keyword for `exit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitKeyword {
+    /// Any exit code other than 0.
+    Nonzero,
+}
+
+impl ExitMatch {
+    /// Whether a process that exited with `code` satisfies this expectation.
+    pub fn matches(self, code: i32) -> bool {
+        match self {
+            ExitMatch::Code(expected) => code == expected,
+            ExitMatch::Keyword(ExitKeyword::Nonzero) => code != 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitMatch::Code(code) => write!(f, "{code}"),
+            ExitMatch::Keyword(ExitKeyword::Nonzero) => write!(f, "nonzero"),
+        }
+    }
+}
+
+/// A signal number. Deserializes from a number or a case-insensitive name such
+/// as `SIGKILL` or `kill`; serializes as the number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "SignalValue", into = "i32")]
+#[schemars(with = "SignalValue")]
+pub struct Signal(pub i32);
+
+/// How a signal is written in a spec.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SignalValue {
+    Number(i32),
+    Name(String),
+}
+
+/// Signal names known on this platform, without the `SIG` prefix.
+#[cfg(unix)]
+const SIGNAL_NAMES: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("SYS", libc::SIGSYS),
+];
+#[cfg(not(unix))]
+const SIGNAL_NAMES: &[(&str, i32)] = &[];
+
+impl TryFrom<SignalValue> for Signal {
+    type Error = String;
+
+    fn try_from(value: SignalValue) -> Result<Self, Self::Error> {
+        match value {
+            SignalValue::Number(number) => Ok(Signal(number)),
+            SignalValue::Name(name) => {
+                let upper = name.to_ascii_uppercase();
+                let short = upper.strip_prefix("SIG").unwrap_or(&upper);
+                SIGNAL_NAMES
+                    .iter()
+                    .find(|(known, _)| *known == short)
+                    .map(|&(_, number)| Signal(number))
+                    .ok_or_else(|| format!("unknown signal name: {name}"))
+            }
+        }
+    }
+}
+
+impl From<Signal> for i32 {
+    fn from(signal: Signal) -> Self {
+        signal.0
+    }
+}
+
+/// Formats as `SIGKILL(9)`, or just the number for signals without a known name.
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match SIGNAL_NAMES.iter().find(|&&(_, number)| number == self.0) {
+            Some((name, number)) => write!(f, "SIG{name}({number})"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// An entry in `only_lines`: an exact line, or a regex the whole line must match.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum AllowedLine {
+    /// The line must equal this text.
+    Exact(String),
+    /// The whole line must match this regular expression.
+    Regex { regex: String },
+}
+
+/// Numeric comparison for output that is a single number.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NumericMatch {
+    /// Exact value expected.
+    #[serde(default)]
+    pub equals: Option<f64>,
+
+    /// Minimum value (exclusive).
+    #[serde(default)]
+    pub greater_than: Option<f64>,
+
+    /// Maximum value (exclusive).
+    #[serde(default)]
+    pub less_than: Option<f64>,
 }
 
 /// Expected state of a file after test execution.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct FileExpect {
     /// Path to the file (relative to sandbox).
     pub path: PathBuf,
@@ -702,10 +1368,28 @@ pub struct FileExpect {
     /// Expected file contents.
     #[serde(default)]
     pub contents: Option<OutputMatch>,
+
+    /// Expected SHA-256 digest of the file's bytes (hex, case-insensitive).
+    #[serde(default)]
+    pub sha256: Option<String>,
+
+    /// Whether the path should be a directory (`true`) or a regular file (`false`).
+    #[serde(default)]
+    pub is_dir: Option<bool>,
+
+    /// Treat the file as binary: `contents` is matched against its bytes as
+    /// lowercase hex (two digits per byte, no separators).
+    #[serde(default)]
+    pub binary: Option<bool>,
+
+    /// Expected exact bytes of the file, as hex (case and whitespace ignored).
+    #[serde(default)]
+    pub bytes_equal: Option<String>,
 }
 
 /// Expected directory tree structure after test execution.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct TreeExpect {
     /// Root directory to check (relative to sandbox, defaults to sandbox root).
     #[serde(default)]
@@ -722,10 +1406,15 @@ pub struct TreeExpect {
     /// If true, only paths in `contains` should exist (no extra files).
     #[serde(default)]
     pub exact: bool,
+
+    /// Directories that must exist and have no entries.
+    #[serde(default)]
+    pub empty_dirs: Vec<PathBuf>,
 }
 
 /// An entry in a tree expectation.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct TreeEntry {
     /// Path to the file or directory (relative to tree root).
     pub path: PathBuf,
@@ -733,6 +1422,10 @@ pub struct TreeEntry {
     /// Expected file contents (only for files, not directories).
     #[serde(default)]
     pub contents: Option<OutputMatch>,
+
+    /// Whether the path should be a directory (`true`) or a regular file (`false`).
+    #[serde(default)]
+    pub is_dir: Option<bool>,
 }
 
 // ============================================================================
@@ -744,6 +1437,7 @@ pub struct TreeEntry {
 /// Supports multiple assertion types through mutually exclusive fields.
 /// Only one type of assertion should be specified per `SqlExpect`.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SqlExpect {
     /// Database connection name (defaults to "default").
     #[serde(default = "default_database_name")]
@@ -769,6 +1463,11 @@ pub struct SqlExpect {
     #[serde(default)]
     pub returns_one_row: Option<bool>,
 
+    /// Expected values by column name for a query returning a single row.
+    /// Numbers and booleans are compared by their text form.
+    #[serde(default, deserialize_with = "deserialize_column_map")]
+    pub returns_map: Option<HashMap<String, String>>,
+
     /// Shorthand: check if a table exists.
     #[serde(default)]
     pub table_exists: Option<String>,
@@ -799,6 +1498,7 @@ pub enum SqlReturns {
 
 /// Structured SQL result matching.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SqlReturnsStructured {
     /// Exact string match.
     #[serde(default)]
@@ -811,13 +1511,69 @@ pub struct SqlReturnsStructured {
     /// Regular expression match.
     #[serde(default)]
     pub regex: Option<String>,
+
+    /// Expected rows, each a list of column values, compared cell by cell in order.
+    /// Numbers and booleans are compared by their text form.
+    #[serde(default, deserialize_with = "deserialize_rows")]
+    pub rows: Option<Vec<Vec<String>>>,
+}
+
+/// A scalar cell value in an expected SQL row.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SqlCell {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl From<SqlCell> for String {
+    fn from(cell: SqlCell) -> Self {
+        match cell {
+            SqlCell::String(s) => s,
+            SqlCell::Integer(n) => n.to_string(),
+            SqlCell::Float(f) => f.to_string(),
+            SqlCell::Bool(b) => b.to_string(),
+        }
+    }
 }
 
-/// Row count assertion for a table.
+/// Deserialize expected rows, accepting numbers and booleans as cell values.
+fn deserialize_rows<'de, D>(deserializer: D) -> Result<Option<Vec<Vec<String>>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let rows: Option<Vec<Vec<SqlCell>>> = Option::deserialize(deserializer)?;
+    Ok(rows.map(|rows| {
+        rows.into_iter()
+            .map(|row| row.into_iter().map(String::from).collect())
+            .collect()
+    }))
+}
+
+/// Deserialize expected column values, accepting numbers and booleans as values.
+fn deserialize_column_map<'de, D>(
+    deserializer: D,
+) -> Result<Option<HashMap<String, String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let map: Option<HashMap<String, SqlCell>> = Option::deserialize(deserializer)?;
+    Ok(map.map(|map| map.into_iter().map(|(k, v)| (k, v.into())).collect()))
+}
+
+/// Row count assertion for a table or query.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct RowCountExpect {
-    /// Table name to count rows from.
-    pub table: String,
+    /// Table name to count rows from. Shorthand for `query: SELECT * FROM <table>`.
+    #[serde(default)]
+    pub table: Option<String>,
+
+    /// Query whose result rows are counted. Mutually exclusive with `table`.
+    #[serde(default)]
+    pub query: Option<String>,
 
     /// Exact row count expected.
     #[serde(default)]
@@ -862,13 +1618,17 @@ pub enum DbIsolation {
     /// For PostgreSQL: Creates an automatic snapshot at file start and
     /// restores it between tests (requires setup to create initial state).
     PerFile,
+    /// Restore the post-setup state before every test, like `per_file`, and
+    /// run every test in the file serially so no test observes another's writes.
+    PerTest,
 }
 
 /// Database connection configuration.
 ///
 /// Defines how to connect to a database. URLs support environment variable
 /// interpolation using `${VAR}` syntax.
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DatabaseConfig {
     /// The database driver to use.
     pub driver: DbDriver,
@@ -888,6 +1648,20 @@ pub struct DatabaseConfig {
     /// Default is `none` (no automatic isolation).
     #[serde(default)]
     pub isolation: DbIsolation,
+
+    /// Maximum time a single query may run, in milliseconds (default: 5000).
+    #[serde(default)]
+    pub query_timeout_ms: Option<u64>,
+
+    /// How many more times to try connecting after the first attempt fails,
+    /// e.g. while a database container starts up (default: 0). Queries are
+    /// never retried.
+    #[serde(default)]
+    pub connect_retries: u32,
+
+    /// Delay between connection attempts, in milliseconds (default: 1000).
+    #[serde(default)]
+    pub connect_retry_delay_ms: Option<u64>,
 }
 
 /// Generate the JSON Schema for test specification files.
@@ -1003,6 +1777,33 @@ tests:
         }
     }
 
+    #[test]
+    fn parse_stdout_numeric_match() {
+        let yaml = r#"
+version: 1
+tests:
+  - name: numeric_output
+    run:
+      cmd: echo
+      args: ["42"]
+    expect:
+      stdout:
+        numeric:
+          greater_than: 40
+          less_than: 50.5
+"#;
+        let spec: TestSpec = serde_yaml::from_str(yaml).unwrap();
+        match &spec.tests[0].steps[0].expect.stdout {
+            Some(OutputMatch::Structured(s)) => {
+                let numeric = s.numeric.as_ref().unwrap();
+                assert_eq!(numeric.greater_than, Some(40.0));
+                assert_eq!(numeric.less_than, Some(50.5));
+                assert_eq!(numeric.equals, None);
+            }
+            _ => panic!("Expected structured match"),
+        }
+    }
+
     #[test]
     fn parse_multi_step_test() {
         let yaml = r#"
@@ -1237,4 +2038,20 @@ tests:
         assert_eq!(test3.skip_if.len(), 1);
         assert_eq!(test3.skip_if[0].env, Some("SKIP_SLOW_TESTS".to_string()));
     }
+
+    #[test]
+    fn schema_documents_binary_field() {
+        let schema = serde_json::to_value(generate_schema()).unwrap();
+        let properties = &schema["properties"];
+        let binary = &properties["binary"];
+        assert!(
+            binary["description"]
+                .as_str()
+                .is_some_and(|d| d.contains("${BINARY}")),
+            "{binary}"
+        );
+        // Set by the loader, never written in a spec
+        assert!(properties.get("resolved_binary").is_none());
+        assert!(properties.get("source_path").is_none());
+    }
 }
@@ -0,0 +1,1344 @@
+//! Integration tests for `bintest run` command-line options.
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn bintest_cmd() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_bintest"))
+}
+
+/// A spec with a single passing echo test.
+const ECHO_SPEC: &str = r#"version: 1
+tests:
+  - name: echo_test
+    run:
+      cmd: echo
+      args: ["hello"]
+    expect:
+      exit: 0
+"#;
+
+#[test]
+fn test_output_file_writes_report() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    let report_path = temp_dir.path().join("results.json");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .args(["--output", "json", "--output-file"])
+        .arg(&report_path)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The report goes to the file
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["passed"], 1);
+    assert_eq!(report["failed"], 0);
+
+    // The terminal only gets a short summary
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 passed, 0 failed"), "stdout: {stdout}");
+    assert!(!stdout.contains("\"results\""), "stdout: {stdout}");
+}
+
+#[test]
+fn test_failures_json_lists_only_failing_tests() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    let failures_path = temp_dir.path().join("failures.json");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+tests:
+  - name: passes
+    run:
+      cmd: "true"
+  - name: fails
+    run:
+      cmd: "false"
+    expect:
+      exit: 0
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--failures-json")
+        .arg(&failures_path)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    // Human output still goes to the terminal
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✗ fails"), "stdout: {stdout}");
+
+    let failures: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&failures_path).unwrap()).unwrap();
+    let failures = failures.as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["name"], "fails");
+    assert_eq!(failures[0]["file"], spec_path.display().to_string());
+    assert_eq!(failures[0]["failures"][0], "Exit code: expected 0, got 1");
+    assert!(failures[0]["failed_step"].is_null());
+}
+
+#[test]
+fn test_seed_db_runs_before_file_setup() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    let seed_path = temp_dir.path().join("seed.sql");
+    fs::write(
+        &seed_path,
+        "CREATE TABLE users (name TEXT);\nINSERT INTO users VALUES ('seeded');\n",
+    )
+    .unwrap();
+    fs::write(
+        &spec_path,
+        r#"version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+setup:
+  - sql:
+      statements:
+        - "INSERT INTO users VALUES ('from_setup')"
+tests:
+  - name: sees_seeded_rows
+    run:
+      cmd: "true"
+    expect:
+      sql:
+        - row_count:
+            table: users
+            equals: 2
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--seed-db")
+        .arg(&seed_path)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_seed_db_runs_once_per_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("shared.db");
+    let seed_path = temp_dir.path().join("seed.sql");
+    fs::write(
+        &seed_path,
+        "CREATE TABLE IF NOT EXISTS seeds (n INTEGER);\nINSERT INTO seeds VALUES (1);\n",
+    )
+    .unwrap();
+    let specs_dir = temp_dir.path().join("specs");
+    fs::create_dir(&specs_dir).unwrap();
+    for name in ["a.yaml", "b.yaml"] {
+        fs::write(
+            specs_dir.join(name),
+            format!(
+                r#"version: 1
+databases:
+  default:
+    driver: sqlite
+    url: "{}"
+tests:
+  - name: noop
+    run:
+      cmd: "true"
+"#,
+                db_path.display()
+            ),
+        )
+        .unwrap();
+    }
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&specs_dir)
+        .args(["--max-parallel-files", "1", "--seed-db"])
+        .arg(&seed_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Each file seeds its own connections, so a shared database is seeded twice
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    let seeded: i64 = conn
+        .query_row("SELECT COUNT(*) FROM seeds", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(seeded, 2);
+}
+
+#[test]
+fn test_plan_lists_schedule_without_running() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+timeout: 10
+databases:
+  default:
+    driver: sqlite
+    url: "sqlite::memory:"
+tests:
+  - name: writes_marker
+    serial: true
+    run:
+      cmd: touch
+      args: ["marker"]
+  - name: quick
+    timeout: 1
+    run:
+      cmd: echo
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", "spec.yaml", "--plan"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 file(s), run in parallel"), "{stdout}");
+    assert!(stdout.contains("timeout: 10s (file)"), "{stdout}");
+    assert!(
+        stdout.contains("database: default (sqlite, isolation: none)"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("serial (1):\n    writes_marker\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("parallel (1):\n    quick [timeout 1s]\n"),
+        "{stdout}"
+    );
+    assert!(!temp_dir.path().join("marker").exists());
+}
+
+#[test]
+fn test_plan_respects_only() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("focus.yaml"),
+        r#"version: 1
+tests:
+  - name: focused
+    only: true
+    run:
+      cmd: "true"
+  - name: unfocused
+    run:
+      cmd: "true"
+"#,
+    )
+    .unwrap();
+    fs::write(temp_dir.path().join("other.yaml"), ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", ".", "--plan"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("    focused\n"), "{stdout}");
+    assert!(!stdout.contains("unfocused"), "{stdout}");
+    assert!(!stdout.contains("echo_test"), "{stdout}");
+}
+
+#[test]
+fn test_smoke_runs_help_and_version_against_binary() {
+    let temp_dir = TempDir::new().unwrap();
+    // Use bintest itself as the binary under test
+    fs::write(
+        temp_dir.path().join("bintest.yaml"),
+        format!("version: 1\nbinary: {:?}\n", env!("CARGO_BIN_EXE_bintest")),
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .arg("--smoke")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("help_exits_zero"), "stdout: {stdout}");
+    assert!(
+        stdout.contains("version_prints_version"),
+        "stdout: {stdout}"
+    );
+    assert!(stdout.contains("2 passed, 0 failed"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_smoke_requires_binary() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .arg("--smoke")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--smoke requires a binary"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn test_results_dir_keeps_most_recent_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    let results_dir = temp_dir.path().join("history");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    for _ in 0..3 {
+        let output = bintest_cmd()
+            .arg("run")
+            .arg(&spec_path)
+            .arg("--results-dir")
+            .arg(&results_dir)
+            .args(["--keep-runs", "2"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        // Human output is unchanged on stdout
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed, 0 failed"));
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    let mut runs: Vec<_> = fs::read_dir(&results_dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    runs.sort();
+    assert_eq!(runs.len(), 2, "runs: {runs:?}");
+    for run in &runs {
+        let name = run.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("run-") && name.ends_with(".json"));
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run).unwrap()).unwrap();
+        assert_eq!(report["passed"], 1);
+        assert_eq!(report["results"][0]["tests"][0]["name"], "echo_test");
+    }
+}
+
+#[test]
+fn test_on_failure_command_runs_only_on_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let marker = temp_dir.path().join("hook.txt");
+    let hook = format!("echo \"$BINTEST_FAILED_COUNT\" > '{}'", marker.display());
+
+    let passing = temp_dir.path().join("passing.yaml");
+    fs::write(&passing, ECHO_SPEC).unwrap();
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&passing)
+        .args(["--on-failure-command", &hook])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!marker.exists(), "hook should not run when all tests pass");
+
+    let failing = temp_dir.path().join("failing.yaml");
+    fs::write(
+        &failing,
+        r#"version: 1
+tests:
+  - name: fails
+    run:
+      cmd: "false"
+    expect:
+      exit: 0
+"#,
+    )
+    .unwrap();
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&failing)
+        .args(["--on-failure-command", &hook])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(&marker).unwrap(), "1\n");
+}
+
+#[test]
+fn test_keep_on_failure_preserves_sandbox() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+sandbox:
+  workdir: temp
+tests:
+  - name: leaves_output
+    run:
+      cmd: sh
+      args: ["-c", "echo partial > output.txt; exit 1"]
+    expect:
+      exit: 0
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--keep-on-failure")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let sandbox = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Sandbox preserved at: "))
+        .unwrap_or_else(|| panic!("no preserved path in stderr: {stderr}"));
+    let sandbox = std::path::PathBuf::from(sandbox);
+    assert_eq!(
+        fs::read_to_string(sandbox.join("output.txt")).unwrap(),
+        "partial\n"
+    );
+    fs::remove_dir_all(&sandbox).unwrap();
+}
+
+#[test]
+fn test_record_then_replay_detects_changed_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    let value_path = temp_dir.path().join("value.txt");
+    let recording_path = temp_dir.path().join("recording.json");
+    fs::write(
+        &spec_path,
+        format!(
+            r#"version: 1
+tests:
+  - name: show_value
+    run:
+      cmd: cat
+      args: ["{}"]
+"#,
+            value_path.display()
+        ),
+    )
+    .unwrap();
+    fs::write(&value_path, "one\n").unwrap();
+
+    let run = |flag: &str, path: &std::path::Path| {
+        bintest_cmd()
+            .arg("run")
+            .arg(&spec_path)
+            .arg(flag)
+            .arg(path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run("--record", &recording_path);
+    assert!(output.status.success());
+    let recording: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&recording_path).unwrap()).unwrap();
+    let recorded = &recording["files"][0]["runs"][0];
+    assert_eq!(recorded["test"], "show_value");
+    assert_eq!(recorded["step"], "run");
+    assert_eq!(recorded["cmd"], "cat");
+    assert_eq!(recorded["exit_code"], 0);
+    assert_eq!(recorded["stdout"], "one\n");
+
+    // Same behavior replays cleanly
+    let output = run("--replay", &recording_path);
+    assert!(
+        output.status.success(),
+        "stdout: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    // Changed output fails against the recording
+    fs::write(&value_path, "two\n").unwrap();
+    let output = run("--replay", &recording_path);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("expected exact match"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_seed_is_reported_for_shuffled_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .args(["--seed", "42"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--seed 42"), "stderr: {stderr}");
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--shuffle")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Shuffling tests with seed"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn test_check_isolation_reports_leaked_host_variables() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("isolated.yaml"),
+        r#"version: 1
+sandbox:
+  env:
+    BINTEST_SET_BY_SPEC: "1"
+tests:
+  - name: marker
+    run:
+      cmd: touch
+      args: ["marker"]
+"#,
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("leaky.yaml"),
+        r#"version: 1
+sandbox:
+  inherit_env: true
+  inherit_env_except: [BINTEST_BLOCKED]
+  env:
+    BINTEST_SET_BY_SPEC: "1"
+tests:
+  - name: marker
+    run:
+      cmd: touch
+      args: ["marker"]
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", ".", "--check-isolation"])
+        .env("BINTEST_LEAKED", "1")
+        .env("BINTEST_BLOCKED", "1")
+        .env("BINTEST_SET_BY_SPEC", "1")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("isolated.yaml: no host environment variables leak"),
+        "{stdout}"
+    );
+    let leaky = stdout
+        .lines()
+        .find(|l| l.contains("leaky.yaml"))
+        .expect("leaky.yaml reported");
+    assert!(leaky.contains("BINTEST_LEAKED"), "{leaky}");
+    assert!(!leaky.contains("BINTEST_BLOCKED"), "{leaky}");
+    assert!(!leaky.contains("BINTEST_SET_BY_SPEC"), "{leaky}");
+    assert!(!temp_dir.path().join("marker").exists());
+}
+
+#[test]
+fn test_json_version_selects_output_shape() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    let run_json = |extra: &[&str]| -> serde_json::Value {
+        let output = bintest_cmd()
+            .arg("run")
+            .arg(&spec_path)
+            .args(["--output", "json"])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+
+    let latest = run_json(&[]);
+    assert_eq!(latest["schema_version"], 4);
+    let test = &latest["results"][0]["tests"][0];
+    assert_eq!(test["steps"][0]["name"], "run");
+    assert_eq!(test["steps"][0]["exit_code"], 0);
+    assert_eq!(test["exit_code"], 0);
+
+    let v3 = run_json(&["--json-version", "3"]);
+    assert_eq!(v3["schema_version"], 3);
+    let test = &v3["results"][0]["tests"][0];
+    assert!(test["id"].is_string(), "{test}");
+    assert!(test.get("exit_code").is_none(), "{test}");
+
+    let v2 = run_json(&["--json-version", "2"]);
+    assert_eq!(v2["schema_version"], 2);
+    let test = &v2["results"][0]["tests"][0];
+    assert_eq!(test["steps"][0]["name"], "run");
+    assert!(test.get("id").is_none(), "{test}");
+
+    let v1 = run_json(&["--json-version", "1"]);
+    assert_eq!(v1["schema_version"], 1);
+    let test = &v1["results"][0]["tests"][0];
+    assert_eq!(test["name"], "echo_test");
+    assert!(test.get("steps").is_none(), "{test}");
+}
+
+#[test]
+fn test_suite_setup_shares_sandbox_with_tests() {
+    let temp_dir = TempDir::new().unwrap();
+    let record = temp_dir.path().join("suite_dir.txt");
+    fs::write(
+        temp_dir.path().join("bintest.yaml"),
+        format!(
+            r#"version: 1
+setup:
+  - write_file:
+      path: shared.txt
+      contents: "prepared by suite setup"
+teardown:
+  - run:
+      cmd: sh
+      args: ["-c", "cat from_test.txt > {}"]
+"#,
+            record.display()
+        ),
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("spec.yaml"),
+        r#"version: 1
+tests:
+  - name: reads_suite_file
+    run:
+      cmd: sh
+      args: ["-c", "cat \"$BINTEST_SUITE_DIR/shared.txt\" && echo seen > \"$BINTEST_SUITE_DIR/from_test.txt\""]
+    expect:
+      stdout: "prepared by suite setup"
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", "."])
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "stdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Teardown ran in the same directory the test wrote to
+    assert_eq!(fs::read_to_string(&record).unwrap(), "seen\n");
+}
+
+#[test]
+fn test_no_capture_streams_output_and_skips_output_assertions() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+tests:
+  - name: streams
+    run:
+      cmd: sh
+      args: ["-c", "echo streamed-output; echo streamed-error >&2"]
+    expect:
+      stdout: "never matches"
+  - name: exit_still_checked
+    run:
+      cmd: "false"
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--no-capture")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("streamed-output"), "stdout: {stdout}");
+    assert!(stderr.contains("streamed-error"), "stderr: {stderr}");
+    assert!(stdout.contains("✓ streams"), "stdout: {stdout}");
+    assert!(stdout.contains("✗ exit_still_checked"), "stdout: {stdout}");
+    assert!(!output.status.success());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_no_capture_keeps_commands_in_the_terminal_session() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    // Field 6 of /proc/<pid>/stat is the session id; the shell's parent is bintest
+    fs::write(
+        &spec_path,
+        r#"version: 1
+tests:
+  - name: same_session
+    run:
+      cmd: sh
+      args: ["-c", "[ \"$(cut -d' ' -f6 /proc/$$/stat)\" = \"$(cut -d' ' -f6 /proc/$PPID/stat)\" ]"]
+  - name: still_times_out
+    timeout: 1
+    run:
+      cmd: sleep
+      args: ["10"]
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--no-capture")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✓ same_session"), "stdout: {stdout}");
+    assert!(stdout.contains("✗ still_times_out"), "stdout: {stdout}");
+    assert!(stdout.contains("timed out"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_repeated_runs_ignore_files_written_by_earlier_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("echo.yaml"), ECHO_SPEC).unwrap();
+
+    // The first run leaves .bintest/last-run.json, results and a recording behind
+    for _ in 0..2 {
+        let output = bintest_cmd()
+            .current_dir(temp_dir.path())
+            .args([
+                "run",
+                ".",
+                "--results-dir",
+                "runs",
+                "--record",
+                "recording.json",
+            ])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "stdout: {stdout}\nstderr: {stderr}"
+        );
+        assert!(stdout.contains("1 passed, 0 failed"), "stdout: {stdout}");
+    }
+    assert!(temp_dir.path().join(".bintest/last-run.json").exists());
+}
+
+#[test]
+fn test_only_failures_reruns_failed_tests_from_last_run() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("spec.yaml"),
+        r#"version: 1
+tests:
+  - name: passes
+    run:
+      cmd: "true"
+  - name: fails_once
+    run:
+      cmd: "false"
+"#,
+    )
+    .unwrap();
+    fs::write(temp_dir.path().join("other.yaml"), ECHO_SPEC).unwrap();
+    let run = |extra: &[&str]| {
+        bintest_cmd()
+            .current_dir(temp_dir.path())
+            .args(["run", "."])
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    // Without a previous run there is nothing to re-run
+    let output = run(&["--only-failures"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("once without --only-failures"),
+        "stderr: {stderr}"
+    );
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+
+    // Fix the failing test; only it runs again
+    fs::write(
+        temp_dir.path().join("spec.yaml"),
+        r#"version: 1
+tests:
+  - name: passes
+    run:
+      cmd: "true"
+  - name: fails_once
+    run:
+      cmd: "true"
+"#,
+    )
+    .unwrap();
+    let output = run(&["--only-failures"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("✓ fails_once"), "stdout: {stdout}");
+    assert!(!stdout.contains("passes"), "stdout: {stdout}");
+    assert!(!stdout.contains("other.yaml"), "stdout: {stdout}");
+    assert!(stdout.contains("1 passed, 0 failed"), "stdout: {stdout}");
+
+    // That run passed, so nothing is left to re-run
+    let output = run(&["--only-failures"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No failures from the previous run"),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn test_json_test_ids_are_relative_to_test_root() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("nested")).unwrap();
+    fs::write(temp_dir.path().join("nested/spec.yaml"), ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .args(["--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let file = &report["results"][0];
+    assert_eq!(file["version"], 1);
+    let test = &file["tests"][0];
+    assert_eq!(test["id"], "nested/spec.yaml::echo_test");
+    assert_eq!(test["file"], file["file"]);
+    assert_eq!(test["skipped"], false);
+    assert!(test["skip_reason"].is_null(), "{test}");
+    assert!(test["failed_step"].is_null(), "{test}");
+}
+
+#[test]
+fn test_slowest_lists_tests_by_descending_duration() {
+    let temp_dir = TempDir::new().unwrap();
+    let sleep_spec = |name: &str, secs: &str| {
+        format!(
+            r#"version: 1
+tests:
+  - name: {name}
+    run:
+      cmd: sleep
+      args: ["{secs}"]
+  - name: skipped_{name}
+    skip_if:
+      - cmd: "true"
+    run:
+      cmd: "true"
+"#
+        )
+    };
+    fs::write(temp_dir.path().join("a.yaml"), sleep_spec("quick", "0.05")).unwrap();
+    fs::write(temp_dir.path().join("b.yaml"), sleep_spec("slow", "0.3")).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .args(["--slowest", "5"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, listing) = stdout
+        .split_once("Slowest tests:\n")
+        .unwrap_or_else(|| panic!("no slowest list in: {stdout}"));
+    let names: Vec<&str> = listing
+        .lines()
+        .filter_map(|line| line.rsplit_once("::").map(|(_, name)| name))
+        .collect();
+    assert_eq!(names, vec!["slow", "quick"], "stdout: {stdout}");
+    assert!(listing.contains("b.yaml::slow"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_slowest_rejects_zero() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .args(["--slowest", "0"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--slowest"));
+}
+
+#[test]
+fn test_color_never_and_always() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("spec.yaml");
+    fs::write(&spec_path, ECHO_SPEC).unwrap();
+
+    let run = |when: &str| {
+        let output = bintest_cmd()
+            .arg("run")
+            .arg(&spec_path)
+            .args(["--color", when])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let plain = run("never");
+    assert!(plain.contains("✓ echo_test ("), "stdout: {plain}");
+    assert!(!plain.contains('\x1b'), "stdout: {plain:?}");
+
+    let colored = run("always");
+    assert!(
+        colored.contains("\x1b[32m✓\x1b[0m echo_test"),
+        "stdout: {colored:?}"
+    );
+}
+
+#[test]
+fn test_progress_line_is_not_written_when_piped() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("a.yaml"), ECHO_SPEC).unwrap();
+    fs::write(temp_dir.path().join("b.yaml"), ECHO_SPEC).unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("specs complete"), "stdout: {stdout}");
+    assert!(!stdout.contains('\r'), "stdout: {stdout:?}");
+    assert!(stdout.starts_with('\n'), "stdout: {stdout:?}");
+    assert!(
+        stdout.ends_with("\n2 passed, 0 failed\n"),
+        "stdout: {stdout:?}"
+    );
+}
+
+#[test]
+fn test_only_in_one_file_filters_every_file() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("focused.yaml"),
+        r#"version: 1
+tests:
+  - name: focused
+    only: true
+    run:
+      cmd: "true"
+  - name: unfocused
+    run:
+      cmd: "false"
+"#,
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("other.yaml"),
+        r#"version: 1
+tests:
+  - name: elsewhere
+    run:
+      cmd: "false"
+"#,
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", "."])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("✓ focused"), "stdout: {stdout}");
+    assert!(!stdout.contains("unfocused"), "stdout: {stdout}");
+    assert!(!stdout.contains("elsewhere"), "stdout: {stdout}");
+    assert!(stdout.contains("1 passed, 0 failed"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_verbose_shows_descriptions() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("described.yaml");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+tests:
+  - name: documented
+    description: Checks the greeting
+    run:
+      cmd: echo
+      args: ["hi"]
+  - name: workflow
+    description: Builds then verifies
+    steps:
+      - name: build
+        run:
+          cmd: "true"
+      - name: verify
+        description: The build leaves no output behind
+        run:
+          cmd: "false"
+"#,
+    )
+    .unwrap();
+
+    let verbose = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .arg("--verbose")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&verbose.stdout);
+    assert!(
+        stdout.contains("✓ documented") && stdout.contains("    Checks the greeting\n"),
+        "stdout: {stdout}"
+    );
+    assert!(
+        stdout.contains("    Builds then verifies\n")
+            && stdout.contains("    step 'verify': The build leaves no output behind\n"),
+        "stdout: {stdout}"
+    );
+
+    let quiet = bintest_cmd().arg("run").arg(&spec_path).output().unwrap();
+    let stdout = String::from_utf8_lossy(&quiet.stdout);
+    assert!(!stdout.contains("Checks the greeting"), "stdout: {stdout}");
+    assert!(!stdout.contains("no output behind"), "stdout: {stdout}");
+
+    let json = bintest_cmd()
+        .arg("run")
+        .arg(&spec_path)
+        .args(["--output", "json"])
+        .output()
+        .unwrap();
+    let results: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    let tests = &results["results"][0]["tests"];
+    assert_eq!(tests[0]["description"], "Checks the greeting");
+    assert_eq!(
+        tests[1]["failed_step"]["description"],
+        "The build leaves no output behind"
+    );
+}
+
+#[test]
+fn test_clean_removes_old_sandboxes() {
+    let temp_dir = TempDir::new().unwrap();
+    let sandboxes = temp_dir.path().join(".bintest");
+    let stamp = |age: chrono::Duration| {
+        (chrono::Local::now() - age)
+            .format("%Y%m%d_%H%M%S_%3f")
+            .to_string()
+    };
+    let old = stamp(chrono::Duration::days(3));
+    let recent = stamp(chrono::Duration::hours(1));
+    for name in [old.as_str(), recent.as_str(), "login"] {
+        fs::create_dir_all(sandboxes.join(name)).unwrap();
+    }
+    let clean = |extra: &[&str]| {
+        bintest_cmd()
+            .current_dir(temp_dir.path())
+            .arg("clean")
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let output = clean(&["--older-than", "2d"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(
+        stdout.contains("Removed 1 sandbox directory"),
+        "stdout: {stdout}"
+    );
+    assert!(!sandboxes.join(&old).exists());
+    assert!(sandboxes.join(&recent).exists());
+    assert!(sandboxes.join("login").exists());
+
+    let output = clean(&["--older-than", "2 days"]);
+    assert!(!output.status.success());
+
+    let output = clean(&["--older-than", "999999999999999999d"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("age is too large"), "stderr: {stderr}");
+
+    let output = clean(&[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Removed 2 sandbox directories"),
+        "stdout: {stdout}"
+    );
+    assert!(!sandboxes.exists());
+}
+
+#[test]
+fn test_clean_keeps_unrelated_entries_in_configured_sandbox_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let sandboxes = temp_dir.path().join("work");
+    fs::write(
+        temp_dir.path().join("bintest.yaml"),
+        "version: 1\nsandbox_dir: work\nsandbox_name: spec\n",
+    )
+    .unwrap();
+    fs::write(temp_dir.path().join("login.yaml"), ECHO_SPEC).unwrap();
+    let stamp = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f").to_string();
+    for name in [
+        stamp.as_str(),
+        "login",
+        "login-2",
+        "suite",
+        "notes",
+        "login-old",
+    ] {
+        fs::create_dir_all(sandboxes.join(name)).unwrap();
+    }
+    fs::write(sandboxes.join("README.txt"), "not a sandbox").unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .arg("clean")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(
+        stdout.contains("Removed 4 sandbox directories"),
+        "stdout: {stdout}"
+    );
+    let mut left: Vec<_> = fs::read_dir(&sandboxes)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["README.txt", "login-old", "notes"]);
+}
+
+#[test]
+fn test_json_lines_output_is_one_object_per_line() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("echo.yaml"), ECHO_SPEC).unwrap();
+    fs::write(
+        temp_dir.path().join("mixed.yaml"),
+        r#"version: 1
+tests:
+  - name: passes
+    run:
+      cmd: "true"
+  - name: fails
+    run:
+      cmd: "false"
+"#,
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("broken.yaml"),
+        "version: 1\ntests: oops\n",
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", ".", "--output", "json-lines"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+        .collect();
+
+    let (summary, tests) = lines.split_last().unwrap();
+    assert_eq!(summary["type"], "summary");
+    assert_eq!(summary["passed"], 2);
+    assert_eq!(summary["failed"], 2);
+    assert!(summary["duration"].is_f64());
+
+    assert_eq!(tests.len(), 4);
+    assert!(tests.iter().all(|t| t["type"] == "test"));
+    let load = tests.iter().find(|t| t["name"] == "<load>").unwrap();
+    assert_eq!(load["file"], "./broken.yaml");
+    // Every test line has the same keys, with null where they don't apply
+    let keys = |t: &serde_json::Value| {
+        let mut keys: Vec<String> = t.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    };
+    assert!(tests.iter().all(|t| keys(t) == keys(load)), "{tests:?}");
+    assert!(load["exit_code"].is_null());
+    let fails = tests.iter().find(|t| t["name"] == "fails").unwrap();
+    assert_eq!(fails["file"], "./mixed.yaml");
+    assert_eq!(fails["passed"], false);
+    assert!(!fails["failures"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_observed_exit_code_is_reported() {
+    let temp_dir = TempDir::new().unwrap();
+    let spec_path = temp_dir.path().join("exits.yaml");
+    fs::write(
+        &spec_path,
+        r#"version: 1
+tests:
+  - name: exits_three
+    run:
+      cmd: sh
+      args: ["-c", "exit 3"]
+  - name: second_step_fails
+    steps:
+      - name: ok
+        run:
+          cmd: "true"
+      - name: broken
+        run:
+          cmd: sh
+          args: ["-c", "exit 5"]
+      - name: never
+        run:
+          cmd: "true"
+"#,
+    )
+    .unwrap();
+    let run = |format: &str| {
+        bintest_cmd()
+            .arg("run")
+            .arg(&spec_path)
+            .args(["--output", format])
+            .output()
+            .unwrap()
+    };
+
+    let output = run("json");
+    assert!(!output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let tests = &json["results"][0]["tests"];
+    assert_eq!(tests[0]["exit_code"], 3);
+    assert_eq!(tests[0]["signal"], serde_json::Value::Null);
+    assert_eq!(tests[1]["exit_code"], 5);
+
+    let output = run("junit");
+    let xml = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        xml.contains(r#"<property name="exit_code" value="3"/>"#),
+        "xml: {xml}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relative_binary_runs_through_binary_variable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let specs = temp_dir.path().join("specs");
+    fs::create_dir(&specs).unwrap();
+    let binary = specs.join("mybin");
+    fs::write(&binary, "#!/bin/sh\necho \"mybin got $1\"\n").unwrap();
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(
+        specs.join("mybin.yaml"),
+        r#"version: 1
+binary: ./mybin
+tests:
+  - name: runs_binary
+    run:
+      cmd: "${BINARY}"
+      args: ["hello"]
+    expect:
+      stdout: "mybin got hello\n"
+"#,
+    )
+    .unwrap();
+
+    // Run from elsewhere: the path is relative to the spec, not the working directory
+    let output = bintest_cmd()
+        .current_dir(temp_dir.path())
+        .args(["run", "specs"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("✓ runs_binary"), "stdout: {stdout}");
+}
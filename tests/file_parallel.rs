@@ -270,3 +270,114 @@ tests:
         String::from_utf8_lossy(&output.stderr)
     );
 }
+
+#[test]
+fn test_max_parallel_files_bounds_concurrency() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Four files that each sleep for 0.3 seconds
+    for i in 1..=4 {
+        fs::write(
+            temp_dir.path().join(format!("spec{i}.yaml")),
+            sleep_spec(&format!("sleep{i}"), 0.3),
+        )
+        .unwrap();
+    }
+
+    let start = Instant::now();
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .args(["--max-parallel-files", "2"])
+        .output()
+        .unwrap();
+    let elapsed = start.elapsed();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    assert!(stdout.contains("4 passed, 0 failed"), "stdout: {stdout}");
+
+    // Two at a time means two rounds of ~0.3s; unbounded would take ~0.3s
+    assert!(
+        elapsed.as_secs_f64() >= 0.55,
+        "Files took {:.2}s to run (expected >= 0.55s with 2 files at a time)",
+        elapsed.as_secs_f64()
+    );
+}
+
+/// Run two serial files against a SQLite file database: the first creates a
+/// per-connection TEMP table, the second counts it. Returns the second file's
+/// stdout.
+fn run_connection_probe(reuse_connections: bool, expected_count: &str) -> String {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("shared.sqlite");
+
+    fs::write(
+        temp_dir.path().join("bintest.yaml"),
+        format!(
+            r#"version: 1
+serial: true
+reuse_connections: {reuse_connections}
+databases:
+  default:
+    driver: sqlite
+    url: "{}"
+"#,
+            db_path.display()
+        ),
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("a_creates.yaml"),
+        r#"version: 1
+setup:
+  - sql:
+      statements: ["CREATE TEMP TABLE conn_marker (x INTEGER)"]
+tests:
+  - name: creates_marker
+    run: { cmd: "true" }
+"#,
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join("b_checks.yaml"),
+        format!(
+            r#"version: 1
+tests:
+  - name: sees_marker
+    run: {{ cmd: "true" }}
+    expect:
+      sql:
+        - query: "SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'conn_marker'"
+          returns: "{expected_count}"
+"#
+        ),
+    )
+    .unwrap();
+
+    let output = bintest_cmd()
+        .arg("run")
+        .arg(temp_dir.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "stdout: {stdout}\nstderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+#[test]
+fn test_reuse_connections_shares_connection_across_serial_files() {
+    // TEMP tables are visible only on the connection that created them
+    let stdout = run_connection_probe(true, "1");
+    assert!(stdout.contains("2 passed, 0 failed"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_connections_are_per_file_by_default() {
+    let stdout = run_connection_probe(false, "0");
+    assert!(stdout.contains("2 passed, 0 failed"), "stdout: {stdout}");
+}